@@ -0,0 +1,68 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Result};
+
+use crate::attr::Args;
+
+pub fn derive(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let span = name.span();
+    let args = Args::parse(
+        &input.attrs,
+        "aggregate",
+        &["type_name", "event", "command"],
+    )?;
+    let type_name = args.string("type_name", span)?;
+    let event = args.ty("event", span)?;
+    let command = args.ty("command", span)?;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let default = match &input.data {
+        Data::Enum(data) => {
+            let mut defaults = Vec::new();
+            for variant in &data.variants {
+                if Args::parse(&variant.attrs, "aggregate", &["default"])?.flag("default") {
+                    match variant.fields {
+                        Fields::Unit => defaults.push(&variant.ident),
+                        _ => {
+                            return Err(Error::new_spanned(
+                                variant,
+                                "#[aggregate(default)] requires a unit variant",
+                            ))
+                        }
+                    }
+                }
+            }
+            match defaults.as_slice() {
+                [] => None,
+                [variant] => Some(quote! {
+                    impl #impl_generics ::std::default::Default for #name #ty_generics #where_clause {
+                        fn default() -> Self {
+                            #name::#variant
+                        }
+                    }
+                }),
+                _ => {
+                    return Err(Error::new(
+                        span,
+                        "#[aggregate(default)] is specified more than once",
+                    ))
+                }
+            }
+        }
+        _ => None,
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::cqrs_es::Aggregate for #name #ty_generics #where_clause {
+            type Event = #event;
+            type Command = #command;
+
+            fn type_name() -> &'static str {
+                #type_name
+            }
+        }
+
+        #default
+    })
+}
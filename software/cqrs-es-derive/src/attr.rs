@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use proc_macro2::Span;
+use syn::{Attribute, Error, Lit, LitInt, LitStr, Meta, NestedMeta, Path, Result, Type};
+
+/// `#[name(key = "value", key = 1, flag)]` 形式のattributeの内容
+#[derive(Default)]
+pub struct Args {
+    values: HashMap<String, Lit>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    /// `attrs` のうち `name` のものをすべて読み込む
+    ///
+    /// 綴りを誤ったキーが黙って無視されないよう、 `keys` 以外のキーはエラーとする
+    pub fn parse(attrs: &[Attribute], name: &str, keys: &[&str]) -> Result<Args> {
+        let mut args = Args::default();
+        for attr in attrs.iter().filter(|a| a.path.is_ident(name)) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => {
+                    return Err(Error::new_spanned(
+                        meta,
+                        format!("expected #[{}(...)]", name),
+                    ))
+                }
+            };
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(nv)) => {
+                        let key = key(&nv.path, keys)?;
+                        match nv.lit {
+                            lit @ Lit::Str(_) | lit @ Lit::Int(_) => {
+                                args.values.insert(key, lit);
+                            }
+                            lit => {
+                                return Err(Error::new_spanned(
+                                    lit,
+                                    "expected string or integer literal",
+                                ))
+                            }
+                        }
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) => args.flags.push(key(&path, keys)?),
+                    nested => return Err(Error::new_spanned(nested, "unexpected attribute")),
+                }
+            }
+        }
+        Ok(args)
+    }
+
+    pub fn string(&self, key: &str, span: Span) -> Result<LitStr> {
+        self.optional_string(key)?
+            .ok_or_else(|| Error::new(span, format!("missing `{} = \"...\"`", key)))
+    }
+
+    pub fn optional_string(&self, key: &str) -> Result<Option<LitStr>> {
+        match self.values.get(key) {
+            Some(Lit::Str(value)) => Ok(Some(value.clone())),
+            Some(lit) => Err(Error::new_spanned(lit, "expected string literal")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn optional_int(&self, key: &str) -> Result<Option<LitInt>> {
+        match self.values.get(key) {
+            Some(Lit::Int(value)) => Ok(Some(value.clone())),
+            Some(lit) => Err(Error::new_spanned(lit, "expected integer literal")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn ty(&self, key: &str, span: Span) -> Result<Type> {
+        self.string(key, span)?.parse()
+    }
+
+    pub fn optional_path(&self, key: &str) -> Result<Option<Path>> {
+        self.optional_string(key)?.map(|s| s.parse()).transpose()
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        self.flags.iter().any(|f| f == key)
+    }
+}
+
+fn key(path: &Path, keys: &[&str]) -> Result<String> {
+    match path.get_ident().map(ToString::to_string) {
+        Some(key) if keys.contains(&key.as_str()) => Ok(key),
+        _ => Err(Error::new_spanned(path, "unknown attribute key")),
+    }
+}
+
+/// `CanisterAdded` を `canister_added` にする
+pub fn snake_case(s: &str) -> String {
+    let mut snake = String::new();
+    s.chars().enumerate().for_each(|(i, c)| {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    });
+    snake
+}
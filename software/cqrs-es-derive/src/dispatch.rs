@@ -0,0 +1,108 @@
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{Data, DataEnum, DeriveInput, Error, Fields, Result};
+
+use crate::attr::{snake_case, Args};
+
+pub fn derive_event(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let args = Args::parse(
+        &input.attrs,
+        "event",
+        &["aggregate", "schema_version", "upcasters"],
+    )?;
+    let aggregate = args.ty("aggregate", name.span())?;
+    let arms = arms(name, enum_data(input)?, "apply");
+    let schema_version = args.optional_int("schema_version")?.map(|version| {
+        quote! {
+            fn schema_version() -> u32 {
+                #version
+            }
+        }
+    });
+    let upcasters = args.optional_path("upcasters")?.map(|upcasters| {
+        quote! {
+            fn upcasters() -> ::cqrs_es::store::upcaster::Upcasters {
+                #upcasters()
+            }
+        }
+    });
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::cqrs_es::Event<#aggregate> for #name #ty_generics #where_clause {
+            fn apply_to(self, aggregate: &mut #aggregate) {
+                match self {
+                    #(#arms)*
+                }
+            }
+
+            #schema_version
+            #upcasters
+        }
+    })
+}
+
+pub fn derive_command(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let span = name.span();
+    let args = Args::parse(&input.attrs, "command", &["aggregate", "events", "error"])?;
+    let aggregate = args.ty("aggregate", span)?;
+    let events = args.ty("events", span)?;
+    let error = args.ty("error", span)?;
+    let arms = arms(name, enum_data(input)?, "handle");
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::cqrs_es::Command<#aggregate> for #name #ty_generics #where_clause {
+            type Events = #events;
+            type Error = #error;
+
+            fn execute_on(self, aggregate: &#aggregate) -> ::std::result::Result<Self::Events, Self::Error> {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+fn enum_data(input: &DeriveInput) -> Result<&DataEnum> {
+    match &input.data {
+        Data::Enum(data) => Ok(data),
+        _ => Err(Error::new_spanned(input, "only enums are supported")),
+    }
+}
+
+/// 各バリアントを分解し、 `aggregate.<prefix>_<バリアント名>(フィールド...)` を呼び出すmatchのアーム
+fn arms(name: &Ident, data: &DataEnum, prefix: &str) -> Vec<TokenStream> {
+    data.variants
+        .iter()
+        .map(|variant| {
+            let ident = &variant.ident;
+            let method = Ident::new(
+                &format!("{}_{}", prefix, snake_case(&ident.to_string())),
+                Span::call_site(),
+            );
+            match &variant.fields {
+                Fields::Named(fields) => {
+                    let fields: Vec<_> = fields.named.iter().map(|f| &f.ident).collect();
+                    quote! {
+                        #name::#ident { #(#fields),* } => aggregate.#method(#(#fields),*),
+                    }
+                }
+                Fields::Unnamed(fields) => {
+                    let fields: Vec<_> = (0..fields.unnamed.len())
+                        .map(|i| format_ident!("field{}", i))
+                        .collect();
+                    quote! {
+                        #name::#ident(#(#fields),*) => aggregate.#method(#(#fields),*),
+                    }
+                }
+                Fields::Unit => quote! {
+                    #name::#ident => aggregate.#method(),
+                },
+            }
+        })
+        .collect()
+}
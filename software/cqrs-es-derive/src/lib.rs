@@ -0,0 +1,46 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+mod aggregate;
+mod attr;
+mod dispatch;
+
+/// `Aggregate` を実装する
+///
+/// `#[aggregate(type_name = "...", event = "...", command = "...")]` で型名と関連型を指定する。
+/// enumの場合、 `#[aggregate(default)]` を付けたユニットバリアントを返す `Default` も実装する
+#[proc_macro_derive(Aggregate, attributes(aggregate))]
+pub fn derive_aggregate(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    aggregate::derive(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// バリアントごとに、Aggregateの `apply_<バリアント名>` を呼び出す `Event` を実装する
+///
+/// `#[event(aggregate = "...")]` で適用先のAggregateを指定する。
+/// `#[event(schema_version = 2, upcasters = "path::to::fn")]` で、スキーマバージョンと
+/// `Upcasters` を返す関数を指定できる。綴りを誤ったキーはコンパイルエラーとなる。
+/// バリアントのフィールドは、宣言順に引数として渡す
+#[proc_macro_derive(Event, attributes(event))]
+pub fn derive_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    dispatch::derive_event(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// バリアントごとに、Aggregateの `handle_<バリアント名>` を呼び出す `Command` を実装する
+///
+/// `#[command(aggregate = "...", events = "...", error = "...")]` で実行先のAggregateと関連型を指定する。
+/// バリアントのフィールドは、宣言順に引数として渡す
+#[proc_macro_derive(Command, attributes(command))]
+pub fn derive_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    dispatch::derive_command(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
@@ -0,0 +1,154 @@
+extern crate cqrs_es;
+extern crate cqrs_es_derive;
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+extern crate serde_json;
+
+use cqrs_es::store::upcaster::Upcasters;
+use cqrs_es::testing::*;
+use cqrs_es::{Aggregate, CommandError, Event};
+use serde_json::json;
+
+#[derive(Debug, Clone, Eq, PartialEq, cqrs_es_derive::Aggregate)]
+#[aggregate(
+    type_name = "test/counter",
+    event = "CounterEvent",
+    command = "CounterCommand"
+)]
+enum CounterAggregate {
+    #[aggregate(default)]
+    Uninitialized,
+    Counting {
+        count: u64,
+    },
+}
+
+impl CounterAggregate {
+    fn apply_started(&mut self) {
+        *self = CounterAggregate::Counting { count: 0 }
+    }
+
+    fn apply_added(&mut self, amount: u64) {
+        if let CounterAggregate::Counting { count } = self {
+            *count += amount
+        }
+    }
+
+    fn apply_reset_to(&mut self, value: u64) {
+        if let CounterAggregate::Counting { count } = self {
+            *count = value
+        }
+    }
+
+    fn handle_start(&self) -> Result<Option<CounterEvent>, CounterCommandError> {
+        match self {
+            CounterAggregate::Uninitialized => Ok(Some(CounterEvent::Started)),
+            _ => Err(CounterCommandError::AlreadyStarted),
+        }
+    }
+
+    fn handle_add(&self, amount: u64) -> Result<Option<CounterEvent>, CounterCommandError> {
+        match self {
+            CounterAggregate::Counting { .. } => Ok(Some(CounterEvent::Added { amount })),
+            _ => Err(CounterCommandError::Uninitialized),
+        }
+    }
+
+    fn handle_reset_to(&self, value: u64) -> Result<Option<CounterEvent>, CounterCommandError> {
+        match self {
+            CounterAggregate::Counting { .. } => Ok(Some(CounterEvent::ResetTo(value))),
+            _ => Err(CounterCommandError::Uninitialized),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, cqrs_es_derive::Event)]
+#[event(
+    aggregate = "CounterAggregate",
+    schema_version = 2,
+    upcasters = "counter_event_upcasters"
+)]
+enum CounterEvent {
+    Started,
+    Added { amount: u64 },
+    ResetTo(u64),
+}
+
+/// スキーマバージョン1の `Reset` を `ResetTo(0)` に変換する
+fn counter_event_upcasters() -> Upcasters {
+    Upcasters::new().register(1, |event| match event.as_str() {
+        Some("Reset") => Ok(json!({ "ResetTo": 0 })),
+        _ => Ok(event),
+    })
+}
+
+#[derive(Debug, Clone, cqrs_es_derive::Command)]
+#[command(
+    aggregate = "CounterAggregate",
+    events = "Option<CounterEvent>",
+    error = "CounterCommandError"
+)]
+enum CounterCommand {
+    Start,
+    Add { amount: u64 },
+    ResetTo(u64),
+}
+
+#[derive(Fail, Debug, Eq, PartialEq)]
+enum CounterCommandError {
+    #[fail(display = "Counter already started")]
+    AlreadyStarted,
+    #[fail(display = "Counter uninitialized")]
+    Uninitialized,
+}
+
+impl CommandError for CounterCommandError {}
+
+#[test]
+fn aggregate() {
+    assert_eq!(CounterAggregate::type_name(), "test/counter");
+    assert_eq!(CounterAggregate::default(), CounterAggregate::Uninitialized);
+}
+
+#[test]
+fn event() {
+    let mut aggregate = CounterAggregate::default();
+
+    CounterEvent::Started.apply_to(&mut aggregate);
+    CounterEvent::Added { amount: 3 }.apply_to(&mut aggregate);
+    assert_eq!(aggregate, CounterAggregate::Counting { count: 3 });
+
+    CounterEvent::ResetTo(1).apply_to(&mut aggregate);
+    assert_eq!(aggregate, CounterAggregate::Counting { count: 1 });
+}
+
+#[test]
+fn event_schema() {
+    assert_eq!(CounterEvent::schema_version(), 2);
+    let got = CounterEvent::upcasters().upcast(json!("Reset"), 1, 2);
+    assert_eq!(got, Ok(json!({ "ResetTo": 0 })));
+}
+
+#[test]
+fn command() {
+    given_no_previous_events::<CounterAggregate>()
+        .when(CounterCommand::Start)
+        .then_expect_events(vec![CounterEvent::Started]);
+    given::<CounterAggregate>(vec![CounterEvent::Started])
+        .when(CounterCommand::Add { amount: 2 })
+        .then_expect_events(vec![CounterEvent::Added { amount: 2 }]);
+    given::<CounterAggregate>(vec![CounterEvent::Started])
+        .when(CounterCommand::ResetTo(5))
+        .then_expect_events(vec![CounterEvent::ResetTo(5)]);
+}
+
+#[test]
+fn command_error() {
+    given::<CounterAggregate>(vec![CounterEvent::Started])
+        .when(CounterCommand::Start)
+        .then_expect_error(CounterCommandError::AlreadyStarted);
+    given_no_previous_events::<CounterAggregate>()
+        .when(CounterCommand::Add { amount: 2 })
+        .then_expect_error(CounterCommandError::Uninitialized);
+}
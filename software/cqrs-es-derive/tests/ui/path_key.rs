@@ -0,0 +1,5 @@
+#[derive(Debug, Clone, cqrs_es_derive::Event)]
+#[event(aggregate = "UiAggregate", cqrs::schema_version = 2)]
+enum UiEvent {}
+
+fn main() {}
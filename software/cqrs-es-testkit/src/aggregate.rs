@@ -0,0 +1,53 @@
+use cqrs_es::*;
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+
+/// テストスイートで使うAggregate。適用したEventの数を数える
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub struct TestAggregate(pub u64);
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum TestEvent {
+    Increased,
+}
+
+impl Event<TestAggregate> for TestEvent {
+    fn apply_to(self, aggregate: &mut TestAggregate) {
+        aggregate.0 += 1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TestCommand {
+    Increase,
+    Invalid,
+}
+
+#[derive(Fail, Debug, Eq, PartialEq)]
+pub enum TestCommandError {
+    #[fail(display = "Invalid")]
+    Invalid,
+}
+
+impl CommandError for TestCommandError {}
+
+impl Command<TestAggregate> for TestCommand {
+    type Events = Option<TestEvent>;
+    type Error = TestCommandError;
+
+    fn execute_on(self, _aggregate: &TestAggregate) -> Result<Self::Events, Self::Error> {
+        match self {
+            TestCommand::Increase => Ok(Some(TestEvent::Increased)),
+            TestCommand::Invalid => Err(TestCommandError::Invalid),
+        }
+    }
+}
+
+impl Aggregate for TestAggregate {
+    type Event = TestEvent;
+    type Command = TestCommand;
+
+    fn type_name() -> &'static str {
+        "conformance"
+    }
+}
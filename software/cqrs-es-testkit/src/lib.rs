@@ -0,0 +1,128 @@
+//! `EventStorage` の実装が満たすべき振る舞いを検証するテストスイート
+//!
+//! 検証対象のEventStorageを開く `StorageFactory` を用意し、
+//! `event_storage_conformance!` でテストを生成するか、個々の関数を呼び出して使う。
+//! バックエンドに依存しない検証とテスト用の型はここにまとめ、
+//! 各バックエンドのテストには、そのバックエンド固有の振る舞いの検証だけを置く
+//!
+//! ```ignore
+//! struct Factory(TestDir);
+//!
+//! impl StorageFactory for Factory {
+//!     type Storage = FileEventStorage<TestAggregate, TestEvent>;
+//!     type Log = FileEventLog;
+//!
+//!     fn open(&self) -> Self::Storage {
+//!         FileEventStorage::new(self.0.path()).unwrap()
+//!     }
+//!
+//!     fn open_log(&self) -> Self::Log {
+//!         FileEventLog::new(self.0.path()).unwrap()
+//!     }
+//! }
+//!
+//! cqrs_es_testkit::event_storage_conformance!(Factory(TestDir::new()));
+//! ```
+extern crate failure;
+extern crate serde;
+extern crate uuid;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use cqrs_es::store::{DeletableEventStorage, EventLog};
+use uuid::Uuid;
+
+mod aggregate;
+pub use aggregate::{TestAggregate, TestCommand, TestCommandError, TestEvent};
+
+mod saga;
+pub use saga::CountingSaga;
+
+pub mod suite;
+
+/// 検証対象のEventStorageと、同じ保存先の全ストリームのログを開く
+///
+/// `open` は呼び出すたびに同じ保存先を開いたEventStorageを返す。
+/// 同時実行の検証では、複数のスレッドから同時に呼び出される
+pub trait StorageFactory {
+    type Storage: DeletableEventStorage<TestAggregate>;
+    type Log: EventLog;
+
+    fn open(&self) -> Self::Storage;
+
+    fn open_log(&self) -> Self::Log;
+}
+
+/// テストごとに `target/tests` 配下に作り、ドロップ時に削除するディレクトリ
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new() -> TestDir {
+        let mut path = PathBuf::new();
+        path.push("target");
+        path.push("tests");
+        path.push(Uuid::new_v4().to_string());
+        TestDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Default for TestDir {
+    fn default() -> Self {
+        TestDir::new()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        // 保存先を作らずに終わったテストもあるため、削除の失敗は無視する
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// `suite` のすべての検証を、それぞれ `#[test]` として生成する
+///
+/// `$factory` はテストごとに評価されるため、テストごとに別の保存先を開くようにする
+#[macro_export]
+macro_rules! event_storage_conformance {
+    ($factory:expr) => {
+        $crate::event_storage_conformance!(
+            $factory;
+            ordering,
+            empty_stream,
+            read_from,
+            version_inconsistency,
+            concurrency_conflict,
+            concurrency_conflict_across_threads,
+            concurrent_appends,
+            concurrent_appends_with_retry,
+            append_batch,
+            large_stream,
+            reopen_after_drop,
+            list_ids,
+            list_ids_in_creation_order,
+            is_processed,
+            idempotency_key_survives_reopen,
+            delete_archive,
+            delete_purge,
+            insert_after_delete,
+            read_all,
+            read_all_from_multiple_storages,
+            saga_runner
+        );
+    };
+    ($factory:expr; $($name:ident),*) => {
+        $(
+            #[test]
+            fn $name() {
+                $crate::suite::$name(&$factory);
+            }
+        )*
+    };
+}
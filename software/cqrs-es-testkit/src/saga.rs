@@ -0,0 +1,30 @@
+use cqrs_es::saga::{Saga, Step};
+use cqrs_es::store::VersionedEvent;
+use cqrs_es::Id;
+use serde::{Deserialize, Serialize};
+
+use crate::TestAggregate;
+
+/// 処理した `TestAggregate` のEventを数えるだけのSaga
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CountingSaga {
+    pub count: u64,
+}
+
+impl Saga for CountingSaga {
+    type Source = TestAggregate;
+    type Target = TestAggregate;
+
+    fn type_name() -> &'static str {
+        "counting"
+    }
+
+    fn handle(
+        &mut self,
+        _id: Id<TestAggregate>,
+        _event: &VersionedEvent<TestAggregate>,
+    ) -> Vec<Step<Self>> {
+        self.count += 1;
+        Vec::new()
+    }
+}
@@ -0,0 +1,519 @@
+//! 各検証は、まだEventが保存されていない保存先を開く `factory` を受け取る
+
+use std::thread;
+use std::time::Duration;
+
+use cqrs_es::bus::CommandBus;
+use cqrs_es::saga::{InMemorySagaStore, SagaRunner};
+use cqrs_es::store::retry::Backoff;
+use cqrs_es::store::*;
+use cqrs_es::Id;
+
+use crate::{CountingSaga, StorageFactory, TestAggregate, TestCommand, TestEvent};
+
+/// 同時実行の検証で使うスレッド数と、スレッドごとに実行するコマンドの数
+const THREADS: u64 = 4;
+const COMMANDS_PER_THREAD: u64 = 10;
+
+/// 大きなストリームの検証で保存するEventの数と、1回の `append` で追加する数
+const LARGE_STREAM_LEN: u64 = 1000;
+const LARGE_STREAM_BATCH: u64 = 100;
+
+fn event(version: u64) -> VersionedEvent<TestAggregate> {
+    VersionedEvent {
+        version: Version(version),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    }
+}
+
+fn versions<I>(events: I) -> Vec<Version>
+where
+    I: IntoIterator<Item = VersionedEvent<TestAggregate>>,
+{
+    events.into_iter().map(|e| e.version).collect()
+}
+
+/// ログのEventを、位置とAggregate ID、バージョンの組にする
+fn logged<L: EventLog>(log: &L, from: Position) -> Vec<(Position, Id<TestAggregate>, Version)> {
+    log.read_all(from)
+        .unwrap()
+        .into_iter()
+        .map(|e| {
+            let (id, event) = e.decode::<TestAggregate, _>().unwrap().unwrap();
+            (e.position, id, event.version)
+        })
+        .collect()
+}
+
+/// Eventは追加した順に読め、他のストリームとは混ざらない
+pub fn ordering<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id1 = Id::<TestAggregate>::new();
+    let id2 = Id::<TestAggregate>::new();
+
+    storage.insert(id1, event(1)).unwrap();
+    storage.insert(id2, event(1)).unwrap();
+    storage
+        .append(id1, Version(1), vec![event(2), event(3)])
+        .unwrap();
+    storage.execute_command(id2, TestCommand::Increase).unwrap();
+    storage.execute_command(id1, TestCommand::Increase).unwrap();
+
+    assert_eq!(
+        versions(storage.read(id1).unwrap()),
+        (1..=4).map(Version).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        versions(storage.read(id2).unwrap()),
+        vec![Version(1), Version(2)]
+    );
+
+    let mut ids = vec![id1, id2];
+    ids.sort();
+    let got = storage.list_ids(Paging::all()).unwrap();
+    assert_eq!(got.iter().map(|s| s.id).collect::<Vec<_>>(), ids);
+}
+
+/// Eventのないストリームは空として扱い、空の `append` ではストリームを作らない
+pub fn empty_stream<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+
+    assert!(storage.read(id).unwrap().into_iter().next().is_none());
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(0));
+    assert_eq!(aggregate.aggregate, TestAggregate(0));
+
+    storage.append(id, Version(0), Vec::new()).unwrap();
+    // 失敗したコマンドもEventを保存しない
+    storage
+        .execute_command(id, TestCommand::Invalid)
+        .unwrap_err();
+
+    assert!(storage.read(id).unwrap().into_iter().next().is_none());
+    assert!(storage.list_ids(Paging::all()).unwrap().is_empty());
+}
+
+/// `read_from` は、まとめて追加したEventの途中からでも、指定したバージョン以降を返す
+pub fn read_from<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+    assert!(storage.read_from(id, Version(1)).unwrap().is_empty());
+
+    storage.insert(id, event(1)).unwrap();
+    storage
+        .append(id, Version(1), vec![event(2), event(3), event(4)])
+        .unwrap();
+    storage.append(id, Version(4), vec![event(5)]).unwrap();
+
+    let got = |from| versions(storage.read_from(id, Version(from)).unwrap());
+    assert_eq!(got(0), (1..=5).map(Version).collect::<Vec<_>>());
+    assert_eq!(got(3), vec![Version(3), Version(4), Version(5)]);
+    assert_eq!(got(5), vec![Version(5)]);
+    assert!(got(6).is_empty());
+}
+
+/// バージョンが連続していないストリームは再構築できない
+pub fn version_inconsistency<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+
+    storage.insert(id, event(1)).unwrap();
+    storage.insert(id, event(3)).unwrap();
+
+    match storage.replay_aggregate(id) {
+        Err(ReplayAggregateError::VersionInconsistent) => {}
+        Err(e) => panic!("expected VersionInconsistent, but got error {:?}", e),
+        Ok(a) => panic!("expected VersionInconsistent, but got {:?}", a),
+    }
+}
+
+/// 最新バージョンが `expected_version` と異なれば、ひとつも追加しない
+pub fn concurrency_conflict<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+    storage.append(id, Version(0), vec![event(1)]).unwrap();
+
+    match storage.append(id, Version(0), vec![event(1), event(2)]) {
+        Err(AppendError::ConcurrencyConflict { expected, actual }) => {
+            assert_eq!(expected, Version(0));
+            assert_eq!(actual, Version(1));
+        }
+        Err(e) => panic!("expected ConcurrencyConflict, but got error {:?}", e),
+        Ok(()) => panic!("expected ConcurrencyConflict, but succeeded"),
+    }
+    assert_eq!(versions(storage.read(id).unwrap()), vec![Version(1)]);
+}
+
+/// 複数のEventStorageから同じバージョンに同時に `append` しても、成功するのはひとつだけ
+pub fn concurrency_conflict_across_threads<F: StorageFactory + Sync>(factory: &F) {
+    let id = Id::<TestAggregate>::new();
+
+    let appended = thread::scope(|s| {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                s.spawn(|| {
+                    factory
+                        .open()
+                        .append(id, Version(0), vec![event(1)])
+                        .is_ok()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|ok| *ok)
+            .count()
+    });
+
+    assert_eq!(appended, 1);
+    assert_eq!(versions(factory.open().read(id).unwrap()), vec![Version(1)]);
+}
+
+/// 複数のEventStorageから同じストリームに同時にコマンドを実行しても、Eventが失われず、バージョンが重複しない
+pub fn concurrent_appends<F: StorageFactory + Sync>(factory: &F) {
+    let id = Id::<TestAggregate>::new();
+
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                let mut storage = factory.open();
+                let mut executed = 0;
+                while executed < COMMANDS_PER_THREAD {
+                    match storage.execute_command(id, TestCommand::Increase) {
+                        Ok(()) => executed += 1,
+                        Err(ExecuteCommandError::ConcurrencyConflict { .. }) => {}
+                        Err(e) => panic!("{:?}", e),
+                    }
+                }
+            });
+        }
+    });
+
+    let storage = factory.open();
+    let total = THREADS * COMMANDS_PER_THREAD;
+    assert_eq!(
+        versions(storage.read(id).unwrap()),
+        (1..=total).map(Version).collect::<Vec<_>>()
+    );
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.aggregate, TestAggregate(total));
+}
+
+/// 競合時に再試行するコマンドは、複数のEventStorageから同時に実行してもすべて保存される
+pub fn concurrent_appends_with_retry<F: StorageFactory + Sync>(factory: &F) {
+    let id = Id::<TestAggregate>::new();
+    let policy = RetryPolicy::new(
+        100,
+        Backoff::Exponential {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(10),
+        },
+    );
+
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                let mut storage = factory.open();
+                for _ in 0..COMMANDS_PER_THREAD {
+                    storage
+                        .execute_command_with_retry(id, TestCommand::Increase, policy)
+                        .unwrap();
+                }
+            });
+        }
+    });
+
+    let total = THREADS * COMMANDS_PER_THREAD;
+    assert_eq!(
+        versions(factory.open().read(id).unwrap()),
+        (1..=total).map(Version).collect::<Vec<_>>()
+    );
+}
+
+/// まとめて追加したEventは、ストリームにもログにも順に記録される
+pub fn append_batch<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+
+    storage
+        .append(id, Version(0), vec![event(1), event(2), event(3)])
+        .unwrap();
+
+    assert_eq!(
+        versions(storage.read(id).unwrap()),
+        vec![Version(1), Version(2), Version(3)]
+    );
+    assert_eq!(
+        logged(&factory.open_log(), Position::default()),
+        vec![
+            (Position(1), id, Version(1)),
+            (Position(2), id, Version(2)),
+            (Position(3), id, Version(3)),
+        ]
+    );
+}
+
+/// 多数のEventを持つストリームも、すべて順に読める
+pub fn large_stream<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+
+    for start in (1..=LARGE_STREAM_LEN).step_by(LARGE_STREAM_BATCH as usize) {
+        let events = (start..start + LARGE_STREAM_BATCH).map(event).collect();
+        storage.append(id, Version(start - 1), events).unwrap();
+    }
+
+    assert_eq!(
+        versions(storage.read(id).unwrap()),
+        (1..=LARGE_STREAM_LEN).map(Version).collect::<Vec<_>>()
+    );
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(LARGE_STREAM_LEN));
+    assert_eq!(aggregate.aggregate, TestAggregate(LARGE_STREAM_LEN));
+}
+
+/// EventStorageをドロップして開き直しても、保存したEventが残っている
+pub fn reopen_after_drop<F: StorageFactory>(factory: &F) {
+    let id = Id::<TestAggregate>::new();
+    let mut storage = factory.open();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    drop(storage);
+
+    let mut storage = factory.open();
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(2));
+    assert_eq!(aggregate.aggregate, TestAggregate(2));
+
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    drop(storage);
+
+    let storage = factory.open();
+    let got = storage.list_ids(Paging::all()).unwrap();
+    assert_eq!(got.len(), 1);
+    assert_eq!(got[0].id, id);
+    assert_eq!(got[0].last_version, Version(3));
+}
+
+/// `list_ids` はストリームをID順に返し、競合して追加できなかったストリームは含まない
+pub fn list_ids<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    assert!(storage.list_ids(Paging::all()).unwrap().is_empty());
+
+    let mut ids: Vec<_> = (0..3).map(|_| Id::<TestAggregate>::new()).collect();
+    ids.sort();
+    for id in &ids {
+        storage.execute_command(*id, TestCommand::Increase).unwrap();
+    }
+    storage
+        .execute_command(ids[0], TestCommand::Increase)
+        .unwrap();
+    storage
+        .append(Id::new(), Version(1), vec![event(2)])
+        .unwrap_err();
+
+    let got = storage.list_ids(Paging::all()).unwrap();
+    assert_eq!(got.iter().map(|s| s.id).collect::<Vec<_>>(), ids);
+    assert_eq!(
+        got.iter().map(|s| s.last_version).collect::<Vec<_>>(),
+        vec![Version(2), Version(1), Version(1)]
+    );
+    assert!(got.iter().all(|s| s.last_modified.is_some()));
+
+    let got = storage.list_ids(Paging::new(1, 5)).unwrap();
+    assert_eq!(got.iter().map(|s| s.id).collect::<Vec<_>>(), ids[1..]);
+}
+
+/// `Id::new_ordered` で作ったIDのストリームは、作成順に並ぶ
+pub fn list_ids_in_creation_order<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let ids: Vec<_> = (0..3)
+        .map(|_| {
+            thread::sleep(Duration::from_millis(2));
+            Id::<TestAggregate>::new_ordered()
+        })
+        .collect();
+    for id in ids.iter().rev() {
+        storage.execute_command(*id, TestCommand::Increase).unwrap();
+    }
+
+    let got = storage.list_ids(Paging::all()).unwrap();
+    assert_eq!(got.iter().map(|s| s.id).collect::<Vec<_>>(), ids);
+}
+
+/// `is_processed` は、記号を含むキーも含めて完全に一致するキーだけを処理済みとする
+pub fn is_processed<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+    let key = "\"quoted\"\\key";
+    storage
+        .execute_command_with_metadata(
+            id,
+            TestCommand::Increase,
+            Metadata::new().with_idempotency_key(key),
+        )
+        .unwrap();
+
+    assert!(storage.is_processed(id, key).unwrap());
+    assert!(!storage.is_processed(id, "quoted").unwrap());
+    assert!(!storage.is_processed(id, "other").unwrap());
+    assert!(!storage.is_processed(Id::new(), key).unwrap());
+}
+
+/// 開き直した後も、処理済みの `idempotency_key` を持つコマンドは保存しない
+pub fn idempotency_key_survives_reopen<F: StorageFactory>(factory: &F) {
+    let id = Id::<TestAggregate>::new();
+    let metadata = Metadata::new().with_idempotency_key("increase-1");
+    let mut storage = factory.open();
+    storage
+        .execute_command_with_metadata(id, TestCommand::Increase, metadata.clone())
+        .unwrap();
+    drop(storage);
+
+    let mut storage = factory.open();
+    storage
+        .execute_command_with_metadata(id, TestCommand::Increase, metadata)
+        .unwrap();
+
+    assert_eq!(versions(storage.read(id).unwrap()), vec![Version(1)]);
+}
+
+/// アーカイブしたストリームは開き直した後も削除済みとして扱い、Eventは `read_archived` とログから読める
+pub fn delete_archive<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    storage.delete(id, DeleteMode::Archive).unwrap();
+    drop(storage);
+
+    let mut storage = factory.open();
+    assert!(storage.is_deleted(id).unwrap());
+    assert!(storage.list_ids(Paging::all()).unwrap().is_empty());
+    assert_eq!(
+        versions(storage.read_archived(id).unwrap()),
+        vec![Version(1), Version(2)]
+    );
+    match storage.execute_command(id, TestCommand::Increase) {
+        Err(ExecuteCommandError::Deleted) => {}
+        Err(e) => panic!("expected Deleted, but got error {:?}", e),
+        Ok(()) => panic!("expected Deleted, but succeeded"),
+    }
+    assert!(storage.insert(id, event(3)).unwrap_err().is_deleted());
+    assert_eq!(logged(&factory.open_log(), Position::default()).len(), 2);
+}
+
+/// 消去したストリームのEventは、アーカイブからもログからも取り除き、ログの位置は再利用しない
+pub fn delete_purge<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+    let other = Id::<TestAggregate>::new();
+    storage
+        .execute_command(other, TestCommand::Increase)
+        .unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.delete(id, DeleteMode::Archive).unwrap();
+
+    storage.delete(id, DeleteMode::Purge).unwrap();
+
+    assert!(storage.is_deleted(id).unwrap());
+    assert!(storage
+        .read_archived(id)
+        .unwrap()
+        .into_iter()
+        .next()
+        .is_none());
+    storage
+        .execute_command(other, TestCommand::Increase)
+        .unwrap();
+    assert_eq!(
+        logged(&factory.open_log(), Position::default()),
+        vec![
+            (Position(1), other, Version(1)),
+            (Position(3), other, Version(2)),
+        ]
+    );
+}
+
+/// Eventを保存する前に削除したストリームにも、Eventを追加できない
+pub fn insert_after_delete<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+    storage.delete(id, DeleteMode::Purge).unwrap();
+
+    assert!(storage.insert(id, event(1)).unwrap_err().is_deleted());
+    match storage.replay_aggregate(id) {
+        Err(ReplayAggregateError::Deleted) => {}
+        Err(e) => panic!("expected Deleted, but got error {:?}", e),
+        Ok(a) => panic!("expected Deleted, but got {:?}", a),
+    }
+    assert!(storage.list_ids(Paging::all()).unwrap().is_empty());
+}
+
+/// ログはすべてのストリームのEventを記録した順に返し、 `from` 以降だけを読める
+pub fn read_all<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let log = factory.open_log();
+    assert!(logged(&log, Position::default()).is_empty());
+
+    let id1 = Id::<TestAggregate>::new();
+    let id2 = Id::<TestAggregate>::new();
+    storage.execute_command(id1, TestCommand::Increase).unwrap();
+    storage.execute_command(id2, TestCommand::Increase).unwrap();
+    storage.execute_command(id1, TestCommand::Increase).unwrap();
+
+    assert_eq!(
+        logged(&log, Position::default()),
+        vec![
+            (Position(1), id1, Version(1)),
+            (Position(2), id2, Version(1)),
+            (Position(3), id1, Version(2)),
+        ]
+    );
+    assert_eq!(
+        logged(&log, Position(3)),
+        vec![(Position(3), id1, Version(2))]
+    );
+}
+
+/// 複数のEventStorageから同時に書き込んでも、ログの位置は重複も欠番もしない
+pub fn read_all_from_multiple_storages<F: StorageFactory + Sync>(factory: &F) {
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                let mut storage = factory.open();
+                let id = Id::<TestAggregate>::new();
+                for _ in 0..COMMANDS_PER_THREAD {
+                    storage.execute_command(id, TestCommand::Increase).unwrap();
+                }
+            });
+        }
+    });
+
+    let got: Vec<_> = logged(&factory.open_log(), Position::default())
+        .into_iter()
+        .map(|(position, _, _)| position)
+        .collect();
+    let total = THREADS * COMMANDS_PER_THREAD;
+    assert_eq!(got, (1..=total).map(Position).collect::<Vec<_>>());
+}
+
+/// Sagaは前回処理した位置より後に記録されたEventだけを処理する
+pub fn saga_runner<F: StorageFactory>(factory: &F) {
+    let mut storage = factory.open();
+    let id = Id::<TestAggregate>::new();
+    for _ in 0..3 {
+        storage.execute_command(id, TestCommand::Increase).unwrap();
+    }
+    let mut bus = CommandBus::new();
+    let mut runner = SagaRunner::new(factory.open_log(), InMemorySagaStore::<CountingSaga>::new());
+
+    assert_eq!(runner.run(&mut bus).unwrap(), 3);
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    assert_eq!(runner.run(&mut bus).unwrap(), 1);
+    assert_eq!(runner.run(&mut bus).unwrap(), 0);
+}
@@ -0,0 +1,207 @@
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use failure::Fail;
+
+use crate::store::{EventStorage, EventStorageError, ExecuteCommandError, Metadata, Version};
+use crate::{Aggregate, CommandError, Id};
+
+pub mod middleware;
+
+#[cfg(test)]
+mod tests;
+
+/// コマンドを、登録されたEventStorageとAggregate IDに振り分けて実行する
+///
+/// 実行の前後には、追加した順にMiddlewareを挟む
+#[derive(Default)]
+pub struct CommandBus {
+    handlers: HashMap<TypeId, Box<dyn Any>>,
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl CommandBus {
+    pub fn new() -> Self {
+        CommandBus::default()
+    }
+
+    /// `A` のコマンドを、 `route` で決まるIDのAggregateに対して `storage` で実行するよう登録する
+    ///
+    /// 同じAggregateを再度登録した場合は置き換える
+    pub fn register<A, S, R>(&mut self, storage: S, route: R)
+    where
+        A: Aggregate + 'static,
+        A::Command: Clone + 'static,
+        S: EventStorage<A> + 'static,
+        R: Fn(&A::Command) -> Id<A> + 'static,
+    {
+        let handler: Box<dyn Handler<A::Command>> = Box::new(StorageHandler {
+            storage,
+            route,
+            phantom: PhantomData,
+        });
+        self.handlers
+            .insert(TypeId::of::<A::Command>(), Box::new(handler));
+    }
+
+    pub fn add_middleware<M>(&mut self, middleware: M)
+    where
+        M: Middleware + 'static,
+    {
+        self.middlewares.push(Box::new(middleware))
+    }
+
+    pub fn dispatch<C>(&mut self, command: C) -> Result<(), DispatchError>
+    where
+        C: Clone + 'static,
+    {
+        self.dispatch_with_metadata(command, Metadata::new())
+    }
+
+    pub fn dispatch_with_metadata<C>(
+        &mut self,
+        command: C,
+        metadata: Metadata,
+    ) -> Result<(), DispatchError>
+    where
+        C: Clone + 'static,
+    {
+        let handler = self
+            .handlers
+            .get_mut(&TypeId::of::<C>())
+            .and_then(|h| h.downcast_mut::<Box<dyn Handler<C>>>())
+            .ok_or(DispatchError::NotRegistered {
+                command: type_name::<C>(),
+            })?;
+        let context = CommandContext {
+            aggregate_type: handler.aggregate_type(),
+            command_type: type_name::<C>(),
+            metadata,
+            command: &command,
+        };
+        // Middlewareが複数回実行できるよう、実行のたびにコマンドを複製する
+        let mut execute =
+            |context: &CommandContext| handler.handle(command.clone(), context.metadata.clone());
+        Next {
+            middlewares: &self.middlewares,
+            execute: &mut execute,
+        }
+        .run(&context)
+    }
+}
+
+trait Handler<C> {
+    fn aggregate_type(&self) -> &'static str;
+
+    fn handle(&mut self, command: C, metadata: Metadata) -> Result<(), DispatchError>;
+}
+
+struct StorageHandler<A, S, R> {
+    storage: S,
+    route: R,
+    phantom: PhantomData<A>,
+}
+
+impl<A, S, R> Handler<A::Command> for StorageHandler<A, S, R>
+where
+    A: Aggregate,
+    S: EventStorage<A>,
+    R: Fn(&A::Command) -> Id<A>,
+{
+    fn aggregate_type(&self) -> &'static str {
+        A::type_name()
+    }
+
+    fn handle(&mut self, command: A::Command, metadata: Metadata) -> Result<(), DispatchError> {
+        let id = (self.route)(&command);
+        self.storage
+            .execute_command_with_metadata(id, command, metadata)
+            .map_err(DispatchError::from)
+    }
+}
+
+/// Middlewareに渡される、実行しようとしているコマンドの情報
+pub struct CommandContext<'a> {
+    pub aggregate_type: &'static str,
+    pub command_type: &'static str,
+    pub metadata: Metadata,
+    command: &'a dyn Any,
+}
+
+impl<'a> CommandContext<'a> {
+    /// コマンドが `C` であれば参照を返す
+    pub fn command<C: 'static>(&self) -> Option<&'a C> {
+        self.command.downcast_ref()
+    }
+
+    /// Metadataを差し替えて後続に渡す場合に使う
+    pub fn with_metadata(&self, metadata: Metadata) -> CommandContext<'a> {
+        CommandContext {
+            aggregate_type: self.aggregate_type,
+            command_type: self.command_type,
+            metadata,
+            command: self.command,
+        }
+    }
+}
+
+/// コマンドの実行の前後に処理を挟む
+pub trait Middleware {
+    /// 後続のMiddlewareとコマンドの実行は `next.run` で行う
+    ///
+    /// `next.run` を呼ばなければコマンドは実行されず、複数回呼べば再実行される
+    fn handle(&self, context: &CommandContext, next: &mut Next) -> Result<(), DispatchError>;
+}
+
+/// 後続のMiddlewareと、最後に行うコマンドの実行
+pub struct Next<'a, 'e> {
+    middlewares: &'a [Box<dyn Middleware>],
+    execute: &'a mut (dyn FnMut(&CommandContext) -> Result<(), DispatchError> + 'e),
+}
+
+impl<'a, 'e> Next<'a, 'e> {
+    pub fn run(&mut self, context: &CommandContext) -> Result<(), DispatchError> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                context,
+                &mut Next {
+                    middlewares: rest,
+                    execute: &mut *self.execute,
+                },
+            ),
+            None => (self.execute)(context),
+        }
+    }
+}
+
+/// 登録されたEventStorageやコマンドによらない形に変換した、コマンドの実行のエラー
+#[derive(Fail, Debug)]
+pub enum DispatchError {
+    #[fail(display = "No handler registered for {}", command)]
+    NotRegistered { command: &'static str },
+    /// Middlewareが実行を拒否した
+    #[fail(display = "Rejected: {}", reason)]
+    Rejected { reason: String },
+    #[fail(display = "Command error: {}", _0)]
+    Command(failure::Error),
+    #[fail(
+        display = "Concurrency conflict: expected {:?}, actual {:?}",
+        expected, actual
+    )]
+    ConcurrencyConflict { expected: Version, actual: Version },
+    #[fail(display = "Storage error: {}", _0)]
+    Storage(failure::Error),
+}
+
+impl<E: EventStorageError, C: CommandError> From<ExecuteCommandError<E, C>> for DispatchError {
+    fn from(e: ExecuteCommandError<E, C>) -> Self {
+        match e {
+            ExecuteCommandError::Command(e) => DispatchError::Command(e.into()),
+            ExecuteCommandError::ConcurrencyConflict { expected, actual } => {
+                DispatchError::ConcurrencyConflict { expected, actual }
+            }
+            e => DispatchError::Storage(e.into()),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use crate::bus::{CommandContext, DispatchError, Middleware, Next};
+use crate::store::RetryPolicy;
+
+#[cfg(test)]
+mod tests;
+
+/// 実行するコマンドと結果を標準エラー出力に書き出す
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn handle(&self, context: &CommandContext, next: &mut Next) -> Result<(), DispatchError> {
+        eprintln!(
+            "dispatch {} to {}",
+            context.command_type, context.aggregate_type
+        );
+        let result = next.run(context);
+        match result {
+            Ok(()) => eprintln!("{} succeeded", context.command_type),
+            Err(ref e) => eprintln!("{} failed: {}", context.command_type, e),
+        }
+        result
+    }
+}
+
+/// `C` のコマンドを実行前に検証し、エラーであれば拒否する
+///
+/// `C` 以外のコマンドはそのまま実行する
+pub struct Validation<C, F> {
+    validate: F,
+    phantom: PhantomData<fn(&C)>,
+}
+
+impl<C, F> Validation<C, F>
+where
+    C: 'static,
+    F: Fn(&C) -> Result<(), String>,
+{
+    pub fn new(validate: F) -> Self {
+        Validation {
+            validate,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<C, F> Middleware for Validation<C, F>
+where
+    C: 'static,
+    F: Fn(&C) -> Result<(), String>,
+{
+    fn handle(&self, context: &CommandContext, next: &mut Next) -> Result<(), DispatchError> {
+        if let Some(command) = context.command::<C>() {
+            (self.validate)(command).map_err(|reason| DispatchError::Rejected { reason })?;
+        }
+        next.run(context)
+    }
+}
+
+/// Metadataの `actor` などから、コマンドの実行を許可するかどうかを判断する
+pub struct Authorization<F> {
+    authorize: F,
+}
+
+impl<F> Authorization<F>
+where
+    F: Fn(&CommandContext) -> bool,
+{
+    pub fn new(authorize: F) -> Self {
+        Authorization { authorize }
+    }
+}
+
+impl<F> Middleware for Authorization<F>
+where
+    F: Fn(&CommandContext) -> bool,
+{
+    fn handle(&self, context: &CommandContext, next: &mut Next) -> Result<(), DispatchError> {
+        if !(self.authorize)(context) {
+            return Err(DispatchError::Rejected {
+                reason: format!(
+                    "{:?} is not authorized to dispatch {}",
+                    context.metadata.actor, context.command_type
+                ),
+            });
+        }
+        next.run(context)
+    }
+}
+
+/// 他の書き込みと競合した場合に、 `policy` に従って実行し直す
+///
+/// 実行し直すのは `DispatchError::ConcurrencyConflict` の場合のみ
+pub struct Retry {
+    pub policy: RetryPolicy,
+}
+
+impl Retry {
+    pub const fn new(policy: RetryPolicy) -> Self {
+        Retry { policy }
+    }
+}
+
+impl Middleware for Retry {
+    fn handle(&self, context: &CommandContext, next: &mut Next) -> Result<(), DispatchError> {
+        self.policy.run(
+            || next.run(context),
+            |e| matches!(e, DispatchError::ConcurrencyConflict { .. }),
+        )
+    }
+}
+
+/// コマンドの実行にかかった時間を `report` に渡す
+pub struct Timing<F> {
+    report: F,
+}
+
+impl<F> Timing<F>
+where
+    F: Fn(&CommandContext, Duration),
+{
+    pub fn new(report: F) -> Self {
+        Timing { report }
+    }
+}
+
+impl<F> Middleware for Timing<F>
+where
+    F: Fn(&CommandContext, Duration),
+{
+    fn handle(&self, context: &CommandContext, next: &mut Next) -> Result<(), DispatchError> {
+        let started_at = Instant::now();
+        let result = next.run(context);
+        (self.report)(context, started_at.elapsed());
+        result
+    }
+}
@@ -0,0 +1,174 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::bus::middleware::*;
+use crate::bus::*;
+use crate::projector::Projector;
+use crate::store::memory::InMemoryEventStorageError;
+use crate::store::retry::Backoff;
+use crate::store::*;
+use crate::tests::test_aggregate::*;
+use crate::Id;
+
+/// 最初の `conflicts` 回のappendで競合を返す
+struct ConflictingStorage {
+    inner: InMemoryEventStorage<TestAggregate>,
+    conflicts: Rc<Cell<u32>>,
+}
+
+impl EventStorage<TestAggregate> for ConflictingStorage {
+    type Events = Vec<VersionedEvent<TestAggregate>>;
+    type Error = InMemoryEventStorageError;
+
+    fn insert(
+        &mut self,
+        id: Id<TestAggregate>,
+        event: VersionedEvent<TestAggregate>,
+    ) -> Result<(), Self::Error> {
+        self.inner.insert(id, event)
+    }
+
+    fn read(&self, id: Id<TestAggregate>) -> Result<Self::Events, Self::Error> {
+        self.inner.read(id)
+    }
+
+    fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<TestAggregate>>, Self::Error> {
+        self.inner.list_ids(paging)
+    }
+
+    fn append(
+        &mut self,
+        id: Id<TestAggregate>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<TestAggregate>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        if self.conflicts.get() > 0 {
+            self.conflicts.set(self.conflicts.get() - 1);
+            return Err(AppendError::ConcurrencyConflict {
+                expected: expected_version,
+                actual: expected_version.next(),
+            });
+        }
+        self.inner.append(id, expected_version, events)
+    }
+
+    fn projectors(&mut self) -> &mut [Box<dyn Projector<TestAggregate>>] {
+        self.inner.projectors()
+    }
+}
+
+fn bus_with_conflicts(conflicts: u32) -> (CommandBus, Rc<Cell<u32>>) {
+    let conflicts = Rc::new(Cell::new(conflicts));
+    let mut bus = CommandBus::new();
+    bus.register(
+        ConflictingStorage {
+            inner: InMemoryEventStorage::new(),
+            conflicts: conflicts.clone(),
+        },
+        |_| Id::new(),
+    );
+    (bus, conflicts)
+}
+
+#[test]
+fn logging() {
+    let (mut bus, _) = bus_with_conflicts(0);
+    bus.add_middleware(Logging);
+
+    assert!(bus.dispatch(TestCommand::Increase).is_ok());
+    assert!(bus.dispatch(TestCommand::Invalid).is_err());
+}
+
+#[test]
+fn validation() {
+    let (mut bus, _) = bus_with_conflicts(0);
+    bus.add_middleware(Validation::new(|command: &TestCommand| match command {
+        TestCommand::Increase => Ok(()),
+        TestCommand::Invalid => Err("invalid".to_owned()),
+    }));
+
+    assert!(bus.dispatch(TestCommand::Increase).is_ok());
+    if let Err(DispatchError::Rejected { reason }) = bus.dispatch(TestCommand::Invalid) {
+        assert_eq!(reason, "invalid");
+    } else {
+        panic!();
+    }
+}
+
+#[test]
+fn authorization() {
+    let (mut bus, _) = bus_with_conflicts(0);
+    bus.add_middleware(Authorization::new(|context| {
+        context.metadata.actor.as_deref() == Some("admin")
+    }));
+
+    let got =
+        bus.dispatch_with_metadata(TestCommand::Increase, Metadata::new().with_actor("admin"));
+    assert!(got.is_ok());
+    let got =
+        bus.dispatch_with_metadata(TestCommand::Increase, Metadata::new().with_actor("guest"));
+    if let Err(DispatchError::Rejected { .. }) = got {
+    } else {
+        panic!();
+    }
+}
+
+#[test]
+fn retry() {
+    let (mut bus, conflicts) = bus_with_conflicts(2);
+    bus.add_middleware(Retry::new(RetryPolicy::new(
+        3,
+        Backoff::Fixed(Duration::from_millis(0)),
+    )));
+
+    assert!(bus.dispatch(TestCommand::Increase).is_ok());
+    assert_eq!(conflicts.get(), 0);
+}
+
+#[test]
+fn retry_gives_up() {
+    let (mut bus, conflicts) = bus_with_conflicts(3);
+    bus.add_middleware(Retry::new(RetryPolicy::new(
+        3,
+        Backoff::Fixed(Duration::from_millis(0)),
+    )));
+
+    let got = bus.dispatch(TestCommand::Increase);
+
+    if let Err(DispatchError::ConcurrencyConflict { .. }) = got {
+    } else {
+        panic!();
+    }
+    assert_eq!(conflicts.get(), 0);
+}
+
+#[test]
+fn retry_does_not_retry_other_errors() {
+    let (mut bus, conflicts) = bus_with_conflicts(0);
+    let attempts = Rc::new(Cell::new(0));
+    let counter = attempts.clone();
+    bus.add_middleware(Retry::new(RetryPolicy::new(
+        3,
+        Backoff::Fixed(Duration::from_millis(0)),
+    )));
+    bus.add_middleware(Timing::new(move |_, _| counter.set(counter.get() + 1)));
+
+    assert!(bus.dispatch(TestCommand::Invalid).is_err());
+    assert_eq!(attempts.get(), 1);
+    assert_eq!(conflicts.get(), 0);
+}
+
+#[test]
+fn timing() {
+    let (mut bus, _) = bus_with_conflicts(0);
+    let reported = Rc::new(RefCell::new(Vec::new()));
+    let reports = reported.clone();
+    bus.add_middleware(Timing::new(move |context, _elapsed| {
+        reports.borrow_mut().push(context.aggregate_type)
+    }));
+
+    bus.dispatch(TestCommand::Increase).unwrap();
+
+    assert_eq!(*reported.borrow(), vec!["test"]);
+}
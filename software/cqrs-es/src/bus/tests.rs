@@ -0,0 +1,120 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bus::*;
+use crate::projector::Projector;
+use crate::store::*;
+use crate::tests::test_aggregate::*;
+use crate::tests::test_upcast_aggregate::*;
+use crate::Id;
+
+#[derive(Default)]
+struct IdsProjector(Vec<uuid::Uuid>);
+
+impl<A: crate::Aggregate> Projector<A> for IdsProjector {
+    fn project(&mut self, id: Id<A>, _event: &VersionedEvent<A>) {
+        self.0.push(*id.as_uuid())
+    }
+}
+
+#[test]
+fn dispatch() {
+    let projector = Rc::new(RefCell::new(IdsProjector::default()));
+    let test_id = Id::<TestAggregate>::new();
+    let upcast_id = Id::<UpcastAggregate>::new();
+    let mut bus = CommandBus::new();
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    storage.add_projector(projector.clone());
+    bus.register(storage, move |_| test_id);
+    let mut storage = InMemoryEventStorage::<UpcastAggregate>::new();
+    storage.add_projector(projector.clone());
+    bus.register(storage, move |_| upcast_id);
+
+    bus.dispatch(TestCommand::Increase).unwrap();
+    bus.dispatch(UpcastCommand).unwrap();
+
+    assert_eq!(
+        projector.borrow().0,
+        vec![*test_id.as_uuid(), *upcast_id.as_uuid()]
+    );
+}
+
+#[test]
+fn dispatch_not_registered() {
+    let mut bus = CommandBus::new();
+
+    let got = bus.dispatch(TestCommand::Increase);
+
+    if let Err(DispatchError::NotRegistered { command }) = got {
+        assert!(command.ends_with("TestCommand"));
+    } else {
+        panic!();
+    }
+}
+
+#[test]
+fn dispatch_command_error() {
+    let mut bus = CommandBus::new();
+    bus.register(InMemoryEventStorage::<TestAggregate>::new(), |_| Id::new());
+
+    let got = bus.dispatch(TestCommand::Invalid);
+
+    if let Err(DispatchError::Command(e)) = got {
+        assert_eq!(
+            e.downcast_ref::<TestCommandError>(),
+            Some(&TestCommandError::Invalid)
+        );
+    } else {
+        panic!();
+    }
+}
+
+struct Recording {
+    name: &'static str,
+    records: Rc<RefCell<Vec<String>>>,
+}
+
+impl Middleware for Recording {
+    fn handle(&self, context: &CommandContext, next: &mut Next) -> Result<(), DispatchError> {
+        self.records.borrow_mut().push(format!(
+            "{} before {}",
+            self.name,
+            context.metadata.actor.as_ref().unwrap()
+        ));
+        let context = context.with_metadata(context.metadata.clone().with_actor(self.name));
+        let result = next.run(&context);
+        self.records
+            .borrow_mut()
+            .push(format!("{} after", self.name));
+        result
+    }
+}
+
+#[test]
+fn dispatch_through_middlewares_in_added_order() {
+    let records = Rc::new(RefCell::new(Vec::new()));
+    let mut bus = CommandBus::new();
+    let id = Id::new();
+    bus.register(InMemoryEventStorage::<TestAggregate>::new(), move |_| id);
+    bus.add_middleware(Recording {
+        name: "outer",
+        records: records.clone(),
+    });
+    bus.add_middleware(Recording {
+        name: "inner",
+        records: records.clone(),
+    });
+
+    bus.dispatch_with_metadata(TestCommand::Increase, Metadata::new().with_actor("user"))
+        .unwrap();
+
+    assert_eq!(
+        *records.borrow(),
+        vec![
+            "outer before user",
+            "inner before outer",
+            "inner after",
+            "outer after",
+        ]
+    );
+}
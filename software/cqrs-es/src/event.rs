@@ -1,5 +1,6 @@
 use std::fmt::Debug;
 
+use crate::store::upcaster::{Upcasters, INITIAL_SCHEMA_VERSION};
 use crate::Aggregate;
 
 #[cfg(test)]
@@ -7,4 +8,16 @@ mod tests;
 
 pub trait Event<A: Aggregate>: Debug + Clone {
     fn apply_to(self, aggregate: &mut A);
+
+    /// シリアライズ形式のバージョン
+    ///
+    /// 保存済みのEventと互換性のない変更を加えたときに上げ、
+    /// 古いバージョンからの変換を `upcasters` に登録する
+    fn schema_version() -> u32 {
+        INITIAL_SCHEMA_VERSION
+    }
+
+    fn upcasters() -> Upcasters {
+        Upcasters::new()
+    }
 }
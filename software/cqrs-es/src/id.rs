@@ -1,26 +1,58 @@
-use std::fmt::{Debug, Error as FmtError, Formatter};
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display, Error as FmtError, Formatter};
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+use std::str::FromStr;
 
+use chrono::Utc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use uuid::Uuid;
 
-use crate::Aggregate;
+#[cfg(test)]
+mod tests;
 
-pub struct Id<A: Aggregate> {
+/// Aggregateのインスタンスを識別するID
+///
+/// Aggregate内のエンティティなど、Aggregate以外の型を `A` として、その値を識別するのにも使える。
+/// 文字列表現やシリアライズ形式は、内部のUUIDと同じ
+pub struct Id<A> {
     id: Uuid,
     phantom: PhantomData<A>,
 }
 
-impl<A: Aggregate> Id<A> {
+impl<A> Id<A> {
     pub fn new() -> Id<A> {
         Id {
             id: Uuid::new_v4(),
             phantom: PhantomData,
         }
     }
+
+    /// 生成した時刻の順に並ぶIDを払い出す
+    ///
+    /// UUIDv7と同じく、先頭48bitにUNIX時刻のミリ秒、残りに乱数を持つ。
+    /// 同じミリ秒内に生成したID同士の順序は保証しない
+    pub fn new_ordered() -> Id<A> {
+        let millis = Utc::now().timestamp_millis() as u64;
+        let mut bytes = *Uuid::new_v4().as_bytes();
+        bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+        bytes[6] = 0x70 | (bytes[6] & 0x0f);
+        bytes[8] = 0x80 | (bytes[8] & 0x3f);
+        Id::from(Uuid::from_bytes(bytes))
+    }
+
+    pub fn as_uuid(&self) -> &Uuid {
+        &self.id
+    }
 }
 
-impl<A: Aggregate> From<Uuid> for Id<A> {
+impl<A> From<Id<A>> for Uuid {
+    fn from(id: Id<A>) -> Self {
+        id.id
+    }
+}
+
+impl<A> From<Uuid> for Id<A> {
     fn from(id: Uuid) -> Self {
         Id {
             id,
@@ -29,25 +61,35 @@ impl<A: Aggregate> From<Uuid> for Id<A> {
     }
 }
 
-impl<A: Aggregate> PartialEq for Id<A> {
+impl<A> PartialEq for Id<A> {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
     }
 }
-impl<A: Aggregate> Eq for Id<A> {}
-impl<A: Aggregate> Hash for Id<A> {
+impl<A> Eq for Id<A> {}
+impl<A> PartialOrd for Id<A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<A> Ord for Id<A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+impl<A> Hash for Id<A> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.id.hash(state)
     }
 }
 
-impl<A: Aggregate> Debug for Id<A> {
+impl<A> Debug for Id<A> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
         write!(f, "{:?}", self.id)
     }
 }
 
-impl<A: Aggregate> Clone for Id<A> {
+impl<A> Clone for Id<A> {
     fn clone(&self) -> Self {
         Id {
             id: self.id,
@@ -55,10 +97,30 @@ impl<A: Aggregate> Clone for Id<A> {
         }
     }
 }
-impl<A: Aggregate> Copy for Id<A> {}
+impl<A> Copy for Id<A> {}
+
+impl<A> Display for Id<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        Display::fmt(&self.id, f)
+    }
+}
+
+impl<A> FromStr for Id<A> {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::parse_str(s).map(Id::from)
+    }
+}
+
+impl<A> Serialize for Id<A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id.serialize(serializer)
+    }
+}
 
-impl<A: Aggregate> ToString for Id<A> {
-    fn to_string(&self) -> String {
-        self.id.to_string()
+impl<'de, A> Deserialize<'de> for Id<A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Uuid::deserialize(deserializer).map(Id::from)
     }
 }
@@ -0,0 +1,64 @@
+use std::collections::BTreeSet;
+
+use uuid::Uuid;
+
+use crate::tests::test_aggregate::*;
+use crate::Id;
+
+const UUID: &str = "5d4ffb41-86f6-4a07-8e51-28b1e5b0e7ad";
+
+#[test]
+fn display_and_from_str() {
+    let id: Id<TestAggregate> = UUID.parse().unwrap();
+
+    assert_eq!(id.to_string(), UUID);
+    assert_eq!(format!("{}", id), UUID);
+    assert_eq!(Uuid::from(id), Uuid::parse_str(UUID).unwrap());
+}
+
+#[test]
+fn from_str_invalid() {
+    assert!("invalid".parse::<Id<TestAggregate>>().is_err());
+}
+
+#[test]
+fn serde() {
+    let id: Id<TestAggregate> = UUID.parse().unwrap();
+
+    let json = serde_json::to_string(&id).unwrap();
+    assert_eq!(json, format!("\"{}\"", UUID));
+
+    let got: Id<TestAggregate> = serde_json::from_str(&json).unwrap();
+    assert_eq!(got, id);
+}
+
+#[test]
+fn new_ordered() {
+    let id = Id::<TestAggregate>::new_ordered();
+
+    assert_eq!(id.as_uuid().get_version_num(), 7);
+    assert_eq!(id.as_uuid().get_variant(), Some(uuid::Variant::RFC4122));
+}
+
+#[test]
+fn new_ordered_sorts_by_creation_time() {
+    let ids: Vec<_> = (0..3)
+        .map(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            Id::<TestAggregate>::new_ordered()
+        })
+        .collect();
+
+    let sorted: Vec<_> = ids
+        .iter()
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    assert_eq!(sorted, ids);
+    let strings: BTreeSet<_> = ids.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        strings.into_iter().collect::<Vec<_>>(),
+        ids.iter().map(ToString::to_string).collect::<Vec<_>>()
+    );
+}
@@ -1,14 +1,32 @@
+#[cfg(feature = "shredding")]
+extern crate aes_gcm;
+#[cfg(feature = "shredding")]
+extern crate base64;
+extern crate chrono;
 extern crate failure;
+extern crate futures;
+#[cfg(feature = "shredding")]
+extern crate rand;
 extern crate serde;
+extern crate serde_json;
 extern crate uuid;
 
 #[cfg(test)]
-extern crate serde_json;
+extern crate bincode;
+#[cfg(test)]
+extern crate rmp_serde;
+#[cfg(test)]
+extern crate serde_cbor;
 #[cfg(test)]
 extern crate simulacrum;
 
+pub mod bus;
 pub mod projector;
+pub mod saga;
+#[cfg(feature = "shredding")]
+pub mod shredding;
 pub mod store;
+pub mod testing;
 
 pub mod aggregate;
 pub use aggregate::Aggregate;
@@ -22,5 +40,8 @@ pub use event::Event;
 pub mod command;
 pub use command::{Command, CommandError};
 
+#[cfg(feature = "derive")]
+pub use cqrs_es_derive::{Aggregate, Command, Event};
+
 #[cfg(test)]
 mod tests;
@@ -1,6 +1,24 @@
-//use crate::version::*;
-//use crate::*;
-//
-//pub trait Projector<A: Aggregate> {
-//    fn project(&mut self, id: Id<A>, event: &VersionedEvent<A>);
-//}
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::store::VersionedEvent;
+use crate::{Aggregate, Id};
+
+#[cfg(test)]
+mod tests;
+
+/// 保存に成功したEventを受け取り、Read Modelを更新する
+pub trait Projector<A: Aggregate> {
+    fn project(&mut self, id: Id<A>, event: &VersionedEvent<A>);
+}
+
+/// EventStorageに登録した後も、呼び出し側からRead Modelを参照できるようにする
+impl<A, P> Projector<A> for Rc<RefCell<P>>
+where
+    A: Aggregate,
+    P: Projector<A>,
+{
+    fn project(&mut self, id: Id<A>, event: &VersionedEvent<A>) {
+        self.borrow_mut().project(id, event)
+    }
+}
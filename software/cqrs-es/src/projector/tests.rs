@@ -0,0 +1,87 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use failure::Fail;
+
+use crate::projector::Projector;
+use crate::store::*;
+use crate::tests::test_aggregate::*;
+use crate::Id;
+
+#[derive(Fail, Debug, Eq, PartialEq)]
+#[fail(display = "unexpected")]
+pub struct VecStorageError {}
+
+impl EventStorageError for VecStorageError {}
+
+struct VecStorage {
+    events: Vec<VersionedEvent<TestAggregate>>,
+    projectors: Vec<Box<dyn Projector<TestAggregate>>>,
+}
+
+impl EventStorage<TestAggregate> for VecStorage {
+    type Events = Vec<VersionedEvent<TestAggregate>>;
+    type Error = VecStorageError;
+
+    fn insert(
+        &mut self,
+        _id: Id<TestAggregate>,
+        event: VersionedEvent<TestAggregate>,
+    ) -> Result<(), Self::Error> {
+        self.events.push(event);
+        Ok(())
+    }
+
+    fn read(&self, _id: Id<TestAggregate>) -> Result<Self::Events, Self::Error> {
+        Ok(self.events.clone())
+    }
+
+    fn list_ids(&self, _paging: Paging) -> Result<Vec<StreamInfo<TestAggregate>>, Self::Error> {
+        unimplemented!()
+    }
+
+    fn projectors(&mut self) -> &mut [Box<dyn Projector<TestAggregate>>] {
+        &mut self.projectors
+    }
+}
+
+#[derive(Default)]
+struct VersionsProjector(Vec<(Id<TestAggregate>, Version)>);
+
+impl Projector<TestAggregate> for VersionsProjector {
+    fn project(&mut self, id: Id<TestAggregate>, event: &VersionedEvent<TestAggregate>) {
+        self.0.push((id, event.version))
+    }
+}
+
+#[test]
+fn execute_command_projects_inserted_events() {
+    let projector = Rc::new(RefCell::new(VersionsProjector::default()));
+    let mut storage = VecStorage {
+        events: Vec::new(),
+        projectors: vec![Box::new(projector.clone())],
+    };
+
+    let id = Id::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    assert_eq!(
+        projector.borrow().0,
+        vec![(id, Version(1)), (id, Version(2))]
+    );
+}
+
+#[test]
+fn execute_command_does_not_project_on_command_error() {
+    let projector = Rc::new(RefCell::new(VersionsProjector::default()));
+    let mut storage = VecStorage {
+        events: Vec::new(),
+        projectors: vec![Box::new(projector.clone())],
+    };
+
+    let id = Id::new();
+    assert!(storage.execute_command(id, TestCommand::Invalid).is_err());
+
+    assert!(projector.borrow().0.is_empty());
+}
@@ -0,0 +1,240 @@
+use std::convert::Infallible;
+use std::marker::PhantomData;
+
+use failure::Fail;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{CommandBus, DispatchError};
+use crate::store::event_log::{EventLog, Position};
+use crate::store::{EventStorageError, Metadata, VersionedEvent};
+use crate::{Aggregate, Id};
+
+#[cfg(test)]
+mod tests;
+
+/// あるAggregateのEventに反応して、別のAggregateにコマンドを発行するプロセスマネージャー
+///
+/// 状態は処理済みのEventの位置とともに `SagaStore` に保存され、次回の実行に引き継がれる
+pub trait Saga: Default + Serialize + DeserializeOwned {
+    /// 反応するEventのAggregate
+    type Source: Aggregate;
+    /// コマンドを発行する先のAggregate
+    type Target: Aggregate;
+
+    /// 状態の保存先やコマンドの `idempotency_key` で、Sagaを区別する名前
+    fn type_name() -> &'static str;
+
+    /// Eventを受け取って状態を更新し、発行するコマンドを返す
+    fn handle(
+        &mut self,
+        id: Id<Self::Source>,
+        event: &VersionedEvent<Self::Source>,
+    ) -> Vec<Step<Self>>;
+
+    /// Stepのコマンドが失敗したときに、補償コマンドを発行する前に呼ばれる
+    fn failed(&mut self, _step: &Step<Self>, _error: &DispatchError) {}
+}
+
+/// Sagaが `Target` に発行するコマンドと、それが失敗したときに `Source` に発行する補償コマンド
+pub struct Step<S: Saga> {
+    pub command: <S::Target as Aggregate>::Command,
+    pub compensation: Option<<S::Source as Aggregate>::Command>,
+}
+
+/// 保存されるSagaの状態
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SagaState<S> {
+    /// 最後に処理したEventの位置
+    pub position: Position,
+    pub saga: S,
+}
+
+/// Sagaの状態の保存先
+pub trait SagaStore<S: Saga> {
+    type Error: EventStorageError;
+
+    fn load(&self) -> Result<Option<SagaState<S>>, Self::Error>;
+
+    fn save(&mut self, state: &SagaState<S>) -> Result<(), Self::Error>;
+}
+
+/// Sagaの状態をメモリ上に保持する
+#[derive(Default)]
+pub struct InMemorySagaStore<S> {
+    state: Option<SagaState<S>>,
+}
+
+impl<S> InMemorySagaStore<S> {
+    pub fn new() -> Self {
+        InMemorySagaStore { state: None }
+    }
+}
+
+/// メモリ上のSagaの状態の読み書きは失敗しないため、値を作れない
+#[derive(Fail, Debug, Eq, PartialEq)]
+#[fail(display = "Unreachable")]
+pub struct InMemorySagaStoreError(Infallible);
+
+impl EventStorageError for InMemorySagaStoreError {}
+
+impl<S: Saga + Clone> SagaStore<S> for InMemorySagaStore<S> {
+    type Error = InMemorySagaStoreError;
+
+    fn load(&self) -> Result<Option<SagaState<S>>, Self::Error> {
+        Ok(self.state.clone())
+    }
+
+    fn save(&mut self, state: &SagaState<S>) -> Result<(), Self::Error> {
+        self.state = Some(state.clone());
+        Ok(())
+    }
+}
+
+/// 全ストリームのログを読み、Sagaを進める
+pub struct SagaRunner<S, L, T> {
+    log: L,
+    store: T,
+    phantom: PhantomData<fn() -> S>,
+}
+
+impl<S, L, T> SagaRunner<S, L, T>
+where
+    S: Saga,
+    <S::Source as Aggregate>::Event: DeserializeOwned,
+    <S::Source as Aggregate>::Command: Clone + 'static,
+    <S::Target as Aggregate>::Command: Clone + 'static,
+    L: EventLog,
+    T: SagaStore<S>,
+{
+    pub fn new(log: L, store: T) -> Self {
+        SagaRunner {
+            log,
+            store,
+            phantom: PhantomData,
+        }
+    }
+
+    /// 状態が保存されていなければ、記録済みのEventは処理せず、以降に記録されるEventから処理するよう状態を保存する
+    ///
+    /// 既存のEventに対してコマンドを発行せずに、Sagaを導入する場合に使う
+    pub fn start_from_latest(&mut self) -> Result<(), SagaError> {
+        if self
+            .store
+            .load()
+            .map_err(|e| SagaError::Store(e.into()))?
+            .is_some()
+        {
+            return Ok(());
+        }
+        let position = self
+            .log
+            .read_all(Position::default())
+            .map_err(|e| SagaError::EventLog(e.into()))?
+            .into_iter()
+            .last()
+            .map(|e| e.position)
+            .unwrap_or_default();
+        let state = SagaState {
+            position,
+            saga: S::default(),
+        };
+        self.store
+            .save(&state)
+            .map_err(|e| SagaError::Store(e.into()))
+    }
+
+    /// 前回処理した位置より後に記録されたEventを処理し、 `Source` のEventの件数を返す
+    ///
+    /// 状態はEventごとに保存する。
+    /// コマンドにはEventの位置から決まる `idempotency_key` を付与するため、
+    /// 状態の保存前に中断して同じEventを再度処理しても、同じコマンドは二重に記録されない
+    pub fn run(&mut self, bus: &mut CommandBus) -> Result<usize, SagaError> {
+        let mut state = self
+            .store
+            .load()
+            .map_err(|e| SagaError::Store(e.into()))?
+            .unwrap_or_default();
+        let events = self
+            .log
+            .read_all(state.position.next())
+            .map_err(|e| SagaError::EventLog(e.into()))?;
+
+        let mut handled = 0;
+        for recorded in events {
+            if let Some((id, event)) = recorded.decode::<S::Source, _>()? {
+                let steps = state.saga.handle(id, &event);
+                for (i, step) in steps.into_iter().enumerate() {
+                    let key = format!("{}/{}/{}", S::type_name(), recorded.position.0, i);
+                    execute(&mut state.saga, bus, step, &event.metadata, key)?;
+                }
+                handled += 1;
+            }
+            state.position = recorded.position;
+            self.store
+                .save(&state)
+                .map_err(|e| SagaError::Store(e.into()))?;
+        }
+        Ok(handled)
+    }
+}
+
+/// Stepのコマンドを発行し、コマンドが失敗した場合は補償コマンドを発行する
+///
+/// 発端となったEventから辿れるよう、コマンドにはEventの `correlation_id` を引き継ぎ、
+/// `causation_id` にも同じIDを付与する。
+/// 保存や競合など、コマンドの失敗以外の理由で実行できなかった場合は補償せずにエラーを返す。
+/// 失敗したコマンドは記録されないため、補償後に中断して再度処理した場合はコマンドが再び実行される
+fn execute<S>(
+    saga: &mut S,
+    bus: &mut CommandBus,
+    step: Step<S>,
+    source: &Metadata,
+    key: String,
+) -> Result<(), SagaError>
+where
+    S: Saga,
+    <S::Source as Aggregate>::Command: Clone + 'static,
+    <S::Target as Aggregate>::Command: Clone + 'static,
+{
+    let metadata = Metadata {
+        correlation_id: source.correlation_id,
+        causation_id: source.correlation_id,
+        ..Metadata::new()
+    };
+    let command_metadata = metadata.clone().with_idempotency_key(key.as_str());
+    match bus.dispatch_with_metadata(step.command.clone(), command_metadata) {
+        Ok(()) => Ok(()),
+        Err(e @ DispatchError::Command(_)) | Err(e @ DispatchError::Rejected { .. }) => {
+            saga.failed(&step, &e);
+            if let Some(compensation) = step.compensation {
+                let metadata = metadata.with_idempotency_key(format!("{}/compensation", key));
+                bus.dispatch_with_metadata(compensation, metadata)
+                    .map_err(SagaError::Compensation)?;
+            }
+            Ok(())
+        }
+        Err(e) => Err(SagaError::Dispatch(e)),
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum SagaError {
+    #[fail(display = "EventLog error: {}", _0)]
+    EventLog(failure::Error),
+    #[fail(display = "Decode error: {}", _0)]
+    Decode(#[fail(cause)] serde_json::Error),
+    #[fail(display = "SagaStore error: {}", _0)]
+    Store(failure::Error),
+    /// コマンドの失敗以外の理由で、コマンドを実行できなかった
+    #[fail(display = "Dispatch error: {}", _0)]
+    Dispatch(#[fail(cause)] DispatchError),
+    #[fail(display = "Compensation error: {}", _0)]
+    Compensation(#[fail(cause)] DispatchError),
+}
+
+impl From<serde_json::Error> for SagaError {
+    fn from(e: serde_json::Error) -> Self {
+        SagaError::Decode(e)
+    }
+}
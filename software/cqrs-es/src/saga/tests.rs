@@ -0,0 +1,271 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{CommandBus, DispatchError};
+use crate::projector::Projector;
+use crate::saga::*;
+use crate::store::event_log::{EventLog, Position, RecordedEvent};
+use crate::store::memory::InMemoryEventStorageError;
+use crate::store::*;
+use crate::tests::test_aggregate::*;
+use crate::tests::test_upcast_aggregate::*;
+use crate::Id;
+
+/// `UpcastAggregate` のEventの `amount` が偶数なら失敗するコマンドを、 `TestAggregate` に発行する
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct TestSaga {
+    handled: u64,
+    failed: u64,
+}
+
+impl Saga for TestSaga {
+    type Source = UpcastAggregate;
+    type Target = TestAggregate;
+
+    fn type_name() -> &'static str {
+        "test_saga"
+    }
+
+    fn handle(
+        &mut self,
+        _id: Id<UpcastAggregate>,
+        event: &VersionedEvent<UpcastAggregate>,
+    ) -> Vec<Step<Self>> {
+        self.handled += 1;
+        let UpcastEvent::Increased { amount } = event.event;
+        let command = if amount % 2 == 0 {
+            TestCommand::Invalid
+        } else {
+            TestCommand::Increase
+        };
+        vec![Step {
+            command,
+            compensation: Some(UpcastCommand),
+        }]
+    }
+
+    fn failed(&mut self, _step: &Step<Self>, _error: &DispatchError) {
+        self.failed += 1;
+    }
+}
+
+struct VecEventLog(Vec<RecordedEvent>);
+
+impl EventLog for VecEventLog {
+    type Events = Vec<RecordedEvent>;
+    type Error = InMemoryEventStorageError;
+
+    fn read_all(&self, from: Position) -> Result<Self::Events, Self::Error> {
+        Ok(self
+            .0
+            .iter()
+            .filter(|e| e.position >= from)
+            .cloned()
+            .collect())
+    }
+}
+
+/// 保存されたEventの件数を数える
+#[derive(Default)]
+struct Counter(usize);
+
+impl<A: crate::Aggregate> Projector<A> for Counter {
+    fn project(&mut self, _id: Id<A>, _event: &VersionedEvent<A>) {
+        self.0 += 1;
+    }
+}
+
+fn log(amounts: &[u64]) -> VecEventLog {
+    let source_id = Id::<UpcastAggregate>::new();
+    let other_id = Id::<TestAggregate>::new();
+    let mut events = Vec::new();
+    amounts.iter().enumerate().for_each(|(i, amount)| {
+        let event = VersionedEvent {
+            version: Version(i as u64 + 1),
+            event: UpcastEvent::Increased { amount: *amount },
+            metadata: Metadata::new(),
+        };
+        let position = Position(events.len() as u64 + 1);
+        events.push(RecordedEvent::new(position, source_id, &event).unwrap());
+
+        let event = VersionedEvent {
+            version: Version(i as u64 + 1),
+            event: TestEvent::Increased,
+            metadata: Metadata::new(),
+        };
+        let position = Position(events.len() as u64 + 1);
+        events.push(RecordedEvent::new(position, other_id, &event).unwrap());
+    });
+    VecEventLog(events)
+}
+
+type Counters = (Rc<RefCell<Counter>>, Rc<RefCell<Counter>>);
+
+/// `TestAggregate` と `UpcastAggregate` を登録したCommandBusと、それぞれに保存されたEventの件数
+fn bus() -> (CommandBus, Counters) {
+    let target = Rc::new(RefCell::new(Counter::default()));
+    let source = Rc::new(RefCell::new(Counter::default()));
+
+    let mut target_storage = InMemoryEventStorage::<TestAggregate>::new();
+    target_storage.add_projector(target.clone());
+    let mut source_storage = InMemoryEventStorage::<UpcastAggregate>::new();
+    source_storage.add_projector(source.clone());
+
+    let target_id = Id::new();
+    let source_id = Id::new();
+    let mut bus = CommandBus::new();
+    bus.register(target_storage, move |_| target_id);
+    bus.register(source_storage, move |_| source_id);
+    (bus, (target, source))
+}
+
+#[test]
+fn run() {
+    let (mut bus, (target, source)) = bus();
+    let mut runner = SagaRunner::new(log(&[1, 3]), InMemorySagaStore::<TestSaga>::new());
+
+    let got = runner.run(&mut bus).unwrap();
+
+    assert_eq!(got, 2);
+    assert_eq!(target.borrow().0, 2);
+    assert_eq!(source.borrow().0, 0);
+    let state = runner.store.load().unwrap().unwrap();
+    assert_eq!(state.position, Position(4));
+    assert_eq!(state.saga.handled, 2);
+    assert_eq!(state.saga.failed, 0);
+}
+
+#[test]
+fn run_from_saved_position() {
+    let (mut bus, (target, _)) = bus();
+    let mut runner = SagaRunner::new(log(&[1, 3]), InMemorySagaStore::<TestSaga>::new());
+    runner.run(&mut bus).unwrap();
+
+    let got = runner.run(&mut bus).unwrap();
+
+    assert_eq!(got, 0);
+    assert_eq!(target.borrow().0, 2);
+    assert_eq!(runner.store.load().unwrap().unwrap().saga.handled, 2);
+}
+
+#[test]
+fn run_compensates_failed_command() {
+    let (mut bus, (target, source)) = bus();
+    let mut runner = SagaRunner::new(log(&[1, 2]), InMemorySagaStore::<TestSaga>::new());
+
+    let got = runner.run(&mut bus).unwrap();
+
+    assert_eq!(got, 2);
+    assert_eq!(target.borrow().0, 1);
+    assert_eq!(source.borrow().0, 1);
+    assert_eq!(runner.store.load().unwrap().unwrap().saga.failed, 1);
+}
+
+/// 保存されたEventのMetadataを記録する
+#[derive(Default)]
+struct MetadataRecorder(Vec<Metadata>);
+
+impl<A: crate::Aggregate> Projector<A> for MetadataRecorder {
+    fn project(&mut self, _id: Id<A>, event: &VersionedEvent<A>) {
+        self.0.push(event.metadata.clone());
+    }
+}
+
+#[test]
+fn run_propagates_correlation_id() {
+    let correlation_id = uuid::Uuid::new_v4();
+    let source_id = Id::<UpcastAggregate>::new();
+    let events = [1, 2]
+        .iter()
+        .enumerate()
+        .map(|(i, amount)| {
+            let event = VersionedEvent {
+                version: Version(i as u64 + 1),
+                event: UpcastEvent::Increased { amount: *amount },
+                metadata: Metadata::new().with_correlation_id(correlation_id),
+            };
+            RecordedEvent::new(Position(i as u64 + 1), source_id, &event).unwrap()
+        })
+        .collect();
+    let target = Rc::new(RefCell::new(MetadataRecorder::default()));
+    let source = Rc::new(RefCell::new(MetadataRecorder::default()));
+    let mut target_storage = InMemoryEventStorage::<TestAggregate>::new();
+    target_storage.add_projector(target.clone());
+    let mut source_storage = InMemoryEventStorage::<UpcastAggregate>::new();
+    source_storage.add_projector(source.clone());
+    let mut bus = CommandBus::new();
+    bus.register(target_storage, |_| Id::new());
+    bus.register(source_storage, move |_| source_id);
+    let mut runner = SagaRunner::new(VecEventLog(events), InMemorySagaStore::<TestSaga>::new());
+
+    runner.run(&mut bus).unwrap();
+
+    // コマンドと補償コマンドのどちらにも引き継ぐ
+    let recorded: Vec<_> = target
+        .borrow()
+        .0
+        .iter()
+        .chain(&source.borrow().0)
+        .cloned()
+        .collect();
+    assert_eq!(recorded.len(), 2);
+    assert!(recorded.iter().all(
+        |m| m.correlation_id == Some(correlation_id) && m.causation_id == Some(correlation_id)
+    ));
+}
+
+#[test]
+fn rerun_does_not_duplicate_commands() {
+    let (mut bus, (target, source)) = bus();
+    let mut runner = SagaRunner::new(log(&[1, 2]), InMemorySagaStore::<TestSaga>::new());
+    runner.run(&mut bus).unwrap();
+
+    // 状態を保存できずに中断した場合を再現する
+    let mut runner = SagaRunner::new(runner.log, InMemorySagaStore::<TestSaga>::new());
+    runner.run(&mut bus).unwrap();
+
+    assert_eq!(target.borrow().0, 1);
+    assert_eq!(source.borrow().0, 1);
+}
+
+#[test]
+fn start_from_latest() {
+    let (mut bus, (target, _)) = bus();
+    let mut runner = SagaRunner::new(log(&[1, 3]), InMemorySagaStore::<TestSaga>::new());
+
+    runner.start_from_latest().unwrap();
+    let got = runner.run(&mut bus).unwrap();
+
+    assert_eq!(got, 0);
+    assert_eq!(target.borrow().0, 0);
+    assert_eq!(runner.store.load().unwrap().unwrap().position, Position(4));
+}
+
+#[test]
+fn start_from_latest_keeps_saved_state() {
+    let (mut bus, _) = bus();
+    let mut store = InMemorySagaStore::<TestSaga>::new();
+    store.save(&SagaState::default()).unwrap();
+    let mut runner = SagaRunner::new(log(&[1, 3]), store);
+
+    runner.start_from_latest().unwrap();
+    let got = runner.run(&mut bus).unwrap();
+
+    assert_eq!(got, 2);
+}
+
+#[test]
+fn run_not_registered() {
+    let mut bus = CommandBus::new();
+    let mut runner = SagaRunner::new(log(&[1]), InMemorySagaStore::<TestSaga>::new());
+
+    let got = runner.run(&mut bus);
+
+    if let Err(SagaError::Dispatch(DispatchError::NotRegistered { .. })) = got {
+    } else {
+        panic!();
+    }
+    assert!(runner.store.load().unwrap().is_none());
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::convert::{Infallible, TryFrom};
+use std::fmt::{Debug, Display, Error as FmtError, Formatter};
+use std::marker::PhantomData;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use failure::Fail;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::store::EventStorageError;
+
+#[cfg(test)]
+mod tests;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// 個人データの持ち主 (主体) を表すID
+///
+/// 主体ごとに暗号鍵を作り、鍵を削除することでその主体の個人データを読めなくする
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct SubjectId(pub String);
+
+impl Display for SubjectId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// 主体ごとの暗号鍵
+///
+/// 鍵を削除した後に同じ主体の鍵を作り直しても、 `id` が異なるため以前のデータは読めないままとなる
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SubjectKey {
+    pub id: Uuid,
+    #[serde(with = "base64_bytes")]
+    key: Vec<u8>,
+}
+
+impl SubjectKey {
+    pub fn generate() -> SubjectKey {
+        SubjectKey {
+            id: Uuid::new_v4(),
+            key: rand::random::<[u8; KEY_LEN]>().to_vec(),
+        }
+    }
+
+    /// 保存されていた鍵の長さが不正な場合は `None`
+    fn cipher(&self) -> Option<Aes256Gcm> {
+        let key = <[u8; KEY_LEN]>::try_from(&self.key[..]).ok()?;
+        Some(Aes256Gcm::new(&key.into()))
+    }
+}
+
+/// ログなどに鍵が出力されないよう、IDのみ表示する
+impl Debug for SubjectKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        f.debug_struct("SubjectKey").field("id", &self.id).finish()
+    }
+}
+
+/// 主体ごとの暗号鍵を保存する
+pub trait KeyStore {
+    type Error: EventStorageError;
+
+    fn load(&self, subject: &SubjectId) -> Result<Option<SubjectKey>, Self::Error>;
+
+    fn save(&mut self, subject: &SubjectId, key: &SubjectKey) -> Result<(), Self::Error>;
+
+    /// 鍵を削除すると、その主体の `PersonalData` は復号できなくなる
+    ///
+    /// 保存されていない場合も成功とする
+    fn delete(&mut self, subject: &SubjectId) -> Result<(), Self::Error>;
+
+    /// 鍵が保存されていない場合のみ保存し、保存したかどうかを返す
+    ///
+    /// 複数のプロセスから共有するKeyStoreでは、確認と保存を不可分に行うよう上書きする
+    fn save_if_absent(
+        &mut self,
+        subject: &SubjectId,
+        key: &SubjectKey,
+    ) -> Result<bool, Self::Error> {
+        if self.load(subject)?.is_some() {
+            return Ok(false);
+        }
+        self.save(subject, key)?;
+        Ok(true)
+    }
+
+    /// 保存されている鍵を返す。なければ新しく作って保存する
+    ///
+    /// 他が先に鍵を保存していた場合は、その鍵を読み直して返す
+    fn load_or_generate(&mut self, subject: &SubjectId) -> Result<SubjectKey, Self::Error> {
+        loop {
+            if let Some(key) = self.load(subject)? {
+                return Ok(key);
+            }
+            let key = SubjectKey::generate();
+            if self.save_if_absent(subject, &key)? {
+                return Ok(key);
+            }
+        }
+    }
+}
+
+/// 暗号鍵をメモリ上に保持する
+#[derive(Default)]
+pub struct InMemoryKeyStore {
+    keys: HashMap<SubjectId, SubjectKey>,
+}
+
+impl InMemoryKeyStore {
+    pub fn new() -> Self {
+        InMemoryKeyStore::default()
+    }
+}
+
+/// メモリ上の暗号鍵の読み書きは失敗しないため、値を作れない
+#[derive(Fail, Debug, Eq, PartialEq)]
+#[fail(display = "Unreachable")]
+pub struct InMemoryKeyStoreError(Infallible);
+
+impl EventStorageError for InMemoryKeyStoreError {}
+
+impl KeyStore for InMemoryKeyStore {
+    type Error = InMemoryKeyStoreError;
+
+    fn load(&self, subject: &SubjectId) -> Result<Option<SubjectKey>, Self::Error> {
+        Ok(self.keys.get(subject).cloned())
+    }
+
+    fn save(&mut self, subject: &SubjectId, key: &SubjectKey) -> Result<(), Self::Error> {
+        self.keys.insert(subject.clone(), key.clone());
+        Ok(())
+    }
+
+    fn delete(&mut self, subject: &SubjectId) -> Result<(), Self::Error> {
+        self.keys.remove(subject);
+        Ok(())
+    }
+}
+
+/// Eventに含める個人データ
+///
+/// 値は主体の鍵で暗号化した状態で保持・シリアライズされるため、
+/// 鍵を削除した後もEventのデシリアライズやAggregateの再構築はそのまま行える。
+/// 値を読むときは `reveal` で復号する
+///
+/// 主体IDは鍵を引くために平文のままシリアライズされ、鍵を削除した後も残る。
+/// そのため、メールアドレスなどの個人データそのものを主体IDにせず、
+/// 個人データを含まない利用者IDなどを使う
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PersonalData<T> {
+    subject: SubjectId,
+    key_id: Uuid,
+    #[serde(with = "base64_bytes")]
+    nonce: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    ciphertext: Vec<u8>,
+    #[serde(skip)]
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> PersonalData<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// `subject` の鍵で `value` を暗号化する。鍵がなければ新しく作る
+    pub fn encrypt<K>(
+        subject: SubjectId,
+        value: &T,
+        key_store: &mut K,
+    ) -> Result<PersonalData<T>, ShreddingError<K::Error>>
+    where
+        K: KeyStore,
+    {
+        let key = key_store
+            .load_or_generate(&subject)
+            .map_err(ShreddingError::KeyStore)?;
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let plaintext = serde_json::to_vec(value)?;
+        let payload = Payload {
+            msg: &plaintext,
+            aad: subject.0.as_bytes(),
+        };
+        let ciphertext = key
+            .cipher()
+            .ok_or(ShreddingError::Crypto)?
+            .encrypt(&nonce.into(), payload)
+            .map_err(|_| ShreddingError::Crypto)?;
+        Ok(PersonalData {
+            subject,
+            key_id: key.id,
+            nonce: nonce.to_vec(),
+            ciphertext,
+            phantom: PhantomData,
+        })
+    }
+
+    /// 主体の鍵が削除されている場合は `Revealed::Redacted` を返す
+    pub fn reveal<K>(&self, key_store: &K) -> Result<Revealed<T>, ShreddingError<K::Error>>
+    where
+        K: KeyStore,
+    {
+        let key = match key_store
+            .load(&self.subject)
+            .map_err(ShreddingError::KeyStore)?
+        {
+            Some(key) if key.id == self.key_id => key,
+            _ => return Ok(Revealed::Redacted),
+        };
+        let nonce =
+            <[u8; NONCE_LEN]>::try_from(&self.nonce[..]).map_err(|_| ShreddingError::Crypto)?;
+        let payload = Payload {
+            msg: &self.ciphertext,
+            aad: self.subject.0.as_bytes(),
+        };
+        let plaintext = key
+            .cipher()
+            .ok_or(ShreddingError::Crypto)?
+            .decrypt(&nonce.into(), payload)
+            .map_err(|_| ShreddingError::Crypto)?;
+        Ok(Revealed::Value(serde_json::from_slice(&plaintext)?))
+    }
+}
+
+impl<T> PersonalData<T> {
+    pub fn subject(&self) -> &SubjectId {
+        &self.subject
+    }
+}
+
+/// `PersonalData::reveal` の結果
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Revealed<T> {
+    Value(T),
+    /// 主体の鍵が削除され、読めなくなった
+    Redacted,
+}
+
+impl<T> Revealed<T> {
+    pub fn value(self) -> Option<T> {
+        match self {
+            Revealed::Value(value) => Some(value),
+            Revealed::Redacted => None,
+        }
+    }
+}
+
+/// 読めなくなった値は `[redacted]` と表示する
+impl<T: Display> Display for Revealed<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        match self {
+            Revealed::Value(value) => write!(f, "{}", value),
+            Revealed::Redacted => write!(f, "[redacted]"),
+        }
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum ShreddingError<E: EventStorageError> {
+    #[fail(display = "KeyStore error: {}", _0)]
+    KeyStore(#[fail(cause)] E),
+    /// 暗号化・復号に失敗した。データが改竄されている可能性がある
+    #[fail(display = "Crypto error")]
+    Crypto,
+    #[fail(display = "JSON error: {}", _0)]
+    Json(#[fail(cause)] serde_json::Error),
+}
+
+impl<E: EventStorageError> From<serde_json::Error> for ShreddingError<E> {
+    fn from(e: serde_json::Error) -> Self {
+        ShreddingError::Json(e)
+    }
+}
+
+/// バイト列をBase64文字列としてシリアライズする
+mod base64_bytes {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        base64::decode(&s).map_err(D::Error::custom)
+    }
+}
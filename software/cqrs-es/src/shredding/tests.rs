@@ -0,0 +1,79 @@
+use crate::shredding::*;
+
+fn subject() -> SubjectId {
+    SubjectId("nisshiee".to_owned())
+}
+
+#[test]
+fn reveal() {
+    let mut key_store = InMemoryKeyStore::new();
+    let data = PersonalData::encrypt(subject(), &"Hiro".to_owned(), &mut key_store).unwrap();
+
+    let got = data.reveal(&key_store).unwrap();
+
+    assert_eq!(got, Revealed::Value("Hiro".to_owned()));
+    assert_eq!(data.subject(), &subject());
+}
+
+#[test]
+fn serialized_without_plaintext() {
+    let mut key_store = InMemoryKeyStore::new();
+    let data = PersonalData::encrypt(subject(), &"Hiro".to_owned(), &mut key_store).unwrap();
+
+    let json = serde_json::to_string(&data).unwrap();
+    assert!(!json.contains("Hiro"));
+
+    let data: PersonalData<String> = serde_json::from_str(&json).unwrap();
+    let got = data.reveal(&key_store).unwrap();
+    assert_eq!(got, Revealed::Value("Hiro".to_owned()));
+}
+
+#[test]
+fn reveal_after_key_deleted() {
+    let mut key_store = InMemoryKeyStore::new();
+    let data = PersonalData::encrypt(subject(), &"Hiro".to_owned(), &mut key_store).unwrap();
+    let other = PersonalData::encrypt(
+        SubjectId("other".to_owned()),
+        &"Other".to_owned(),
+        &mut key_store,
+    )
+    .unwrap();
+
+    key_store.delete(&subject()).unwrap();
+
+    let got = data.reveal(&key_store).unwrap();
+    assert_eq!(got, Revealed::Redacted);
+    assert_eq!(got.to_string(), "[redacted]");
+    let got = other.reveal(&key_store).unwrap();
+    assert_eq!(got, Revealed::Value("Other".to_owned()));
+
+    // 鍵を作り直しても、以前のデータは読めない
+    let data2 = PersonalData::encrypt(subject(), &"Hiro".to_owned(), &mut key_store).unwrap();
+    assert_eq!(data.reveal(&key_store).unwrap(), Revealed::Redacted);
+    assert_eq!(
+        data2.reveal(&key_store).unwrap(),
+        Revealed::Value("Hiro".to_owned())
+    );
+}
+
+#[test]
+fn reveal_tampered() {
+    let mut key_store = InMemoryKeyStore::new();
+    let data = PersonalData::encrypt(subject(), &"Hiro".to_owned(), &mut key_store).unwrap();
+    let mut json = serde_json::to_value(&data).unwrap();
+    json["subject"] = serde_json::json!("other");
+    key_store
+        .save(
+            &SubjectId("other".to_owned()),
+            &key_store.load(&subject()).unwrap().unwrap(),
+        )
+        .unwrap();
+
+    let data: PersonalData<String> = serde_json::from_value(json).unwrap();
+    let got = data.reveal(&key_store);
+
+    match got {
+        Err(ShreddingError::Crypto) => {}
+        _ => panic!(),
+    }
+}
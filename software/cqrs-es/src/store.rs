@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
 use failure::Fail;
 
+use crate::projector::Projector;
 use crate::{Aggregate, Command, CommandError, Event, Id};
 
 pub mod version;
@@ -11,6 +13,32 @@ pub use versioned_aggregate::VersionedAggregate;
 pub mod versioned_event;
 pub use versioned_event::VersionedEvent;
 
+pub mod metadata;
+pub use metadata::Metadata;
+
+pub mod snapshot;
+use snapshot::SnapshotPolicy;
+
+pub mod upcaster;
+
+pub mod retry;
+pub use retry::RetryPolicy;
+
+pub mod event_log;
+pub use event_log::{EventLog, Position, RecordedEvent};
+
+pub mod stream_info;
+pub use stream_info::{Paging, StreamInfo};
+
+pub mod async_storage;
+pub use async_storage::{AsyncAdapter, AsyncAdapterError, AsyncEventStorage, BlockingEventStorage};
+
+pub mod memory;
+pub use memory::InMemoryEventStorage;
+
+pub mod tombstone;
+pub use tombstone::{DeletableEventStorage, DeleteMode};
+
 mod serde;
 
 #[cfg(test)]
@@ -20,26 +48,133 @@ pub trait EventStorage<A: Aggregate> {
     type Events: IntoIterator<Item = VersionedEvent<A>>;
     type Error: EventStorageError;
 
+    /// バージョンを確認せずにEventを追加する
+    ///
+    /// 他の書き込みと競合しうるため、通常は `append` を使う
     fn insert(&mut self, id: Id<A>, event: VersionedEvent<A>) -> Result<(), Self::Error>;
 
     fn read(&self, id: Id<A>) -> Result<Self::Events, Self::Error>;
 
+    /// `from` 以降 ( `from` を含む) のバージョンのEventを返す
+    ///
+    /// スナップショットより後のEventだけを読むために使う。
+    /// デフォルト実装はストリーム全体を読み込むため、途中から読める実装ではオーバーライドする
+    fn read_from(&self, id: Id<A>, from: Version) -> Result<Vec<VersionedEvent<A>>, Self::Error> {
+        Ok(self
+            .read(id)?
+            .into_iter()
+            .skip_while(|e| e.version < from)
+            .collect())
+    }
+
+    /// Eventを1件以上保持しているストリームを、ID順に `paging` の範囲で返す
+    fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<A>>, Self::Error>;
+
+    /// 保存済みの最新バージョンが `expected_version` と一致する場合のみ、Eventをまとめて追加する
+    ///
+    /// `events` はすべて保存されるか、ひとつも保存されないかのどちらかでなければならない。
+    /// デフォルト実装は確認と追加の間に排他を取らず、1件ずつ `insert` するため、
+    /// 永続化を伴う実装ではオーバーライドする
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let actual_version = self
+            .read(id)?
+            .into_iter()
+            .last()
+            .map(|e| e.version)
+            .unwrap_or_default();
+        if actual_version != expected_version {
+            return Err(AppendError::ConcurrencyConflict {
+                expected: expected_version,
+                actual: actual_version,
+            });
+        }
+        events.into_iter().try_for_each(|e| self.insert(id, e))?;
+        Ok(())
+    }
+
+    /// `execute_command` で保存したEventの配信先
+    fn projectors(&mut self) -> &mut [Box<dyn Projector<A>>] {
+        &mut []
+    }
+
+    /// `execute_command` でスナップショットを保存するタイミング
+    fn snapshot_policy(&self) -> SnapshotPolicy {
+        SnapshotPolicy::Never
+    }
+
+    fn load_snapshot(&self, _id: Id<A>) -> Result<Option<VersionedAggregate<A>>, Self::Error> {
+        Ok(None)
+    }
+
+    fn save_snapshot(
+        &mut self,
+        _id: Id<A>,
+        _aggregate: &VersionedAggregate<A>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// `idempotency_key` を持つEventが、 `id` のストリームに記録済みかどうか
+    ///
+    /// デフォルト実装はストリームのEventをすべて読み込んで探す
+    fn is_processed(&self, id: Id<A>, idempotency_key: &str) -> Result<bool, Self::Error> {
+        Ok(self
+            .read(id)?
+            .into_iter()
+            .any(|e| e.metadata.idempotency_key.as_deref() == Some(idempotency_key)))
+    }
+
     fn replay_aggregate(
         &self,
         id: Id<A>,
     ) -> Result<VersionedAggregate<A>, ReplayAggregateError<Self::Error>> {
-        let mut aggregate = VersionedAggregate::<A>::default();
-        let events = self.read(id)?;
-        events.into_iter().try_for_each(|e| {
-            if e.version.is_next_of(&aggregate.version) {
-                e.event.apply_to(&mut aggregate.aggregate);
-                aggregate.version = e.version;
-                Ok(())
-            } else {
-                Err(ReplayAggregateError::<Self::Error>::VersionInconsistent)
-            }
-        })?;
-        Ok(aggregate)
+        let aggregate = self.load_snapshot(id)?.unwrap_or_default();
+        let events = self.read_from(id, aggregate.version.next())?;
+        apply_events(aggregate, events)
+    }
+
+    /// `until` のバージョンまでEventを適用したAggregateを返す
+    fn replay_aggregate_until(
+        &self,
+        id: Id<A>,
+        until: Version,
+    ) -> Result<VersionedAggregate<A>, ReplayAggregateError<Self::Error>> {
+        let aggregate = self
+            .load_snapshot(id)?
+            .filter(|s| s.version <= until)
+            .unwrap_or_default();
+        let events = self
+            .read_from(id, aggregate.version.next())?
+            .into_iter()
+            .take_while(|e| e.version <= until);
+        apply_events(aggregate, events)
+    }
+
+    /// `at` の時点までに記録されたEventを適用したAggregateを返す
+    ///
+    /// スナップショットは記録日時を持たないため使わない。
+    /// また、 `recorded_at` が記録されていないEventは `at` より前に記録されたものとみなす
+    fn replay_aggregate_as_of(
+        &self,
+        id: Id<A>,
+        at: DateTime<Utc>,
+    ) -> Result<VersionedAggregate<A>, ReplayAggregateError<Self::Error>> {
+        let events = self
+            .read(id)?
+            .into_iter()
+            .take_while(|e| match e.metadata.recorded_at {
+                Some(recorded_at) => recorded_at <= at,
+                None => true,
+            });
+        apply_events(VersionedAggregate::default(), events)
     }
 
     fn execute_command<C: Command<A>>(
@@ -50,26 +185,155 @@ pub trait EventStorage<A: Aggregate> {
     where
         A: Aggregate<Command = C>,
     {
-        let aggregate = self.replay_aggregate(id)?;
-        let mut next_version = aggregate.version.next();
+        self.execute_command_with_metadata(id, command, Metadata::new())
+    }
+
+    /// 生成されたすべてのEventに `metadata` を付与してコマンドを実行する
+    ///
+    /// `recorded_at` が未指定の場合は現在時刻を記録する。
+    /// `idempotency_key` が記録済みの場合は、コマンドを実行せずに成功を返す。
+    /// Eventを生成しなかったコマンドや失敗したコマンドは記録されないため、再度実行される
+    fn execute_command_with_metadata<C: Command<A>>(
+        &mut self,
+        id: Id<A>,
+        command: C,
+        metadata: Metadata,
+    ) -> Result<(), ExecuteCommandError<Self::Error, C::Error>>
+    where
+        A: Aggregate<Command = C>,
+    {
+        let metadata = Metadata {
+            recorded_at: metadata.recorded_at.or_else(|| Some(Utc::now())),
+            ..metadata
+        };
+
+        if let Some(key) = &metadata.idempotency_key {
+            if self
+                .is_processed(id, key)
+                .map_err(ReplayAggregateError::from)?
+            {
+                return Ok(());
+            }
+        }
+
+        let mut aggregate = self.replay_aggregate(id)?;
+        let previous_version = aggregate.version;
 
         let events = command.execute_on(&aggregate.aggregate);
         let events = events.map_err(|e| ExecuteCommandError::Command(e))?;
-        let events = events.into_iter().map(|e| {
-            let version = next_version;
-            next_version = version.next();
+        let events = versioned_events(aggregate.version, events, &metadata);
 
-            VersionedEvent { version, event: e }
+        self.append(id, aggregate.version, events.clone())?;
+
+        events.into_iter().for_each(|e| {
+            self.projectors().iter_mut().for_each(|p| p.project(id, &e));
+            e.event.apply_to(&mut aggregate.aggregate);
+            aggregate.version = e.version;
         });
 
-        events
-            .into_iter()
-            .try_for_each(|e| self.insert(id, e))
-            .map_err(|e| ExecuteCommandError::Insert(e))
+        if self
+            .snapshot_policy()
+            .is_due(previous_version, aggregate.version)
+        {
+            self.save_snapshot(id, &aggregate)
+                .map_err(ExecuteCommandError::SaveSnapshot)?;
+        }
+        Ok(())
+    }
+
+    /// 他の書き込みと競合した場合に、 `policy` に従ってAggregateを再構築しコマンドを実行し直す
+    ///
+    /// 実行し直すのは `ConcurrencyConflict` の場合のみで、 `CommandError` などでは実行し直さない
+    fn execute_command_with_retry<C: Command<A> + Clone>(
+        &mut self,
+        id: Id<A>,
+        command: C,
+        policy: RetryPolicy,
+    ) -> Result<(), ExecuteCommandError<Self::Error, C::Error>>
+    where
+        A: Aggregate<Command = C>,
+    {
+        self.execute_command_with_metadata_and_retry(id, command, Metadata::new(), policy)
+    }
+
+    /// `execute_command_with_metadata` を、 `policy` に従って実行し直す
+    ///
+    /// 競合した実行ではEventが保存されないため、 `idempotency_key` を指定していても実行し直される
+    fn execute_command_with_metadata_and_retry<C: Command<A> + Clone>(
+        &mut self,
+        id: Id<A>,
+        command: C,
+        metadata: Metadata,
+        policy: RetryPolicy,
+    ) -> Result<(), ExecuteCommandError<Self::Error, C::Error>>
+    where
+        A: Aggregate<Command = C>,
+    {
+        policy.run(
+            || self.execute_command_with_metadata(id, command.clone(), metadata.clone()),
+            |e| matches!(e, ExecuteCommandError::ConcurrencyConflict { .. }),
+        )
     }
 }
 
-pub trait EventStorageError: Fail {}
+/// `base_version` に続くバージョンと `metadata` を、各Eventに付与する
+pub(crate) fn versioned_events<A, I>(
+    base_version: Version,
+    events: I,
+    metadata: &Metadata,
+) -> Vec<VersionedEvent<A>>
+where
+    A: Aggregate,
+    I: IntoIterator<Item = A::Event>,
+{
+    let mut next_version = base_version.next();
+    events
+        .into_iter()
+        .map(|e| {
+            let version = next_version;
+            next_version = version.next();
+
+            VersionedEvent {
+                version,
+                event: e,
+                metadata: metadata.clone(),
+            }
+        })
+        .collect()
+}
+
+/// `aggregate` のバージョンより新しいEventを、バージョンの連続性を確認しながら適用する
+pub(crate) fn apply_events<A, I, E>(
+    mut aggregate: VersionedAggregate<A>,
+    events: I,
+) -> Result<VersionedAggregate<A>, ReplayAggregateError<E>>
+where
+    A: Aggregate,
+    I: IntoIterator<Item = VersionedEvent<A>>,
+    E: EventStorageError,
+{
+    let base_version = aggregate.version;
+    events
+        .into_iter()
+        .skip_while(|e| e.version <= base_version)
+        .try_for_each(|e| {
+            if e.version.is_next_of(&aggregate.version) {
+                e.event.apply_to(&mut aggregate.aggregate);
+                aggregate.version = e.version;
+                Ok(())
+            } else {
+                Err(ReplayAggregateError::VersionInconsistent)
+            }
+        })?;
+    Ok(aggregate)
+}
+
+pub trait EventStorageError: Fail {
+    /// 削除済みのストリームを読み書きしようとしたことによるエラーかどうか
+    fn is_deleted(&self) -> bool {
+        false
+    }
+}
 
 #[derive(Fail, Debug, Eq, PartialEq)]
 pub enum ReplayAggregateError<E: EventStorageError> {
@@ -77,11 +341,41 @@ pub enum ReplayAggregateError<E: EventStorageError> {
     Read(#[fail(cause)] E),
     #[fail(display = "Version inconsistent")]
     VersionInconsistent,
+    #[fail(display = "Stream deleted")]
+    Deleted,
 }
 
 impl<E: EventStorageError> From<E> for ReplayAggregateError<E> {
     fn from(e: E) -> Self {
-        ReplayAggregateError::Read(e)
+        if e.is_deleted() {
+            ReplayAggregateError::Deleted
+        } else {
+            ReplayAggregateError::Read(e)
+        }
+    }
+}
+
+#[derive(Fail, Debug, Eq, PartialEq)]
+pub enum AppendError<E: EventStorageError> {
+    #[fail(display = "Storage error: {}", _0)]
+    Storage(#[fail(cause)] E),
+    /// 読み込んでから追加するまでの間に、他の書き込みでバージョンが進んだ
+    #[fail(
+        display = "Concurrency conflict: expected {:?}, actual {:?}",
+        expected, actual
+    )]
+    ConcurrencyConflict { expected: Version, actual: Version },
+    #[fail(display = "Stream deleted")]
+    Deleted,
+}
+
+impl<E: EventStorageError> From<E> for AppendError<E> {
+    fn from(e: E) -> Self {
+        if e.is_deleted() {
+            AppendError::Deleted
+        } else {
+            AppendError::Storage(e)
+        }
     }
 }
 
@@ -93,12 +387,37 @@ pub enum ExecuteCommandError<E: EventStorageError, C: CommandError> {
     Command(#[fail(cause)] C),
     #[fail(display = "Insert error: {}", _0)]
     Insert(#[fail(cause)] E),
+    #[fail(
+        display = "Concurrency conflict: expected {:?}, actual {:?}",
+        expected, actual
+    )]
+    ConcurrencyConflict { expected: Version, actual: Version },
+    /// Eventは保存済みで、スナップショットの保存のみ失敗した
+    #[fail(display = "SaveSnapshot error: {}", _0)]
+    SaveSnapshot(#[fail(cause)] E),
+    #[fail(display = "Stream deleted")]
+    Deleted,
 }
 
 impl<E: EventStorageError, C: CommandError> From<ReplayAggregateError<E>>
     for ExecuteCommandError<E, C>
 {
     fn from(e: ReplayAggregateError<E>) -> Self {
-        ExecuteCommandError::ReplayAggregate(e)
+        match e {
+            ReplayAggregateError::Deleted => ExecuteCommandError::Deleted,
+            e => ExecuteCommandError::ReplayAggregate(e),
+        }
+    }
+}
+
+impl<E: EventStorageError, C: CommandError> From<AppendError<E>> for ExecuteCommandError<E, C> {
+    fn from(e: AppendError<E>) -> Self {
+        match e {
+            AppendError::Storage(e) => ExecuteCommandError::Insert(e),
+            AppendError::ConcurrencyConflict { expected, actual } => {
+                ExecuteCommandError::ConcurrencyConflict { expected, actual }
+            }
+            AppendError::Deleted => ExecuteCommandError::Deleted,
+        }
     }
 }
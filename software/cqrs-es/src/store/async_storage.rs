@@ -0,0 +1,404 @@
+use std::thread;
+
+use chrono::Utc;
+use failure::Fail;
+use futures::channel::oneshot::Canceled;
+use futures::channel::{mpsc, oneshot};
+use futures::executor;
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::store::{
+    apply_events, versioned_events, AppendError, EventStorage, EventStorageError,
+    ExecuteCommandError, Metadata, Paging, ReplayAggregateError, StreamInfo, Version,
+    VersionedAggregate, VersionedEvent,
+};
+use crate::{Aggregate, Command, CommandError, Id};
+
+#[cfg(test)]
+mod tests;
+
+/// `AsyncEventStorage::execute_command` が返すFuture
+pub type ExecuteCommandFuture<'a, E, C> = BoxFuture<'a, Result<(), ExecuteCommandError<E, C>>>;
+
+/// `EventStorage` の非同期版
+///
+/// 返すFutureは `Send` であるため、マルチスレッドのランタイムからも扱える。
+///
+/// `EventStorage` と異なりスナップショットやProjectorを扱う仕組みを持たず、
+/// デフォルト実装の `replay_aggregate` は常に全てのEventを読み込み、
+/// `execute_command` は追加したEventをProjectorに渡さない。
+/// これらが必要な場合は、同期のEventStorageに委ねる `AsyncAdapter` を使う
+pub trait AsyncEventStorage<A>: Send + Sync
+where
+    A: Aggregate + Send + 'static,
+    A::Event: Send,
+{
+    type Events: IntoIterator<Item = VersionedEvent<A>> + Send;
+    type Error: EventStorageError;
+
+    /// バージョンを確認せずにEventを追加する
+    ///
+    /// 他の書き込みと競合しうるため、通常は `append` を使う
+    fn insert(
+        &mut self,
+        id: Id<A>,
+        event: VersionedEvent<A>,
+    ) -> BoxFuture<'_, Result<(), Self::Error>>;
+
+    fn read(&self, id: Id<A>) -> BoxFuture<'_, Result<Self::Events, Self::Error>>;
+
+    /// Eventを1件以上保持しているストリームを、ID順に `paging` の範囲で返す
+    fn list_ids(&self, paging: Paging) -> BoxFuture<'_, Result<Vec<StreamInfo<A>>, Self::Error>>;
+
+    /// 保存済みの最新バージョンが `expected_version` と一致する場合のみ、Eventをまとめて追加する
+    ///
+    /// デフォルト実装の制約は `EventStorage::append` と同じ
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> BoxFuture<'_, Result<(), AppendError<Self::Error>>> {
+        async move {
+            if events.is_empty() {
+                return Ok(());
+            }
+            let actual_version = self
+                .read(id)
+                .await?
+                .into_iter()
+                .last()
+                .map(|e| e.version)
+                .unwrap_or_default();
+            if actual_version != expected_version {
+                return Err(AppendError::ConcurrencyConflict {
+                    expected: expected_version,
+                    actual: actual_version,
+                });
+            }
+            for e in events {
+                self.insert(id, e).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// `idempotency_key` を持つEventが、 `id` のストリームに記録済みかどうか
+    fn is_processed<'a>(
+        &'a self,
+        id: Id<A>,
+        idempotency_key: &'a str,
+    ) -> BoxFuture<'a, Result<bool, Self::Error>> {
+        async move {
+            Ok(self
+                .read(id)
+                .await?
+                .into_iter()
+                .any(|e| e.metadata.idempotency_key.as_deref() == Some(idempotency_key)))
+        }
+        .boxed()
+    }
+
+    fn replay_aggregate(
+        &self,
+        id: Id<A>,
+    ) -> BoxFuture<'_, Result<VersionedAggregate<A>, ReplayAggregateError<Self::Error>>> {
+        async move {
+            let events = self.read(id).await?;
+            apply_events(VersionedAggregate::default(), events)
+        }
+        .boxed()
+    }
+
+    fn execute_command<C>(
+        &mut self,
+        id: Id<A>,
+        command: C,
+    ) -> ExecuteCommandFuture<'_, Self::Error, C::Error>
+    where
+        A: Aggregate<Command = C>,
+        C: Command<A> + Send + 'static,
+    {
+        self.execute_command_with_metadata(id, command, Metadata::new())
+    }
+
+    /// 生成されたすべてのEventに `metadata` を付与してコマンドを実行する
+    ///
+    /// `recorded_at` や `idempotency_key` の扱いは `EventStorage::execute_command_with_metadata` と同じ
+    fn execute_command_with_metadata<C>(
+        &mut self,
+        id: Id<A>,
+        command: C,
+        metadata: Metadata,
+    ) -> ExecuteCommandFuture<'_, Self::Error, C::Error>
+    where
+        A: Aggregate<Command = C>,
+        C: Command<A> + Send + 'static,
+    {
+        async move {
+            let metadata = Metadata {
+                recorded_at: metadata.recorded_at.or_else(|| Some(Utc::now())),
+                ..metadata
+            };
+
+            if let Some(key) = &metadata.idempotency_key {
+                if self
+                    .is_processed(id, key)
+                    .await
+                    .map_err(ReplayAggregateError::from)?
+                {
+                    return Ok(());
+                }
+            }
+
+            let aggregate = self.replay_aggregate(id).await?;
+            let events = command
+                .execute_on(&aggregate.aggregate)
+                .map_err(ExecuteCommandError::Command)?;
+            let events = versioned_events(aggregate.version, events, &metadata);
+            self.append(id, aggregate.version, events).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// 非同期のEventStorageを、完了を待つことで同期のEventStorageとして使う
+pub struct BlockingEventStorage<S> {
+    inner: S,
+}
+
+impl<S> BlockingEventStorage<S> {
+    pub fn new(inner: S) -> Self {
+        BlockingEventStorage { inner }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<A, S> EventStorage<A> for BlockingEventStorage<S>
+where
+    A: Aggregate + Send + 'static,
+    A::Event: Send,
+    S: AsyncEventStorage<A>,
+{
+    type Events = S::Events;
+    type Error = S::Error;
+
+    fn insert(&mut self, id: Id<A>, event: VersionedEvent<A>) -> Result<(), Self::Error> {
+        executor::block_on(self.inner.insert(id, event))
+    }
+
+    fn read(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        executor::block_on(self.inner.read(id))
+    }
+
+    fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<A>>, Self::Error> {
+        executor::block_on(self.inner.list_ids(paging))
+    }
+
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        executor::block_on(self.inner.append(id, expected_version, events))
+    }
+}
+
+type Job<S> = Box<dyn FnOnce(&mut S) + Send>;
+
+#[derive(Fail, Debug, Eq, PartialEq)]
+pub enum AsyncAdapterError<E: EventStorageError> {
+    #[fail(display = "Storage error: {}", _0)]
+    Storage(#[fail(cause)] E),
+    /// Projectorのpanicなどで、EventStorageのスレッドが終了している
+    ///
+    /// 実行中にスレッドが終了した場合、その操作が反映されたかどうかは分からない
+    #[fail(display = "EventStorage thread terminated")]
+    Terminated,
+}
+
+impl<E: EventStorageError> EventStorageError for AsyncAdapterError<E> {
+    fn is_deleted(&self) -> bool {
+        match self {
+            AsyncAdapterError::Storage(e) => e.is_deleted(),
+            AsyncAdapterError::Terminated => false,
+        }
+    }
+}
+
+/// 同期のEventStorageを専用のスレッドで動かし、非同期のEventStorageとして使う
+///
+/// Projectorなどスレッド間で共有できないものを持つEventStorageも扱えるよう、
+/// EventStorageはそのスレッドの中で生成する。
+/// `replay_aggregate` や `execute_command` も同期のEventStorageに委ねるため、
+/// スナップショットやProjectorはそのまま機能する
+pub struct AsyncAdapter<S> {
+    sender: mpsc::UnboundedSender<Job<S>>,
+}
+
+impl<S: 'static> AsyncAdapter<S> {
+    pub fn spawn<F>(create_storage: F) -> Self
+    where
+        F: FnOnce() -> S + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::unbounded::<Job<S>>();
+        thread::spawn(move || {
+            let mut storage = create_storage();
+            executor::block_on_stream(receiver).for_each(|job| job(&mut storage));
+        });
+        AsyncAdapter { sender }
+    }
+
+    /// スレッドが終了している場合は `Canceled` とする
+    fn run<F, T>(&self, f: F) -> BoxFuture<'static, Result<T, Canceled>>
+    where
+        F: FnOnce(&mut S) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let job: Job<S> = Box::new(move |storage| {
+            let _ = result_sender.send(f(storage));
+        });
+        // スレッドが終了している場合はjobごと破棄され、下のawaitで検知する
+        let _ = self.sender.unbounded_send(job);
+        result_receiver.boxed()
+    }
+}
+
+fn storage_error<E: EventStorageError>(
+    result: Result<Result<(), E>, Canceled>,
+) -> Result<(), AsyncAdapterError<E>> {
+    result
+        .map_err(|_| AsyncAdapterError::Terminated)?
+        .map_err(AsyncAdapterError::Storage)
+}
+
+fn append_error<E: EventStorageError>(e: AppendError<E>) -> AppendError<AsyncAdapterError<E>> {
+    match e {
+        AppendError::Storage(e) => AppendError::Storage(AsyncAdapterError::Storage(e)),
+        AppendError::ConcurrencyConflict { expected, actual } => {
+            AppendError::ConcurrencyConflict { expected, actual }
+        }
+        AppendError::Deleted => AppendError::Deleted,
+    }
+}
+
+fn replay_aggregate_error<E: EventStorageError>(
+    e: ReplayAggregateError<E>,
+) -> ReplayAggregateError<AsyncAdapterError<E>> {
+    match e {
+        ReplayAggregateError::Read(e) => ReplayAggregateError::Read(AsyncAdapterError::Storage(e)),
+        ReplayAggregateError::VersionInconsistent => ReplayAggregateError::VersionInconsistent,
+        ReplayAggregateError::Deleted => ReplayAggregateError::Deleted,
+    }
+}
+
+fn execute_command_error<E: EventStorageError, C: CommandError>(
+    e: ExecuteCommandError<E, C>,
+) -> ExecuteCommandError<AsyncAdapterError<E>, C> {
+    match e {
+        ExecuteCommandError::ReplayAggregate(e) => {
+            ExecuteCommandError::ReplayAggregate(replay_aggregate_error(e))
+        }
+        ExecuteCommandError::Command(e) => ExecuteCommandError::Command(e),
+        ExecuteCommandError::Insert(e) => {
+            ExecuteCommandError::Insert(AsyncAdapterError::Storage(e))
+        }
+        ExecuteCommandError::ConcurrencyConflict { expected, actual } => {
+            ExecuteCommandError::ConcurrencyConflict { expected, actual }
+        }
+        ExecuteCommandError::SaveSnapshot(e) => {
+            ExecuteCommandError::SaveSnapshot(AsyncAdapterError::Storage(e))
+        }
+        ExecuteCommandError::Deleted => ExecuteCommandError::Deleted,
+    }
+}
+
+impl<A, S> AsyncEventStorage<A> for AsyncAdapter<S>
+where
+    A: Aggregate + Send + 'static,
+    A::Event: Send,
+    S: EventStorage<A> + 'static,
+    S::Events: Send,
+{
+    type Events = S::Events;
+    type Error = AsyncAdapterError<S::Error>;
+
+    fn insert(
+        &mut self,
+        id: Id<A>,
+        event: VersionedEvent<A>,
+    ) -> BoxFuture<'_, Result<(), Self::Error>> {
+        self.run(move |s| s.insert(id, event))
+            .map(storage_error)
+            .boxed()
+    }
+
+    fn read(&self, id: Id<A>) -> BoxFuture<'_, Result<Self::Events, Self::Error>> {
+        self.run(move |s| s.read(id))
+            .map(|r| match r {
+                Ok(r) => r.map_err(AsyncAdapterError::Storage),
+                Err(_) => Err(AsyncAdapterError::Terminated),
+            })
+            .boxed()
+    }
+
+    fn list_ids(&self, paging: Paging) -> BoxFuture<'_, Result<Vec<StreamInfo<A>>, Self::Error>> {
+        self.run(move |s| s.list_ids(paging))
+            .map(|r| match r {
+                Ok(r) => r.map_err(AsyncAdapterError::Storage),
+                Err(_) => Err(AsyncAdapterError::Terminated),
+            })
+            .boxed()
+    }
+
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> BoxFuture<'_, Result<(), AppendError<Self::Error>>> {
+        self.run(move |s| s.append(id, expected_version, events))
+            .map(|r| match r {
+                Ok(r) => r.map_err(append_error),
+                Err(_) => Err(AppendError::Storage(AsyncAdapterError::Terminated)),
+            })
+            .boxed()
+    }
+
+    fn replay_aggregate(
+        &self,
+        id: Id<A>,
+    ) -> BoxFuture<'_, Result<VersionedAggregate<A>, ReplayAggregateError<Self::Error>>> {
+        self.run(move |s| s.replay_aggregate(id))
+            .map(|r| match r {
+                Ok(r) => r.map_err(replay_aggregate_error),
+                Err(_) => Err(ReplayAggregateError::Read(AsyncAdapterError::Terminated)),
+            })
+            .boxed()
+    }
+
+    fn execute_command_with_metadata<C>(
+        &mut self,
+        id: Id<A>,
+        command: C,
+        metadata: Metadata,
+    ) -> ExecuteCommandFuture<'_, Self::Error, C::Error>
+    where
+        A: Aggregate<Command = C>,
+        C: Command<A> + Send + 'static,
+    {
+        self.run(move |s| s.execute_command_with_metadata(id, command, metadata))
+            .map(|r| match r {
+                Ok(r) => r.map_err(execute_command_error),
+                Err(_) => Err(ExecuteCommandError::Insert(AsyncAdapterError::Terminated)),
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::mpsc;
+
+use futures::executor::block_on;
+use futures::future::{self, BoxFuture, FutureExt};
+
+use crate::projector::Projector;
+use crate::store::memory::InMemoryEventStorageError;
+use crate::store::*;
+use crate::tests::test_aggregate::*;
+use crate::Id;
+
+#[derive(Default)]
+struct AsyncVecStorage {
+    events: Vec<VersionedEvent<TestAggregate>>,
+}
+
+impl AsyncEventStorage<TestAggregate> for AsyncVecStorage {
+    type Events = Vec<VersionedEvent<TestAggregate>>;
+    type Error = InMemoryEventStorageError;
+
+    fn insert(
+        &mut self,
+        _id: Id<TestAggregate>,
+        event: VersionedEvent<TestAggregate>,
+    ) -> BoxFuture<'_, Result<(), Self::Error>> {
+        self.events.push(event);
+        future::ok(()).boxed()
+    }
+
+    fn read(&self, _id: Id<TestAggregate>) -> BoxFuture<'_, Result<Self::Events, Self::Error>> {
+        future::ok(self.events.clone()).boxed()
+    }
+
+    fn list_ids(
+        &self,
+        _paging: Paging,
+    ) -> BoxFuture<'_, Result<Vec<StreamInfo<TestAggregate>>, Self::Error>> {
+        unimplemented!()
+    }
+}
+
+fn event(version: u64) -> VersionedEvent<TestAggregate> {
+    VersionedEvent {
+        version: Version(version),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    }
+}
+
+#[test]
+fn execute_command() {
+    let mut storage = AsyncVecStorage::default();
+    let id = Id::new();
+
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+
+    let got = block_on(storage.replay_aggregate(id)).unwrap();
+    assert_eq!(got.version, Version(2));
+    assert_eq!(got.aggregate, TestAggregate(2));
+    assert!(storage
+        .events
+        .iter()
+        .all(|e| e.metadata.recorded_at.is_some()));
+}
+
+#[test]
+fn execute_command_error() {
+    let mut storage = AsyncVecStorage::default();
+
+    let got = block_on(storage.execute_command(Id::new(), TestCommand::Invalid));
+
+    assert_eq!(
+        got,
+        Err(ExecuteCommandError::Command(TestCommandError::Invalid))
+    );
+    assert!(storage.events.is_empty());
+}
+
+#[test]
+fn execute_command_idempotent() {
+    let mut storage = AsyncVecStorage::default();
+    let id = Id::new();
+    let metadata = Metadata::new().with_idempotency_key("increase-1");
+
+    block_on(storage.execute_command_with_metadata(id, TestCommand::Increase, metadata.clone()))
+        .unwrap();
+    block_on(storage.execute_command_with_metadata(id, TestCommand::Increase, metadata)).unwrap();
+
+    assert_eq!(storage.events.len(), 1);
+}
+
+#[test]
+fn append_concurrency_conflict() {
+    let mut storage = AsyncVecStorage::default();
+    let id = Id::new();
+    block_on(storage.append(id, Version(0), vec![event(1)])).unwrap();
+
+    let got = block_on(storage.append(id, Version(0), vec![event(1)]));
+
+    assert_eq!(
+        got,
+        Err(AppendError::ConcurrencyConflict {
+            expected: Version(0),
+            actual: Version(1),
+        })
+    );
+}
+
+#[test]
+fn blocking_event_storage() {
+    let mut storage = BlockingEventStorage::new(AsyncVecStorage::default());
+    let id = Id::new();
+
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    let got = storage.replay_aggregate(id).unwrap();
+    assert_eq!(got.version, Version(2));
+    assert_eq!(storage.into_inner().events.len(), 2);
+}
+
+struct SenderProjector(mpsc::Sender<Version>);
+
+impl Projector<TestAggregate> for SenderProjector {
+    fn project(&mut self, _id: Id<TestAggregate>, event: &VersionedEvent<TestAggregate>) {
+        self.0.send(event.version).unwrap()
+    }
+}
+
+#[test]
+fn async_adapter() {
+    let (sender, receiver) = mpsc::channel();
+    let mut storage = AsyncAdapter::spawn(move || {
+        let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+        storage.add_projector(SenderProjector(sender));
+        storage
+    });
+    let id = Id::new();
+
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+    block_on(storage.append(id, Version(1), vec![event(2)])).unwrap();
+
+    let got = block_on(storage.replay_aggregate(id)).unwrap();
+    assert_eq!(got.version, Version(2));
+    assert_eq!(got.aggregate, TestAggregate(2));
+    let got = block_on(storage.list_ids(Paging::all())).unwrap();
+    assert_eq!(got.len(), 1);
+    // Projectorにはexecute_commandで追加したEventのみ渡る
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![Version(1)]);
+}
+
+struct PanickingProjector;
+
+impl Projector<TestAggregate> for PanickingProjector {
+    fn project(&mut self, _id: Id<TestAggregate>, _event: &VersionedEvent<TestAggregate>) {
+        panic!("projector failed")
+    }
+}
+
+#[test]
+fn async_adapter_terminated() {
+    let mut storage = AsyncAdapter::spawn(|| {
+        let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+        storage.add_projector(PanickingProjector);
+        storage
+    });
+    let id = Id::new();
+
+    let got = block_on(storage.execute_command(id, TestCommand::Increase));
+    assert_eq!(
+        got,
+        Err(ExecuteCommandError::Insert(AsyncAdapterError::Terminated))
+    );
+    // スレッドが終了した後も、panicせずにエラーを返す
+    let got = block_on(storage.read(id)).map(|_| ());
+    assert_eq!(got, Err(AsyncAdapterError::Terminated));
+    let got = block_on(storage.replay_aggregate(id)).map(|_| ());
+    assert_eq!(
+        got,
+        Err(ReplayAggregateError::Read(AsyncAdapterError::Terminated))
+    );
+}
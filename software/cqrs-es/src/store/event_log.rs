@@ -0,0 +1,91 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::store::{EventStorageError, VersionedEvent};
+use crate::{Aggregate, Event, Id};
+
+#[cfg(test)]
+mod tests;
+
+/// 全Aggregateのストリームを通して、Eventが記録された順序を表す
+///
+/// # Examples
+///
+/// ```
+/// use cqrs_es::store::event_log::Position;
+/// let first = Position::default().next();
+/// assert_eq!(first, Position(1));
+/// ```
+#[derive(
+    Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Serialize, Deserialize,
+)]
+pub struct Position(pub u64);
+
+impl Position {
+    pub fn next(&self) -> Position {
+        Position(self.0 + 1)
+    }
+}
+
+/// 全ストリームのログに記録されたEvent
+///
+/// Aggregateの型によらず扱えるよう、Eventはシリアライズされた形式のまま保持する
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub position: Position,
+    pub type_name: String,
+    pub id: Uuid,
+    pub event: Value,
+}
+
+/// `RecordedEvent::decode` で得られる、Aggregate IDと型付けされたEvent
+pub type Decoded<A> = (Id<A>, VersionedEvent<A>);
+
+impl RecordedEvent {
+    pub fn new<A, E>(
+        position: Position,
+        id: Id<A>,
+        event: &VersionedEvent<A>,
+    ) -> Result<RecordedEvent, serde_json::Error>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + Serialize,
+    {
+        Ok(RecordedEvent {
+            position,
+            type_name: A::type_name().to_owned(),
+            id: *id.as_uuid(),
+            event: serde_json::to_value(event)?,
+        })
+    }
+
+    pub fn is_of<A: Aggregate>(&self) -> bool {
+        self.type_name == A::type_name()
+    }
+
+    /// `A` のEventであれば、型付けされたEventに戻す
+    pub fn decode<A, E>(&self) -> Result<Option<Decoded<A>>, serde_json::Error>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + DeserializeOwned,
+    {
+        if !self.is_of::<A>() {
+            return Ok(None);
+        }
+        let event = VersionedEvent::deserialize(&self.event)?;
+        Ok(Some((Id::from(self.id), event)))
+    }
+}
+
+/// 全Aggregateのストリームに追加されたEventを、記録された順に読み出す
+pub trait EventLog {
+    type Events: IntoIterator<Item = RecordedEvent>;
+    type Error: EventStorageError;
+
+    /// `from` 以降 ( `from` を含む) に記録されたEventを返す
+    ///
+    /// 最初から読む場合は `Position::default()` を渡す
+    fn read_all(&self, from: Position) -> Result<Self::Events, Self::Error>;
+}
@@ -0,0 +1,54 @@
+use crate::store::event_log::*;
+use crate::store::{Metadata, Version, VersionedEvent};
+use crate::tests::test_aggregate::*;
+use crate::tests::test_upcast_aggregate::*;
+use crate::Id;
+
+fn recorded(id: Id<TestAggregate>) -> RecordedEvent {
+    let event = VersionedEvent {
+        version: Version(1),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    };
+    RecordedEvent::new(Position(3), id, &event).unwrap()
+}
+
+#[test]
+fn new() {
+    let id = Id::new();
+
+    let got = recorded(id);
+
+    assert_eq!(got.position, Position(3));
+    assert_eq!(got.type_name, "test");
+    assert_eq!(got.id, *id.as_uuid());
+    assert!(got.is_of::<TestAggregate>());
+}
+
+#[test]
+fn decode() {
+    let id = Id::new();
+
+    let (got_id, got_event) = recorded(id).decode::<TestAggregate, _>().unwrap().unwrap();
+
+    assert_eq!(got_id, id);
+    assert_eq!(got_event.version, Version(1));
+    assert_eq!(got_event.event, TestEvent::Increased);
+}
+
+#[test]
+fn decode_other_aggregate() {
+    let got = recorded(Id::new()).decode::<UpcastAggregate, _>().unwrap();
+
+    assert!(got.is_none());
+}
+
+#[test]
+fn serde() {
+    let recorded = recorded(Id::new());
+
+    let json = serde_json::to_string(&recorded).unwrap();
+    let got: RecordedEvent = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(got, recorded);
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+
+use failure::Fail;
+
+use crate::projector::Projector;
+use crate::store::snapshot::SnapshotPolicy;
+use crate::store::{
+    AppendError, DeletableEventStorage, DeleteMode, EventStorage, EventStorageError, Paging,
+    StreamInfo, Version, VersionedAggregate, VersionedEvent,
+};
+use crate::{Aggregate, Id};
+
+#[cfg(test)]
+mod tests;
+
+/// Eventをメモリ上に保持するEventStorage
+///
+/// ファイルなどに永続化しないため、テストやプロトタイピングに用いる
+pub struct InMemoryEventStorage<A: Aggregate> {
+    streams: HashMap<Id<A>, Vec<VersionedEvent<A>>>,
+    projectors: Vec<Box<dyn Projector<A>>>,
+    snapshots: HashMap<Id<A>, VersionedAggregate<A>>,
+    snapshot_policy: SnapshotPolicy,
+    tombstones: HashSet<Id<A>>,
+    archive: HashMap<Id<A>, Vec<VersionedEvent<A>>>,
+}
+
+impl<A: Aggregate> InMemoryEventStorage<A> {
+    pub fn new() -> Self {
+        InMemoryEventStorage {
+            streams: HashMap::new(),
+            projectors: Vec::new(),
+            snapshots: HashMap::new(),
+            snapshot_policy: SnapshotPolicy::Never,
+            tombstones: HashSet::new(),
+            archive: HashMap::new(),
+        }
+    }
+
+    pub fn with_snapshot_policy(mut self, policy: SnapshotPolicy) -> Self {
+        self.snapshot_policy = policy;
+        self
+    }
+
+    pub fn add_projector<P>(&mut self, projector: P)
+    where
+        P: Projector<A> + 'static,
+    {
+        self.projectors.push(Box::new(projector))
+    }
+
+    fn check_deleted(&self, id: Id<A>) -> Result<(), InMemoryEventStorageError> {
+        if self.tombstones.contains(&id) {
+            Err(InMemoryEventStorageError::Deleted)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<A: Aggregate> Default for InMemoryEventStorage<A> {
+    fn default() -> Self {
+        InMemoryEventStorage::new()
+    }
+}
+
+/// メモリ上の操作は、削除済みのストリームに対するもの以外は失敗しない
+#[derive(Fail, Debug, Eq, PartialEq)]
+pub enum InMemoryEventStorageError {
+    #[fail(display = "Stream deleted")]
+    Deleted,
+}
+
+impl EventStorageError for InMemoryEventStorageError {
+    fn is_deleted(&self) -> bool {
+        *self == InMemoryEventStorageError::Deleted
+    }
+}
+
+impl<A: Aggregate> EventStorage<A> for InMemoryEventStorage<A> {
+    type Events = Vec<VersionedEvent<A>>;
+    type Error = InMemoryEventStorageError;
+
+    fn insert(&mut self, id: Id<A>, event: VersionedEvent<A>) -> Result<(), Self::Error> {
+        self.check_deleted(id)?;
+        self.streams.entry(id).or_default().push(event);
+        Ok(())
+    }
+
+    fn read(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        self.check_deleted(id)?;
+        Ok(self.streams.get(&id).cloned().unwrap_or_default())
+    }
+
+    fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<A>>, Self::Error> {
+        let mut streams: Vec<_> = self
+            .streams
+            .iter()
+            .filter_map(|(id, events)| events.last().map(|last| (*id, last)))
+            .collect();
+        streams.sort_by_key(|(id, _)| *id.as_uuid());
+        Ok(paging
+            .apply(streams)
+            .map(|(id, last)| StreamInfo {
+                id,
+                last_version: last.version,
+                last_modified: last.metadata.recorded_at,
+            })
+            .collect())
+    }
+
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        self.check_deleted(id)?;
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let stream = self.streams.entry(id).or_default();
+        let actual_version = stream.last().map(|e| e.version).unwrap_or_default();
+        if actual_version != expected_version {
+            return Err(AppendError::ConcurrencyConflict {
+                expected: expected_version,
+                actual: actual_version,
+            });
+        }
+        stream.extend(events);
+        Ok(())
+    }
+
+    fn projectors(&mut self) -> &mut [Box<dyn Projector<A>>] {
+        &mut self.projectors
+    }
+
+    fn snapshot_policy(&self) -> SnapshotPolicy {
+        self.snapshot_policy
+    }
+
+    fn load_snapshot(&self, id: Id<A>) -> Result<Option<VersionedAggregate<A>>, Self::Error> {
+        Ok(self.snapshots.get(&id).cloned())
+    }
+
+    fn save_snapshot(
+        &mut self,
+        id: Id<A>,
+        aggregate: &VersionedAggregate<A>,
+    ) -> Result<(), Self::Error> {
+        self.snapshots.insert(id, aggregate.clone());
+        Ok(())
+    }
+}
+
+impl<A: Aggregate> DeletableEventStorage<A> for InMemoryEventStorage<A> {
+    fn delete(&mut self, id: Id<A>, mode: DeleteMode) -> Result<(), Self::Error> {
+        self.tombstones.insert(id);
+        self.snapshots.remove(&id);
+        let events = self.streams.remove(&id);
+        match mode {
+            DeleteMode::Archive => {
+                if let Some(events) = events {
+                    self.archive.insert(id, events);
+                }
+            }
+            DeleteMode::Purge => {
+                self.archive.remove(&id);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_deleted(&self, id: Id<A>) -> Result<bool, Self::Error> {
+        Ok(self.tombstones.contains(&id))
+    }
+
+    fn read_archived(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        Ok(self.archive.get(&id).cloned().unwrap_or_default())
+    }
+}
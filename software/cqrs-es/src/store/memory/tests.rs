@@ -0,0 +1,278 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::projector::Projector;
+use crate::store::memory::InMemoryEventStorageError;
+use crate::store::snapshot::SnapshotPolicy;
+use crate::store::*;
+use crate::tests::test_aggregate::*;
+use crate::Id;
+
+fn event(version: u64) -> VersionedEvent<TestAggregate> {
+    VersionedEvent {
+        version: Version(version),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    }
+}
+
+fn versions(events: Vec<VersionedEvent<TestAggregate>>) -> Vec<Version> {
+    events.into_iter().map(|e| e.version).collect()
+}
+
+#[test]
+fn read_empty() {
+    let storage = InMemoryEventStorage::<TestAggregate>::new();
+
+    let got = storage.read(Id::new()).unwrap();
+
+    assert!(got.is_empty());
+}
+
+#[test]
+fn read_in_inserted_order_per_id() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id1 = Id::new();
+    let id2 = Id::new();
+
+    storage.insert(id1, event(1)).unwrap();
+    storage.insert(id2, event(1)).unwrap();
+    storage.insert(id1, event(2)).unwrap();
+
+    assert_eq!(
+        versions(storage.read(id1).unwrap()),
+        vec![Version(1), Version(2)]
+    );
+    assert_eq!(versions(storage.read(id2).unwrap()), vec![Version(1)]);
+}
+
+#[test]
+fn append_batch() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id = Id::new();
+
+    storage
+        .append(id, Version(0), vec![event(1), event(2)])
+        .unwrap();
+    storage.append(id, Version(2), vec![event(3)]).unwrap();
+
+    assert_eq!(
+        versions(storage.read(id).unwrap()),
+        vec![Version(1), Version(2), Version(3)]
+    );
+}
+
+#[test]
+fn append_concurrency_conflict() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id = Id::new();
+    storage.append(id, Version(0), vec![event(1)]).unwrap();
+
+    let got = storage.append(id, Version(0), vec![event(1), event(2)]);
+
+    assert_eq!(
+        got,
+        Err(AppendError::ConcurrencyConflict {
+            expected: Version(0),
+            actual: Version(1),
+        })
+    );
+    assert_eq!(versions(storage.read(id).unwrap()), vec![Version(1)]);
+}
+
+#[test]
+fn execute_command() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id = Id::new();
+
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    let got = storage.replay_aggregate(id).unwrap();
+    assert_eq!(got.version, Version(2));
+    assert_eq!(got.aggregate, TestAggregate(2));
+}
+
+#[test]
+fn execute_command_error() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id = Id::new();
+
+    let got = storage.execute_command(id, TestCommand::Invalid);
+
+    if let Err(ExecuteCommandError::Command(TestCommandError::Invalid)) = got {
+    } else {
+        panic!();
+    }
+    assert!(storage.read(id).unwrap().is_empty());
+}
+
+#[test]
+fn execute_command_idempotent() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id = Id::new();
+    let metadata = Metadata::new().with_idempotency_key("increase-1");
+
+    storage
+        .execute_command_with_metadata(id, TestCommand::Increase, metadata.clone())
+        .unwrap();
+    storage
+        .execute_command_with_metadata(id, TestCommand::Increase, metadata)
+        .unwrap();
+    storage
+        .execute_command_with_metadata(
+            id,
+            TestCommand::Increase,
+            Metadata::new().with_idempotency_key("increase-2"),
+        )
+        .unwrap();
+
+    assert_eq!(
+        versions(storage.read(id).unwrap()),
+        vec![Version(1), Version(2)]
+    );
+    assert!(storage.is_processed(id, "increase-1").unwrap());
+    assert!(!storage.is_processed(id, "increase-3").unwrap());
+}
+
+#[test]
+fn execute_command_idempotency_key_per_stream() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id1 = Id::new();
+    let id2 = Id::new();
+    let metadata = Metadata::new().with_idempotency_key("increase-1");
+
+    storage
+        .execute_command_with_metadata(id1, TestCommand::Increase, metadata.clone())
+        .unwrap();
+    storage
+        .execute_command_with_metadata(id2, TestCommand::Increase, metadata)
+        .unwrap();
+
+    assert_eq!(versions(storage.read(id2).unwrap()), vec![Version(1)]);
+}
+
+#[derive(Default)]
+struct VersionsProjector(Vec<Version>);
+
+impl Projector<TestAggregate> for VersionsProjector {
+    fn project(&mut self, _id: Id<TestAggregate>, event: &VersionedEvent<TestAggregate>) {
+        self.0.push(event.version)
+    }
+}
+
+#[test]
+fn execute_command_projects_events() {
+    let projector = Rc::new(RefCell::new(VersionsProjector::default()));
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    storage.add_projector(projector.clone());
+    let id = Id::new();
+
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    assert_eq!(projector.borrow().0, vec![Version(1), Version(2)]);
+}
+
+#[test]
+fn execute_command_saves_snapshot() {
+    let mut storage =
+        InMemoryEventStorage::<TestAggregate>::new().with_snapshot_policy(SnapshotPolicy::Every(2));
+    let id = Id::new();
+
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    assert!(storage.load_snapshot(id).unwrap().is_none());
+
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    let snapshot = storage.load_snapshot(id).unwrap().unwrap();
+    assert_eq!(snapshot.version, Version(2));
+    assert_eq!(snapshot.aggregate, TestAggregate(2));
+}
+
+#[test]
+fn list_ids() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let mut ids: Vec<Id<TestAggregate>> = (0..3).map(|_| Id::new()).collect();
+    ids.iter()
+        .for_each(|id| storage.execute_command(*id, TestCommand::Increase).unwrap());
+    storage
+        .execute_command(ids[1], TestCommand::Increase)
+        .unwrap();
+    storage
+        .execute_command(Id::new(), TestCommand::Invalid)
+        .unwrap_err();
+    ids.sort_by_key(|id| *id.as_uuid());
+
+    let got = storage.list_ids(Paging::all()).unwrap();
+
+    assert_eq!(got.iter().map(|s| s.id).collect::<Vec<_>>(), ids);
+    assert!(got.iter().all(|s| s.last_modified.is_some()));
+
+    let got = storage.list_ids(Paging::new(1, 1)).unwrap();
+
+    assert_eq!(got.len(), 1);
+    assert_eq!(got[0].id, ids[1]);
+}
+
+#[test]
+fn delete_archive() {
+    let mut storage =
+        InMemoryEventStorage::<TestAggregate>::new().with_snapshot_policy(SnapshotPolicy::Every(1));
+    let id = Id::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    storage.delete(id, DeleteMode::Archive).unwrap();
+
+    assert!(storage.is_deleted(id).unwrap());
+    assert_eq!(
+        storage.read(id).unwrap_err(),
+        InMemoryEventStorageError::Deleted
+    );
+    assert!(storage.load_snapshot(id).unwrap().is_none());
+    assert!(storage.list_ids(Paging::all()).unwrap().is_empty());
+    let archived = storage.read_archived(id).unwrap();
+    assert_eq!(
+        archived.iter().map(|e| e.version).collect::<Vec<_>>(),
+        vec![Version(1), Version(2)]
+    );
+
+    // 再度削除してもアーカイブは残る
+    storage.delete(id, DeleteMode::Archive).unwrap();
+    assert_eq!(storage.read_archived(id).unwrap().len(), 2);
+}
+
+#[test]
+fn delete_purge() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id = Id::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.delete(id, DeleteMode::Archive).unwrap();
+
+    storage.delete(id, DeleteMode::Purge).unwrap();
+
+    assert!(storage.is_deleted(id).unwrap());
+    assert!(storage.read_archived(id).unwrap().is_empty());
+}
+
+#[test]
+fn execute_command_deleted() {
+    let mut storage = InMemoryEventStorage::<TestAggregate>::new();
+    let id = Id::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.delete(id, DeleteMode::Purge).unwrap();
+
+    assert_eq!(
+        storage.execute_command(id, TestCommand::Increase),
+        Err(ExecuteCommandError::Deleted)
+    );
+    assert_eq!(
+        storage.replay_aggregate(id).unwrap_err(),
+        ReplayAggregateError::Deleted
+    );
+    assert_eq!(
+        storage.append(id, Version(0), vec![event(1)]),
+        Err(AppendError::Deleted)
+    );
+    assert!(!storage.is_deleted(Id::new()).unwrap());
+}
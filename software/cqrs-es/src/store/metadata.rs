@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Eventに付随する、いつ・誰が・何をきっかけに記録したかの情報
+///
+/// # Examples
+///
+/// ```
+/// use cqrs_es::store::Metadata;
+/// let metadata = Metadata::new()
+///     .with_actor("nisshiee")
+///     .with_header("source", "console");
+/// assert_eq!(metadata.actor, Some("nisshiee".to_owned()));
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    /// Eventが記録された日時
+    pub recorded_at: Option<DateTime<Utc>>,
+    /// コマンドを実行した主体
+    pub actor: Option<String>,
+    /// 一連の処理を通して共有されるID
+    pub correlation_id: Option<Uuid>,
+    /// このEventを引き起こしたコマンドやEventのID
+    pub causation_id: Option<Uuid>,
+    /// コマンドの発行元が付与する、同じコマンドの重複実行を防ぐためのキー
+    pub idempotency_key: Option<String>,
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    pub fn new() -> Metadata {
+        Metadata::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    pub fn with_recorded_at(mut self, recorded_at: DateTime<Utc>) -> Metadata {
+        self.recorded_at = Some(recorded_at);
+        self
+    }
+
+    pub fn with_actor<S: Into<String>>(mut self, actor: S) -> Metadata {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    pub fn with_correlation_id(mut self, correlation_id: Uuid) -> Metadata {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
+    pub fn with_causation_id(mut self, causation_id: Uuid) -> Metadata {
+        self.causation_id = Some(causation_id);
+        self
+    }
+
+    pub fn with_idempotency_key<S: Into<String>>(mut self, idempotency_key: S) -> Metadata {
+        self.idempotency_key = Some(idempotency_key.into());
+        self
+    }
+
+    pub fn with_header<K, V>(mut self, key: K, value: V) -> Metadata
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+}
@@ -0,0 +1,95 @@
+use std::thread;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+/// 他の書き込みと競合したときに実行し直す回数と間隔
+///
+/// `execute_command_with_retry` と、CommandBusの `Retry` ミドルウェアで使う
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use cqrs_es::store::retry::{Backoff, RetryPolicy};
+/// let policy = RetryPolicy::new(3, Backoff::Fixed(Duration::from_millis(10)));
+/// assert!(policy.should_retry(2));
+/// assert!(!policy.should_retry(3));
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// 最初の実行を含めた、実行回数の上限
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+}
+
+impl RetryPolicy {
+    pub const fn new(max_attempts: u32, backoff: Backoff) -> Self {
+        RetryPolicy {
+            max_attempts,
+            backoff,
+        }
+    }
+
+    /// 実行し直さない
+    pub fn never() -> Self {
+        RetryPolicy::new(1, Backoff::Fixed(Duration::from_millis(0)))
+    }
+
+    /// `attempts` 回実行して競合したときに、もう一度実行すべきかどうか
+    pub fn should_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// `f` を実行し、 `is_conflict` が `true` を返すエラーの場合のみ、待ってから実行し直す
+    pub fn run<T, E, F, P>(&self, mut f: F, is_conflict: P) -> Result<T, E>
+    where
+        F: FnMut() -> Result<T, E>,
+        P: Fn(&E) -> bool,
+    {
+        let mut attempts = 1;
+        loop {
+            match f() {
+                Err(ref e) if is_conflict(e) && self.should_retry(attempts) => {
+                    thread::sleep(self.backoff.delay(attempts));
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::never()
+    }
+}
+
+/// 実行し直すまでの待ち時間
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Backoff {
+    Fixed(Duration),
+    /// `initial` から1回ごとに倍にし、 `max` で頭打ちにする
+    Exponential {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// `attempts` 回実行して競合したあと、次の実行までに待つ時間
+    pub fn delay(&self, attempts: u32) -> Duration {
+        match *self {
+            Backoff::Fixed(interval) => interval,
+            Backoff::Exponential { initial, max } => {
+                let factor = 1u32.checked_shl(attempts.saturating_sub(1)).unwrap_or(0);
+                match initial.checked_mul(factor) {
+                    Some(delay) if factor > 0 && delay < max => delay,
+                    _ => max,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use super::*;
+
+#[test]
+fn never_does_not_retry() {
+    assert!(!RetryPolicy::never().should_retry(1));
+    assert_eq!(RetryPolicy::default(), RetryPolicy::never());
+}
+
+#[test]
+fn fixed_delay() {
+    let backoff = Backoff::Fixed(Duration::from_millis(10));
+    assert_eq!(backoff.delay(1), Duration::from_millis(10));
+    assert_eq!(backoff.delay(5), Duration::from_millis(10));
+}
+
+#[test]
+fn exponential_delay() {
+    let backoff = Backoff::Exponential {
+        initial: Duration::from_millis(10),
+        max: Duration::from_millis(50),
+    };
+    assert_eq!(backoff.delay(1), Duration::from_millis(10));
+    assert_eq!(backoff.delay(2), Duration::from_millis(20));
+    assert_eq!(backoff.delay(3), Duration::from_millis(40));
+    assert_eq!(backoff.delay(4), Duration::from_millis(50));
+    assert_eq!(backoff.delay(100), Duration::from_millis(50));
+}
+
+#[test]
+fn run_retries_only_conflicts() {
+    let policy = RetryPolicy::new(3, Backoff::Fixed(Duration::from_millis(0)));
+
+    let mut attempts = 0;
+    let got: Result<(), &str> = policy.run(
+        || {
+            attempts += 1;
+            Err("conflict")
+        },
+        |e| *e == "conflict",
+    );
+    assert_eq!(got, Err("conflict"));
+    assert_eq!(attempts, 3);
+
+    let mut attempts = 0;
+    let got: Result<(), &str> = policy.run(
+        || {
+            attempts += 1;
+            Err("other")
+        },
+        |e| *e == "conflict",
+    );
+    assert_eq!(got, Err("other"));
+    assert_eq!(attempts, 1);
+}
@@ -1,41 +1,93 @@
-use serde::de::MapAccess;
+use serde::de::{Error as _, IgnoredAny, MapAccess};
 use serde::Deserialize;
+use serde_json::Value;
 
-use super::Version;
+use super::upcaster::{EventSchema, INITIAL_SCHEMA_VERSION};
+use super::{Metadata, Version};
 
 pub const SERIALIZE_KEY_VERSION: &str = "version";
+pub const SERIALIZE_KEY_SCHEMA_VERSION: &str = "schema_version";
 pub const SERIALIZE_KEY_AGGREGATE: &str = "aggregate";
 pub const SERIALIZE_KEY_EVENT: &str = "event";
+pub const SERIALIZE_KEY_METADATA: &str = "metadata";
 
+/// `schema` が与えられた場合、保存時のスキーマバージョンが現在と異なれば
+/// `base_key` の値を一度 `Value` として読み込み、Upcasterで変換してから `B` にデシリアライズする
+///
+/// `Value` としての読み込みは自己記述的な形式でしか行えないため、bincodeなどで保存された古いスキーマバージョンのEventはエラーとなる
 pub fn visit_versioned<'de, B, V, F, M>(
     mut map: M,
     base_key: &'static str,
+    schema: Option<EventSchema>,
     f: F,
 ) -> Result<V, M::Error>
 where
     B: Deserialize<'de>,
-    F: (FnOnce(Version, B) -> V),
+    F: (FnOnce(Version, B, Metadata) -> V),
     M: MapAccess<'de>,
 {
     let mut version = None;
+    let mut schema_version = None;
     let mut base = None;
+    let mut raw_base = None;
+    let mut metadata = None;
 
     while let Some(key) = map.next_key::<String>()? {
         if key == SERIALIZE_KEY_VERSION {
             if version.is_some() {
-                return Err(serde::de::Error::duplicate_field(SERIALIZE_KEY_VERSION));
+                return Err(M::Error::duplicate_field(SERIALIZE_KEY_VERSION));
             }
             version = Some(map.next_value()?);
+        } else if key == SERIALIZE_KEY_SCHEMA_VERSION {
+            if schema_version.is_some() {
+                return Err(M::Error::duplicate_field(SERIALIZE_KEY_SCHEMA_VERSION));
+            }
+            schema_version = Some(map.next_value::<u32>()?);
         } else if key == base_key {
-            if base.is_some() {
-                return Err(serde::de::Error::duplicate_field(base_key));
+            if base.is_some() || raw_base.is_some() {
+                return Err(M::Error::duplicate_field(base_key));
+            }
+            match schema {
+                Some(schema)
+                    if schema_version.unwrap_or(INITIAL_SCHEMA_VERSION) != schema.version =>
+                {
+                    raw_base = Some(map.next_value::<Value>()?)
+                }
+                _ => base = Some(map.next_value()?),
+            }
+        } else if key == SERIALIZE_KEY_METADATA {
+            if metadata.is_some() {
+                return Err(M::Error::duplicate_field(SERIALIZE_KEY_METADATA));
             }
-            base = Some(map.next_value()?);
+            metadata = Some(map.next_value()?);
+        } else {
+            map.next_value::<IgnoredAny>()?;
         }
     }
 
-    let version = version.ok_or_else(|| serde::de::Error::missing_field(SERIALIZE_KEY_VERSION))?;
-    let base = base.ok_or_else(|| serde::de::Error::missing_field(base_key))?;
+    let version = version.ok_or_else(|| M::Error::missing_field(SERIALIZE_KEY_VERSION))?;
+    let base = match (base, raw_base, schema) {
+        (Some(_), _, Some(schema))
+            if schema_version.unwrap_or(INITIAL_SCHEMA_VERSION) != schema.version =>
+        {
+            // スキーマバージョンが値より後ろに書かれていた場合
+            return Err(M::Error::custom(format!(
+                "{} must precede {}",
+                SERIALIZE_KEY_SCHEMA_VERSION, base_key
+            )));
+        }
+        (Some(base), _, _) => base,
+        (None, Some(raw_base), Some(schema)) => {
+            let stored = schema_version.unwrap_or(INITIAL_SCHEMA_VERSION);
+            let raw_base = (schema.upcasters)()
+                .upcast(raw_base, stored, schema.version)
+                .map_err(M::Error::custom)?;
+            B::deserialize(raw_base).map_err(M::Error::custom)?
+        }
+        _ => return Err(M::Error::missing_field(base_key)),
+    };
+    // metadataを持たない形式で書き込まれたものも読めるようにする
+    let metadata = metadata.unwrap_or_default();
 
-    Ok(f(version, base))
+    Ok(f(version, base, metadata))
 }
@@ -0,0 +1,47 @@
+use crate::store::{EventStorageError, Version, VersionedAggregate};
+use crate::{Aggregate, Id};
+
+#[cfg(test)]
+mod tests;
+
+/// Replayの起点となる、シリアライズされたAggregateの保存先
+pub trait SnapshotStore<A: Aggregate> {
+    type Error: EventStorageError;
+
+    fn load(&self, id: Id<A>) -> Result<Option<VersionedAggregate<A>>, Self::Error>;
+
+    fn save(&mut self, id: Id<A>, aggregate: &VersionedAggregate<A>) -> Result<(), Self::Error>;
+
+    /// 保存されていない場合も成功とする
+    fn delete(&mut self, id: Id<A>) -> Result<(), Self::Error>;
+}
+
+/// スナップショットを保存するタイミング
+///
+/// # Examples
+///
+/// ```
+/// use cqrs_es::store::snapshot::SnapshotPolicy;
+/// use cqrs_es::store::Version;
+/// let policy = SnapshotPolicy::Every(10);
+/// assert!(policy.is_due(Version(9), Version(10)));
+/// assert!(!policy.is_due(Version(10), Version(11)));
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum SnapshotPolicy {
+    #[default]
+    Never,
+    /// バージョンがNの倍数をまたいだときに保存する
+    Every(u64),
+}
+
+impl SnapshotPolicy {
+    /// `previous` から `current` までEventを適用したときに、スナップショットを保存すべきかどうか
+    pub fn is_due(&self, previous: Version, current: Version) -> bool {
+        match *self {
+            SnapshotPolicy::Never => false,
+            SnapshotPolicy::Every(0) => false,
+            SnapshotPolicy::Every(n) => current.0 / n > previous.0 / n,
+        }
+    }
+}
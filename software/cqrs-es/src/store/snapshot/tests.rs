@@ -0,0 +1,27 @@
+use crate::store::Version;
+
+use super::SnapshotPolicy;
+
+#[test]
+fn never_is_not_due() {
+    assert!(!SnapshotPolicy::Never.is_due(Version(0), Version(100)));
+}
+
+#[test]
+fn every_is_due_on_multiple() {
+    let policy = SnapshotPolicy::Every(3);
+    assert!(!policy.is_due(Version(0), Version(2)));
+    assert!(policy.is_due(Version(2), Version(3)));
+    assert!(!policy.is_due(Version(3), Version(5)));
+}
+
+#[test]
+fn every_is_due_when_multiple_is_skipped() {
+    let policy = SnapshotPolicy::Every(3);
+    assert!(policy.is_due(Version(5), Version(7)));
+}
+
+#[test]
+fn every_zero_is_never_due() {
+    assert!(!SnapshotPolicy::Every(0).is_due(Version(0), Version(100)));
+}
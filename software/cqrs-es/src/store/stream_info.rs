@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+
+use crate::store::Version;
+use crate::{Aggregate, Id};
+
+#[cfg(test)]
+mod tests;
+
+/// EventStorageが保持しているストリームの情報
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo<A: Aggregate> {
+    pub id: Id<A>,
+    pub last_version: Version,
+    /// 最後にEventが追加された日時 (記録されていなければ `None` )
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// 一覧を取得する範囲
+///
+/// # Examples
+///
+/// ```
+/// use cqrs_es::store::stream_info::Paging;
+/// let got: Vec<_> = Paging::new(2, 3).apply(0..10).collect();
+/// assert_eq!(got, vec![2, 3, 4]);
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct Paging {
+    pub offset: usize,
+    /// `None` の場合は `offset` 以降をすべて取得する
+    pub limit: Option<usize>,
+}
+
+impl Paging {
+    pub fn new(offset: usize, limit: usize) -> Paging {
+        Paging {
+            offset,
+            limit: Some(limit),
+        }
+    }
+
+    pub fn all() -> Paging {
+        Paging::default()
+    }
+
+    /// 並び順の定まった要素から、範囲内のものを取り出す
+    pub fn apply<I>(&self, items: I) -> impl Iterator<Item = I::Item>
+    where
+        I: IntoIterator,
+    {
+        items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+    }
+}
@@ -0,0 +1,34 @@
+use crate::store::stream_info::*;
+
+#[test]
+fn apply_all() {
+    let got: Vec<_> = Paging::all().apply(0..5).collect();
+
+    assert_eq!(got, vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn apply_limit() {
+    let got: Vec<_> = Paging::new(0, 2).apply(0..5).collect();
+
+    assert_eq!(got, vec![0, 1]);
+}
+
+#[test]
+fn apply_offset_without_limit() {
+    let paging = Paging {
+        offset: 3,
+        limit: None,
+    };
+
+    let got: Vec<_> = paging.apply(0..5).collect();
+
+    assert_eq!(got, vec![3, 4]);
+}
+
+#[test]
+fn apply_out_of_range() {
+    let got: Vec<_> = Paging::new(5, 2).apply(0..5).collect();
+
+    assert!(got.is_empty());
+}
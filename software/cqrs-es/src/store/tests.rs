@@ -1,5 +1,10 @@
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
 use simulacrum::*;
 
+use crate::store::retry::Backoff;
+use crate::store::snapshot::SnapshotPolicy;
 use crate::store::*;
 use crate::tests::test_aggregate::*;
 use crate::Id;
@@ -31,6 +36,10 @@ impl EventStorage<TestAggregate> for MockEventStorage1 {
     fn read(&self, id: Id<TestAggregate>) -> Result<Self::Events, Self::Error> {
         was_called!(self, "read", (id: Id<TestAggregate>) -> Result<Self::Events, Self::Error>)
     }
+
+    fn list_ids(&self, _paging: Paging) -> Result<Vec<StreamInfo<TestAggregate>>, Self::Error> {
+        unimplemented!()
+    }
 }
 
 #[test]
@@ -41,10 +50,12 @@ fn replay_aggregate() {
             VersionedEvent {
                 version: Version(1),
                 event: TestEvent::Increased,
+                metadata: Metadata::new(),
             },
             VersionedEvent {
                 version: Version(2),
                 event: TestEvent::Increased,
+                metadata: Metadata::new(),
             },
         ])
     });
@@ -77,6 +88,7 @@ fn replay_aggregate_version_inconsistent() {
         Ok(vec![VersionedEvent {
             version: Version(99),
             event: TestEvent::Increased,
+            metadata: Metadata::new(),
         }])
     });
 
@@ -88,13 +100,13 @@ fn replay_aggregate_version_inconsistent() {
 }
 
 create_mock_struct! {
-    struct MockEventStorage22: {
-        expect_replay_aggregate("replay_aggregate") Id<TestAggregate> => Result<VersionedAggregate<TestAggregate>, ReplayAggregateError<MockStorageError>>;
+    struct MockEventStorage21: {
+        expect_read("read") Id<TestAggregate> => Result<Vec<VersionedEvent<TestAggregate>>, MockStorageError>;
         expect_insert("insert") (Id<TestAggregate>, VersionedEvent<TestAggregate>) => Result<(), MockStorageError>;
     }
 }
 
-impl EventStorage<TestAggregate> for MockEventStorage22 {
+impl EventStorage<TestAggregate> for MockEventStorage21 {
     type Events = Vec<VersionedEvent<TestAggregate>>;
     type Error = MockStorageError;
 
@@ -106,10 +118,129 @@ impl EventStorage<TestAggregate> for MockEventStorage22 {
         was_called!(self, "insert", (id: Id<TestAggregate>, event: VersionedEvent<TestAggregate>) -> Result<(), MockStorageError>)
     }
 
+    fn read(&self, id: Id<TestAggregate>) -> Result<Self::Events, Self::Error> {
+        was_called!(self, "read", (id: Id<TestAggregate>) -> Result<Self::Events, Self::Error>)
+    }
+
+    fn list_ids(&self, _paging: Paging) -> Result<Vec<StreamInfo<TestAggregate>>, Self::Error> {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn append() {
+    let mut storage = MockEventStorage21::new();
+    storage.expect_read().called_once().returning(|_| {
+        Ok(vec![VersionedEvent {
+            version: Version(1),
+            event: TestEvent::Increased,
+            metadata: Metadata::new(),
+        }])
+    });
+    storage
+        .expect_insert()
+        .called_times(2)
+        .returning(|_| Ok(()));
+
+    let id = Id::new();
+    let events = vec![
+        VersionedEvent {
+            version: Version(2),
+            event: TestEvent::Increased,
+            metadata: Metadata::new(),
+        },
+        VersionedEvent {
+            version: Version(3),
+            event: TestEvent::Increased,
+            metadata: Metadata::new(),
+        },
+    ];
+
+    assert_eq!(storage.append(id, Version(1), events), Ok(()));
+}
+
+#[test]
+fn append_empty() {
+    let mut storage = MockEventStorage21::new();
+    storage.expect_read().called_never();
+    storage.expect_insert().called_never();
+
+    let id = Id::new();
+
+    assert_eq!(storage.append(id, Version(1), Vec::new()), Ok(()));
+}
+
+#[test]
+fn append_concurrency_conflict() {
+    let mut storage = MockEventStorage21::new();
+    storage.expect_read().called_once().returning(|_| {
+        Ok(vec![
+            VersionedEvent {
+                version: Version(1),
+                event: TestEvent::Increased,
+                metadata: Metadata::new(),
+            },
+            VersionedEvent {
+                version: Version(2),
+                event: TestEvent::Increased,
+                metadata: Metadata::new(),
+            },
+        ])
+    });
+    storage.expect_insert().called_never();
+
+    let id = Id::new();
+    let events = vec![VersionedEvent {
+        version: Version(2),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    }];
+
+    assert_eq!(
+        storage.append(id, Version(1), events),
+        Err(AppendError::ConcurrencyConflict {
+            expected: Version(1),
+            actual: Version(2),
+        })
+    );
+}
+
+create_mock_struct! {
+    struct MockEventStorage22: {
+        expect_replay_aggregate("replay_aggregate") Id<TestAggregate> => Result<VersionedAggregate<TestAggregate>, ReplayAggregateError<MockStorageError>>;
+        expect_append("append") (Id<TestAggregate>, Version, Vec<VersionedEvent<TestAggregate>>) => Result<(), AppendError<MockStorageError>>;
+    }
+}
+
+impl EventStorage<TestAggregate> for MockEventStorage22 {
+    type Events = Vec<VersionedEvent<TestAggregate>>;
+    type Error = MockStorageError;
+
+    fn insert(
+        &mut self,
+        _id: Id<TestAggregate>,
+        _event: VersionedEvent<TestAggregate>,
+    ) -> Result<(), Self::Error> {
+        unimplemented!()
+    }
+
+    fn append(
+        &mut self,
+        id: Id<TestAggregate>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<TestAggregate>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        was_called!(self, "append", (id: Id<TestAggregate>, expected_version: Version, events: Vec<VersionedEvent<TestAggregate>>) -> Result<(), AppendError<MockStorageError>>)
+    }
+
     fn read(&self, _id: Id<TestAggregate>) -> Result<Self::Events, Self::Error> {
         unimplemented!()
     }
 
+    fn list_ids(&self, _paging: Paging) -> Result<Vec<StreamInfo<TestAggregate>>, Self::Error> {
+        unimplemented!()
+    }
+
     fn replay_aggregate(
         &self,
         id: Id<TestAggregate>,
@@ -135,11 +266,18 @@ fn execute_command() {
                 aggregate: TestAggregate(1),
             })
         });
-    let version_check = passes::<(Id<TestAggregate>, VersionedEvent<TestAggregate>), _>(|event| {
-        event.1.version.is_next_of(&Version(1))
+    let version_check = passes::<
+        (
+            Id<TestAggregate>,
+            Version,
+            Vec<VersionedEvent<TestAggregate>>,
+        ),
+        _,
+    >(|append| {
+        append.1 == Version(1) && append.2.len() == 1 && append.2[0].version.is_next_of(&Version(1))
     });
     storage
-        .expect_insert()
+        .expect_append()
         .called_once()
         .with(version_check)
         .returning(|_| Ok(()));
@@ -151,6 +289,43 @@ fn execute_command() {
     assert_eq!(got, Ok(()))
 }
 
+#[test]
+fn execute_command_with_metadata() {
+    let mut storage = MockEventStorage22::new();
+    storage
+        .expect_replay_aggregate()
+        .called_once()
+        .returning(|_| {
+            Ok(VersionedAggregate {
+                version: Version(1),
+                aggregate: TestAggregate(1),
+            })
+        });
+    let metadata_check = passes::<
+        (
+            Id<TestAggregate>,
+            Version,
+            Vec<VersionedEvent<TestAggregate>>,
+        ),
+        _,
+    >(|append| {
+        let metadata = &append.2[0].metadata;
+        metadata.actor == Some("nisshiee".to_owned()) && metadata.recorded_at.is_some()
+    });
+    storage
+        .expect_append()
+        .called_once()
+        .with(metadata_check)
+        .returning(|_| Ok(()));
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+    let metadata = Metadata::new().with_actor("nisshiee");
+
+    let got = storage.execute_command_with_metadata(id, cmd, metadata);
+    assert_eq!(got, Ok(()))
+}
+
 #[test]
 fn execute_command_replay_aggregate_error() {
     let mut storage = MockEventStorage22::new();
@@ -205,9 +380,9 @@ fn execute_command_insert_error() {
             })
         });
     storage
-        .expect_insert()
+        .expect_append()
         .called_once()
-        .returning(|_| Err(MockStorageError {}));
+        .returning(|_| Err(AppendError::Storage(MockStorageError {})));
 
     let id = Id::new();
     let cmd = TestCommand::Increase;
@@ -218,3 +393,479 @@ fn execute_command_insert_error() {
         panic!();
     }
 }
+
+#[test]
+fn execute_command_concurrency_conflict() {
+    let mut storage = MockEventStorage22::new();
+    storage
+        .expect_replay_aggregate()
+        .called_once()
+        .returning(|_| {
+            Ok(VersionedAggregate {
+                version: Version(1),
+                aggregate: TestAggregate(1),
+            })
+        });
+    storage.expect_append().called_once().returning(|_| {
+        Err(AppendError::ConcurrencyConflict {
+            expected: Version(1),
+            actual: Version(2),
+        })
+    });
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+
+    let got = storage.execute_command(id, cmd);
+    assert_eq!(
+        got,
+        Err(ExecuteCommandError::ConcurrencyConflict {
+            expected: Version(1),
+            actual: Version(2),
+        })
+    );
+}
+
+#[test]
+fn execute_command_with_retry() {
+    let mut storage = MockEventStorage22::new();
+    let mut replayed = 0;
+    storage
+        .expect_replay_aggregate()
+        .called_times(2)
+        .returning(move |_| {
+            replayed += 1;
+            Ok(VersionedAggregate {
+                version: Version(replayed),
+                aggregate: TestAggregate(replayed),
+            })
+        });
+    storage
+        .expect_append()
+        .called_times(2)
+        .returning(|(_, expected_version, _)| {
+            if *expected_version == Version(1) {
+                Err(AppendError::ConcurrencyConflict {
+                    expected: Version(1),
+                    actual: Version(2),
+                })
+            } else {
+                Ok(())
+            }
+        });
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+    let policy = RetryPolicy::new(3, Backoff::Fixed(Duration::from_millis(0)));
+
+    let got = storage.execute_command_with_retry(id, cmd, policy);
+    assert_eq!(got, Ok(()));
+}
+
+#[test]
+fn execute_command_with_metadata_and_retry() {
+    let mut storage = MockEventStorage22::new();
+    let mut replayed = 0;
+    storage
+        .expect_replay_aggregate()
+        .called_times(2)
+        .returning(move |_| {
+            replayed += 1;
+            Ok(VersionedAggregate {
+                version: Version(replayed),
+                aggregate: TestAggregate(replayed),
+            })
+        });
+    storage
+        .expect_append()
+        .called_times(2)
+        .returning(|(_, expected_version, events)| {
+            assert!(events
+                .iter()
+                .all(|e| e.metadata.actor.as_deref() == Some("alice")));
+            if *expected_version == Version(1) {
+                Err(AppendError::ConcurrencyConflict {
+                    expected: Version(1),
+                    actual: Version(2),
+                })
+            } else {
+                Ok(())
+            }
+        });
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+    let metadata = Metadata::new().with_actor("alice");
+    let policy = RetryPolicy::new(3, Backoff::Fixed(Duration::from_millis(0)));
+
+    let got = storage.execute_command_with_metadata_and_retry(id, cmd, metadata, policy);
+    assert_eq!(got, Ok(()));
+}
+
+#[test]
+fn execute_command_with_retry_gives_up() {
+    let mut storage = MockEventStorage22::new();
+    storage
+        .expect_replay_aggregate()
+        .called_times(2)
+        .returning(|_| {
+            Ok(VersionedAggregate {
+                version: Version(1),
+                aggregate: TestAggregate(1),
+            })
+        });
+    storage.expect_append().called_times(2).returning(|_| {
+        Err(AppendError::ConcurrencyConflict {
+            expected: Version(1),
+            actual: Version(2),
+        })
+    });
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+    let policy = RetryPolicy::new(2, Backoff::Fixed(Duration::from_millis(0)));
+
+    let got = storage.execute_command_with_retry(id, cmd, policy);
+    assert_eq!(
+        got,
+        Err(ExecuteCommandError::ConcurrencyConflict {
+            expected: Version(1),
+            actual: Version(2),
+        })
+    );
+}
+
+#[test]
+fn execute_command_with_retry_command_error() {
+    let mut storage = MockEventStorage22::new();
+    storage
+        .expect_replay_aggregate()
+        .called_once()
+        .returning(|_| {
+            Ok(VersionedAggregate {
+                version: Version(1),
+                aggregate: TestAggregate(1),
+            })
+        });
+    storage.expect_append().called_never();
+
+    let id = Id::new();
+    let cmd = TestCommand::Invalid;
+    let policy = RetryPolicy::new(3, Backoff::Fixed(Duration::from_millis(0)));
+
+    let got = storage.execute_command_with_retry(id, cmd, policy);
+    assert_eq!(
+        got,
+        Err(ExecuteCommandError::Command(TestCommandError::Invalid))
+    );
+}
+
+create_mock_struct! {
+    struct MockEventStorage3: {
+        expect_read("read") Id<TestAggregate> => Result<Vec<VersionedEvent<TestAggregate>>, MockStorageError>;
+        expect_load_snapshot("load_snapshot") Id<TestAggregate> => Result<Option<VersionedAggregate<TestAggregate>>, MockStorageError>;
+    }
+}
+
+impl EventStorage<TestAggregate> for MockEventStorage3 {
+    type Events = Vec<VersionedEvent<TestAggregate>>;
+    type Error = MockStorageError;
+
+    fn insert(
+        &mut self,
+        _id: Id<TestAggregate>,
+        _event: VersionedEvent<TestAggregate>,
+    ) -> Result<(), Self::Error> {
+        unimplemented!()
+    }
+
+    fn read(&self, id: Id<TestAggregate>) -> Result<Self::Events, Self::Error> {
+        was_called!(self, "read", (id: Id<TestAggregate>) -> Result<Self::Events, Self::Error>)
+    }
+
+    fn list_ids(&self, _paging: Paging) -> Result<Vec<StreamInfo<TestAggregate>>, Self::Error> {
+        unimplemented!()
+    }
+
+    fn load_snapshot(
+        &self,
+        id: Id<TestAggregate>,
+    ) -> Result<Option<VersionedAggregate<TestAggregate>>, Self::Error> {
+        was_called!(
+            self,
+            "load_snapshot",
+            (id: Id<TestAggregate>) -> Result<Option<VersionedAggregate<TestAggregate>>, MockStorageError>
+        )
+    }
+}
+
+#[test]
+fn replay_aggregate_from_snapshot() {
+    let mut storage = MockEventStorage3::new();
+    storage.expect_load_snapshot().called_once().returning(|_| {
+        Ok(Some(VersionedAggregate {
+            version: Version(2),
+            aggregate: TestAggregate(10),
+        }))
+    });
+    storage.expect_read().called_once().returning(|_| {
+        Ok(vec![
+            VersionedEvent {
+                version: Version(1),
+                event: TestEvent::Increased,
+                metadata: Metadata::new(),
+            },
+            VersionedEvent {
+                version: Version(2),
+                event: TestEvent::Increased,
+                metadata: Metadata::new(),
+            },
+            VersionedEvent {
+                version: Version(3),
+                event: TestEvent::Increased,
+                metadata: Metadata::new(),
+            },
+        ])
+    });
+
+    let id = Id::new();
+    let got = storage.replay_aggregate(id).unwrap();
+
+    assert_eq!(got.version, Version(3));
+    assert_eq!(got.aggregate, TestAggregate(11));
+}
+
+#[test]
+fn replay_aggregate_snapshot_error() {
+    let mut storage = MockEventStorage3::new();
+    storage
+        .expect_load_snapshot()
+        .called_once()
+        .returning(|_| Err(MockStorageError {}));
+
+    let id = Id::new();
+    if let Err(ReplayAggregateError::Read(_)) = storage.replay_aggregate(id) {
+    } else {
+        panic!();
+    }
+}
+
+create_mock_struct! {
+    struct MockEventStorage4: {
+        expect_replay_aggregate("replay_aggregate") Id<TestAggregate> => Result<VersionedAggregate<TestAggregate>, ReplayAggregateError<MockStorageError>>;
+        expect_append("append") (Id<TestAggregate>, Version, Vec<VersionedEvent<TestAggregate>>) => Result<(), AppendError<MockStorageError>>;
+        expect_save_snapshot("save_snapshot") (Id<TestAggregate>, VersionedAggregate<TestAggregate>) => Result<(), MockStorageError>;
+    }
+}
+
+impl EventStorage<TestAggregate> for MockEventStorage4 {
+    type Events = Vec<VersionedEvent<TestAggregate>>;
+    type Error = MockStorageError;
+
+    fn insert(
+        &mut self,
+        _id: Id<TestAggregate>,
+        _event: VersionedEvent<TestAggregate>,
+    ) -> Result<(), Self::Error> {
+        unimplemented!()
+    }
+
+    fn append(
+        &mut self,
+        id: Id<TestAggregate>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<TestAggregate>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        was_called!(self, "append", (id: Id<TestAggregate>, expected_version: Version, events: Vec<VersionedEvent<TestAggregate>>) -> Result<(), AppendError<MockStorageError>>)
+    }
+
+    fn read(&self, _id: Id<TestAggregate>) -> Result<Self::Events, Self::Error> {
+        unimplemented!()
+    }
+
+    fn list_ids(&self, _paging: Paging) -> Result<Vec<StreamInfo<TestAggregate>>, Self::Error> {
+        unimplemented!()
+    }
+
+    fn snapshot_policy(&self) -> SnapshotPolicy {
+        SnapshotPolicy::Every(2)
+    }
+
+    fn save_snapshot(
+        &mut self,
+        id: Id<TestAggregate>,
+        aggregate: &VersionedAggregate<TestAggregate>,
+    ) -> Result<(), Self::Error> {
+        let aggregate = aggregate.clone();
+        was_called!(self, "save_snapshot", (id: Id<TestAggregate>, aggregate: VersionedAggregate<TestAggregate>) -> Result<(), MockStorageError>)
+    }
+
+    fn replay_aggregate(
+        &self,
+        id: Id<TestAggregate>,
+    ) -> Result<VersionedAggregate<TestAggregate>, ReplayAggregateError<MockStorageError>> {
+        was_called!(
+            self,
+            "replay_aggregate",
+            (id: Id<TestAggregate>) ->
+                Result<VersionedAggregate<TestAggregate>, ReplayAggregateError<MockStorageError>>
+        )
+    }
+}
+
+#[test]
+fn execute_command_save_snapshot() {
+    let mut storage = MockEventStorage4::new();
+    storage
+        .expect_replay_aggregate()
+        .called_once()
+        .returning(|_| {
+            Ok(VersionedAggregate {
+                version: Version(1),
+                aggregate: TestAggregate(1),
+            })
+        });
+    storage.expect_append().called_once().returning(|_| Ok(()));
+    let snapshot_check =
+        passes::<(Id<TestAggregate>, VersionedAggregate<TestAggregate>), _>(|(_, snapshot)| {
+            snapshot.version == Version(2) && snapshot.aggregate == TestAggregate(2)
+        });
+    storage
+        .expect_save_snapshot()
+        .called_once()
+        .with(snapshot_check)
+        .returning(|_| Ok(()));
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+
+    let got = storage.execute_command(id, cmd);
+    assert_eq!(got, Ok(()))
+}
+
+#[test]
+fn execute_command_skip_snapshot() {
+    let mut storage = MockEventStorage4::new();
+    storage
+        .expect_replay_aggregate()
+        .called_once()
+        .returning(|_| {
+            Ok(VersionedAggregate {
+                version: Version(2),
+                aggregate: TestAggregate(2),
+            })
+        });
+    storage.expect_append().called_once().returning(|_| Ok(()));
+    storage.expect_save_snapshot().called_never();
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+
+    let got = storage.execute_command(id, cmd);
+    assert_eq!(got, Ok(()))
+}
+
+#[test]
+fn execute_command_save_snapshot_error() {
+    let mut storage = MockEventStorage4::new();
+    storage
+        .expect_replay_aggregate()
+        .called_once()
+        .returning(|_| {
+            Ok(VersionedAggregate {
+                version: Version(1),
+                aggregate: TestAggregate(1),
+            })
+        });
+    storage.expect_append().called_once().returning(|_| Ok(()));
+    storage
+        .expect_save_snapshot()
+        .called_once()
+        .returning(|_| Err(MockStorageError {}));
+
+    let id = Id::new();
+    let cmd = TestCommand::Increase;
+
+    if let Err(ExecuteCommandError::SaveSnapshot(e)) = storage.execute_command(id, cmd) {
+        assert_eq!(e, MockStorageError {});
+    } else {
+        panic!();
+    }
+}
+
+fn increased_events(recorded_at: &[Option<DateTime<Utc>>]) -> Vec<VersionedEvent<TestAggregate>> {
+    recorded_at
+        .iter()
+        .enumerate()
+        .map(|(i, recorded_at)| VersionedEvent {
+            version: Version(i as u64 + 1),
+            event: TestEvent::Increased,
+            metadata: Metadata {
+                recorded_at: *recorded_at,
+                ..Metadata::new()
+            },
+        })
+        .collect()
+}
+
+#[test]
+fn replay_aggregate_until() {
+    let mut storage = MockEventStorage3::new();
+    storage
+        .expect_load_snapshot()
+        .called_once()
+        .returning(|_| Ok(None));
+    storage
+        .expect_read()
+        .called_once()
+        .returning(|_| Ok(increased_events(&[None, None, None])));
+
+    let id = Id::new();
+    let got = storage.replay_aggregate_until(id, Version(2)).unwrap();
+
+    assert_eq!(got.version, Version(2));
+    assert_eq!(got.aggregate, TestAggregate(2));
+}
+
+#[test]
+fn replay_aggregate_until_ignores_newer_snapshot() {
+    let mut storage = MockEventStorage3::new();
+    storage.expect_load_snapshot().called_once().returning(|_| {
+        Ok(Some(VersionedAggregate {
+            version: Version(3),
+            aggregate: TestAggregate(10),
+        }))
+    });
+    storage
+        .expect_read()
+        .called_once()
+        .returning(|_| Ok(increased_events(&[None, None, None])));
+
+    let id = Id::new();
+    let got = storage.replay_aggregate_until(id, Version(1)).unwrap();
+
+    assert_eq!(got.version, Version(1));
+    assert_eq!(got.aggregate, TestAggregate(1));
+}
+
+#[test]
+fn replay_aggregate_as_of() {
+    let mut storage = MockEventStorage3::new();
+    storage.expect_load_snapshot().called_never();
+    storage.expect_read().called_once().returning(|_| {
+        Ok(increased_events(&[
+            None,
+            Some(Utc.timestamp_opt(100, 0).unwrap()),
+            Some(Utc.timestamp_opt(200, 0).unwrap()),
+            Some(Utc.timestamp_opt(300, 0).unwrap()),
+        ]))
+    });
+
+    let id = Id::new();
+    let got = storage
+        .replay_aggregate_as_of(id, Utc.timestamp_opt(250, 0).unwrap())
+        .unwrap();
+
+    assert_eq!(got.version, Version(3));
+    assert_eq!(got.aggregate, TestAggregate(3));
+}
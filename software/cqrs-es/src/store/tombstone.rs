@@ -0,0 +1,26 @@
+use crate::store::EventStorage;
+use crate::{Aggregate, Id};
+
+/// ストリームを削除するときに、保存済みのEventをどう扱うか
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DeleteMode {
+    /// Eventを保管しておき、 `read_archived` で読めるようにする
+    Archive,
+    /// Eventを消去する
+    Purge,
+}
+
+/// ストリームの削除に対応したEventStorage
+///
+/// 削除したストリームには削除済みの記録 (tombstone) が残り、
+/// 以降の `read` 、 `insert` 、 `append` は `EventStorageError::is_deleted` が真となるエラーを返す。
+/// そのため、同じIDのストリームを再び作ることはできない
+pub trait DeletableEventStorage<A: Aggregate>: EventStorage<A> {
+    /// 削除済みのストリームを再度削除した場合は、 `mode` に従ってEventを扱い直す
+    fn delete(&mut self, id: Id<A>, mode: DeleteMode) -> Result<(), Self::Error>;
+
+    fn is_deleted(&self, id: Id<A>) -> Result<bool, Self::Error>;
+
+    /// `DeleteMode::Archive` で削除したストリームのEventを返す
+    fn read_archived(&self, id: Id<A>) -> Result<Self::Events, Self::Error>;
+}
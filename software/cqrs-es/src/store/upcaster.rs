@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use failure::Fail;
+use serde_json::Value;
+
+#[cfg(test)]
+mod tests;
+
+/// スキーマバージョンを持たずに保存されたEventのスキーマバージョン
+pub const INITIAL_SCHEMA_VERSION: u32 = 1;
+
+/// シリアライズされたEventを、ひとつ新しいスキーマバージョンの形式に変換する
+pub type Upcaster = Box<dyn Fn(Value) -> Result<Value, String>>;
+
+/// 変換元のスキーマバージョンごとに登録されたUpcaster
+///
+/// # Examples
+///
+/// ```
+/// use cqrs_es::store::upcaster::Upcasters;
+/// use serde_json::json;
+/// let upcasters = Upcasters::new().register(1, |_| Ok(json!({ "Increased": { "amount": 1 } })));
+/// let got = upcasters.upcast(json!("Increased"), 1, 2).unwrap();
+/// assert_eq!(got, json!({ "Increased": { "amount": 1 } }));
+/// ```
+#[derive(Default)]
+pub struct Upcasters {
+    upcasters: BTreeMap<u32, Upcaster>,
+}
+
+impl Upcasters {
+    pub fn new() -> Upcasters {
+        Upcasters::default()
+    }
+
+    /// スキーマバージョン `from` のEventを `from + 1` に変換するUpcasterを登録する
+    pub fn register<F>(mut self, from: u32, upcaster: F) -> Upcasters
+    where
+        F: Fn(Value) -> Result<Value, String> + 'static,
+    {
+        self.upcasters.insert(from, Box::new(upcaster));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upcasters.is_empty()
+    }
+
+    /// 登録されたUpcasterを順に適用し、スキーマバージョン `from` から `to` に変換する
+    pub fn upcast(&self, event: Value, from: u32, to: u32) -> Result<Value, UpcastError> {
+        if from > to {
+            return Err(UpcastError::UnknownSchemaVersion {
+                stored: from,
+                current: to,
+            });
+        }
+        (from..to).try_fold(event, |event, version| {
+            let upcaster = self
+                .upcasters
+                .get(&version)
+                .ok_or(UpcastError::NotRegistered { from: version })?;
+            upcaster(event).map_err(|message| UpcastError::Failed {
+                from: version,
+                message,
+            })
+        })
+    }
+}
+
+/// Eventの現在のスキーマバージョンと、そこに至るまでのUpcaster
+#[derive(Copy, Clone)]
+pub struct EventSchema {
+    pub version: u32,
+    pub upcasters: fn() -> Upcasters,
+}
+
+#[derive(Fail, Debug, Eq, PartialEq)]
+pub enum UpcastError {
+    #[fail(display = "Upcaster from schema version {} is not registered", from)]
+    NotRegistered { from: u32 },
+    #[fail(display = "Upcast from schema version {} failed: {}", from, message)]
+    Failed { from: u32, message: String },
+    #[fail(
+        display = "Stored schema version {} is newer than current {}",
+        stored, current
+    )]
+    UnknownSchemaVersion { stored: u32, current: u32 },
+}
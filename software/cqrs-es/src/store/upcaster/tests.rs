@@ -0,0 +1,52 @@
+use serde_json::json;
+
+use super::{UpcastError, Upcasters};
+
+fn upcasters() -> Upcasters {
+    Upcasters::new()
+        .register(1, |v| Ok(json!({ "v2": v })))
+        .register(2, |v| Ok(json!({ "v3": v })))
+}
+
+#[test]
+fn upcast() {
+    let got = upcasters().upcast(json!("a"), 1, 3);
+    assert_eq!(got, Ok(json!({ "v3": { "v2": "a" } })));
+}
+
+#[test]
+fn upcast_current() {
+    let got = upcasters().upcast(json!("a"), 3, 3);
+    assert_eq!(got, Ok(json!("a")));
+}
+
+#[test]
+fn upcast_not_registered() {
+    let got = upcasters().upcast(json!("a"), 1, 4);
+    assert_eq!(got, Err(UpcastError::NotRegistered { from: 3 }));
+}
+
+#[test]
+fn upcast_failed() {
+    let upcasters = Upcasters::new().register(1, |_| Err("broken".to_owned()));
+    let got = upcasters.upcast(json!("a"), 1, 2);
+    assert_eq!(
+        got,
+        Err(UpcastError::Failed {
+            from: 1,
+            message: "broken".to_owned()
+        })
+    );
+}
+
+#[test]
+fn upcast_newer() {
+    let got = upcasters().upcast(json!("a"), 4, 3);
+    assert_eq!(
+        got,
+        Err(UpcastError::UnknownSchemaVersion {
+            stored: 4,
+            current: 3
+        })
+    );
+}
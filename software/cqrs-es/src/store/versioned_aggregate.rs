@@ -64,9 +64,12 @@ where
     where
         M: MapAccess<'de>,
     {
-        visit_versioned(map, SERIALIZE_KEY_AGGREGATE, |version, aggregate| {
-            VersionedAggregate { version, aggregate }
-        })
+        visit_versioned(
+            map,
+            SERIALIZE_KEY_AGGREGATE,
+            None,
+            |version, aggregate, _| VersionedAggregate { version, aggregate },
+        )
     }
 }
 
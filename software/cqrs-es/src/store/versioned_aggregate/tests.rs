@@ -15,3 +15,15 @@ fn versioned_aggregate_serde() {
     assert_eq!(before.version, after.version);
     assert_eq!(before.aggregate, after.aggregate);
 }
+
+#[test]
+fn versioned_aggregate_serde_bincode() {
+    let before = VersionedAggregate {
+        version: Version(123),
+        aggregate: TestAggregate(456),
+    };
+    let bytes = bincode::serialize(&before).unwrap();
+    let after: VersionedAggregate<TestAggregate> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(before.version, after.version);
+    assert_eq!(before.aggregate, after.aggregate);
+}
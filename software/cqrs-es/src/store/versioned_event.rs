@@ -5,7 +5,8 @@ use serde::de::{MapAccess, Visitor};
 use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::store::Version;
+use crate::store::upcaster::EventSchema;
+use crate::store::{Metadata, Version};
 use crate::{Aggregate, Event};
 
 use super::serde::*;
@@ -18,6 +19,7 @@ mod tests;
 pub struct VersionedEvent<A: Aggregate> {
     pub version: Version,
     pub event: A::Event,
+    pub metadata: Metadata,
 }
 
 impl<A, E> Serialize for VersionedEvent<A>
@@ -29,9 +31,15 @@ where
     where
         S: Serializer,
     {
-        let mut map = serializer.serialize_map(Some(2))?;
+        let len = if self.metadata.is_empty() { 3 } else { 4 };
+        let mut map = serializer.serialize_map(Some(len))?;
         map.serialize_entry(SERIALIZE_KEY_VERSION, &self.version)?;
+        // Upcastが必要かどうかをEventより先に判断できるよう、前に書き込む
+        map.serialize_entry(SERIALIZE_KEY_SCHEMA_VERSION, &E::schema_version())?;
         map.serialize_entry(SERIALIZE_KEY_EVENT, &self.event)?;
+        if !self.metadata.is_empty() {
+            map.serialize_entry(SERIALIZE_KEY_METADATA, &self.metadata)?;
+        }
         map.end()
     }
 }
@@ -67,10 +75,20 @@ where
     where
         M: MapAccess<'de>,
     {
-        visit_versioned(map, SERIALIZE_KEY_EVENT, |version, event| VersionedEvent {
-            version,
-            event,
-        })
+        let schema = EventSchema {
+            version: E::schema_version(),
+            upcasters: E::upcasters,
+        };
+        visit_versioned(
+            map,
+            SERIALIZE_KEY_EVENT,
+            Some(schema),
+            |version, event, metadata| VersionedEvent {
+                version,
+                event,
+                metadata,
+            },
+        )
     }
 }
 
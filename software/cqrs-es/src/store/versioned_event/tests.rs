@@ -1,5 +1,8 @@
-use crate::store::Version;
+use crate::store::{Metadata, Version};
 use crate::tests::test_aggregate::{TestAggregate, TestEvent};
+use crate::tests::test_upcast_aggregate::{UpcastAggregate, UpcastEvent};
+
+use uuid::Uuid;
 
 use super::VersionedEvent;
 
@@ -8,10 +11,150 @@ fn versioned_event_serde() {
     let before = VersionedEvent::<TestAggregate> {
         version: Version(123),
         event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    };
+    let json = serde_json::to_string(&before).unwrap();
+    println!("{}", json);
+    let after: VersionedEvent<TestAggregate> = serde_json::from_str(&json).unwrap();
+    assert_eq!(before.version, after.version);
+    assert_eq!(before.event, after.event);
+}
+
+#[test]
+fn versioned_event_serde_with_metadata() {
+    let before = VersionedEvent::<TestAggregate> {
+        version: Version(123),
+        event: TestEvent::Increased,
+        metadata: Metadata::new()
+            .with_recorded_at("2020-01-02T03:04:05Z".parse().unwrap())
+            .with_actor("nisshiee")
+            .with_correlation_id(Uuid::new_v4())
+            .with_causation_id(Uuid::new_v4())
+            .with_idempotency_key("purchase-1")
+            .with_header("source", "test"),
     };
     let json = serde_json::to_string(&before).unwrap();
     println!("{}", json);
     let after: VersionedEvent<TestAggregate> = serde_json::from_str(&json).unwrap();
     assert_eq!(before.version, after.version);
     assert_eq!(before.event, after.event);
+    assert_eq!(before.metadata, after.metadata);
+}
+
+/// 自己記述的でない形式を含む、JSON以外の形式でも読み書きできる
+#[test]
+fn versioned_event_serde_binary_formats() {
+    let events = vec![
+        VersionedEvent::<TestAggregate> {
+            version: Version(1),
+            event: TestEvent::Increased,
+            metadata: Metadata::new(),
+        },
+        VersionedEvent::<TestAggregate> {
+            version: Version(2),
+            event: TestEvent::Increased,
+            metadata: Metadata::new()
+                .with_recorded_at("2020-01-02T03:04:05Z".parse().unwrap())
+                .with_actor("nisshiee")
+                .with_correlation_id(Uuid::new_v4())
+                .with_header("source", "test"),
+        },
+    ];
+    let check = |after: Vec<VersionedEvent<TestAggregate>>| {
+        assert_eq!(after.len(), events.len());
+        for (before, after) in events.iter().zip(after) {
+            assert_eq!(before.version, after.version);
+            assert_eq!(before.event, after.event);
+            assert_eq!(before.metadata, after.metadata);
+        }
+    };
+
+    check(bincode::deserialize(&bincode::serialize(&events).unwrap()).unwrap());
+    check(serde_cbor::from_slice(&serde_cbor::to_vec(&events).unwrap()).unwrap());
+    check(rmp_serde::from_slice(&rmp_serde::to_vec(&events).unwrap()).unwrap());
+}
+
+#[test]
+fn versioned_event_deserialize_without_metadata() {
+    let json = r#"{"version":1,"event":"Increased"}"#;
+    let after: VersionedEvent<TestAggregate> = serde_json::from_str(json).unwrap();
+    assert_eq!(after.version, Version(1));
+    assert_eq!(after.event, TestEvent::Increased);
+    assert!(after.metadata.is_empty());
+}
+
+#[test]
+fn versioned_event_deserialize_partial_metadata() {
+    let json = r#"{"version":1,"event":"Increased","metadata":{"actor":"nisshiee"}}"#;
+    let after: VersionedEvent<TestAggregate> = serde_json::from_str(json).unwrap();
+    assert_eq!(after.metadata, Metadata::new().with_actor("nisshiee"));
+}
+
+#[test]
+fn versioned_event_deserialize_unknown_key() {
+    let json = r#"{"version":1,"unknown":{"a":[1,2]},"event":"Increased"}"#;
+    let after: VersionedEvent<TestAggregate> = serde_json::from_str(json).unwrap();
+    assert_eq!(after.version, Version(1));
+    assert_eq!(after.event, TestEvent::Increased);
+}
+
+#[test]
+fn versioned_event_serialize_schema_version() {
+    let event = VersionedEvent::<UpcastAggregate> {
+        version: Version(1),
+        event: UpcastEvent::Increased { amount: 3 },
+        metadata: Metadata::new(),
+    };
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(
+        json,
+        r#"{"version":1,"schema_version":2,"event":{"Increased":{"amount":3}}}"#
+    );
+}
+
+#[test]
+fn versioned_event_current_schema_bincode() {
+    let before = VersionedEvent::<UpcastAggregate> {
+        version: Version(1),
+        event: UpcastEvent::Increased { amount: 3 },
+        metadata: Metadata::new(),
+    };
+    let bytes = bincode::serialize(&before).unwrap();
+    let after: VersionedEvent<UpcastAggregate> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(after.event, UpcastEvent::Increased { amount: 3 });
+}
+
+#[test]
+fn versioned_event_upcast_legacy() {
+    let json = r#"{"version":1,"event":"Increased"}"#;
+    let after: VersionedEvent<UpcastAggregate> = serde_json::from_str(json).unwrap();
+    assert_eq!(after.event, UpcastEvent::Increased { amount: 1 });
+}
+
+#[test]
+fn versioned_event_upcast_old_schema() {
+    let json = r#"{"version":1,"schema_version":1,"event":"Increased"}"#;
+    let after: VersionedEvent<UpcastAggregate> = serde_json::from_str(json).unwrap();
+    assert_eq!(after.event, UpcastEvent::Increased { amount: 1 });
+}
+
+#[test]
+fn versioned_event_current_schema_after_event() {
+    let json = r#"{"version":1,"event":{"Increased":{"amount":3}},"schema_version":2}"#;
+    let after: VersionedEvent<UpcastAggregate> = serde_json::from_str(json).unwrap();
+    assert_eq!(after.event, UpcastEvent::Increased { amount: 3 });
+}
+
+#[test]
+fn versioned_event_upcast_error() {
+    let json = r#"{"version":1,"schema_version":1,"event":"Decreased"}"#;
+    let got = serde_json::from_str::<VersionedEvent<UpcastAggregate>>(json);
+    assert!(got.is_err());
+}
+
+#[test]
+fn versioned_event_newer_schema() {
+    let json = r#"{"version":1,"schema_version":3,"event":{"Increased":{"amount":3}}}"#;
+    let got = serde_json::from_str::<VersionedEvent<UpcastAggregate>>(json);
+    assert!(got.is_err());
 }
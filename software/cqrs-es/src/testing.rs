@@ -0,0 +1,66 @@
+use crate::{Aggregate, Command, Event};
+
+#[cfg(test)]
+mod tests;
+
+type CommandErrorOf<A> = <<A as Aggregate>::Command as Command<A>>::Error;
+
+/// Given/When/Then形式でCommandの振る舞いを検証するためのテストハーネス
+pub struct Given<A: Aggregate> {
+    aggregate: A,
+}
+
+/// 過去に発生したEventを `Event::apply_to` で適用したAggregateを起点にする
+pub fn given<A: Aggregate>(events: Vec<A::Event>) -> Given<A> {
+    let mut aggregate = A::default();
+    events.into_iter().for_each(|e| e.apply_to(&mut aggregate));
+    Given { aggregate }
+}
+
+/// Eventが1つも発生していないAggregateを起点にする
+pub fn given_no_previous_events<A: Aggregate>() -> Given<A> {
+    given(Vec::new())
+}
+
+impl<A: Aggregate> Given<A> {
+    pub fn when(self, command: A::Command) -> Then<A> {
+        let result = command
+            .execute_on(&self.aggregate)
+            .map(|events| events.into_iter().collect());
+        Then { result }
+    }
+}
+
+/// Commandの実行結果
+pub struct Then<A: Aggregate> {
+    result: Result<Vec<A::Event>, CommandErrorOf<A>>,
+}
+
+impl<A: Aggregate> Then<A> {
+    /// 発生したEventが `expected` と一致することを検証する
+    pub fn then_expect_events(self, expected: Vec<A::Event>)
+    where
+        A::Event: PartialEq,
+    {
+        match self.result {
+            Ok(events) => assert_eq!(events, expected),
+            Err(e) => panic!("expected events {:?}, but got error {:?}", expected, e),
+        }
+    }
+
+    /// `expected` のCommandErrorで失敗したことを検証する
+    pub fn then_expect_error(self, expected: CommandErrorOf<A>)
+    where
+        CommandErrorOf<A>: PartialEq,
+    {
+        match self.result {
+            Ok(events) => panic!("expected error {:?}, but got events {:?}", expected, events),
+            Err(e) => assert_eq!(e, expected),
+        }
+    }
+
+    /// 検証をテスト側で行うため、実行結果をそのまま返す
+    pub fn into_result(self) -> Result<Vec<A::Event>, CommandErrorOf<A>> {
+        self.result
+    }
+}
@@ -0,0 +1,40 @@
+use crate::testing::*;
+use crate::tests::test_aggregate::*;
+
+#[test]
+fn then_expect_events() {
+    given_no_previous_events::<TestAggregate>()
+        .when(TestCommand::Increase)
+        .then_expect_events(vec![TestEvent::Increased]);
+}
+
+#[test]
+fn then_expect_error() {
+    given::<TestAggregate>(vec![TestEvent::Increased])
+        .when(TestCommand::Invalid)
+        .then_expect_error(TestCommandError::Invalid);
+}
+
+#[test]
+#[should_panic]
+fn then_expect_events_mismatched() {
+    given_no_previous_events::<TestAggregate>()
+        .when(TestCommand::Increase)
+        .then_expect_events(Vec::new());
+}
+
+#[test]
+#[should_panic]
+fn then_expect_events_but_error() {
+    given_no_previous_events::<TestAggregate>()
+        .when(TestCommand::Invalid)
+        .then_expect_events(vec![TestEvent::Increased]);
+}
+
+#[test]
+#[should_panic]
+fn then_expect_error_but_events() {
+    given_no_previous_events::<TestAggregate>()
+        .when(TestCommand::Increase)
+        .then_expect_error(TestCommandError::Invalid);
+}
@@ -1 +1,2 @@
 pub mod test_aggregate;
+pub mod test_upcast_aggregate;
@@ -23,6 +23,7 @@ impl Event<TestAggregate> for TestEvent {
     }
 }
 
+#[derive(Debug, Clone)]
 pub enum TestCommand {
     Increase,
     Invalid,
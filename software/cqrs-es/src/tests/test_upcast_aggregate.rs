@@ -0,0 +1,61 @@
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::store::upcaster::Upcasters;
+use crate::*;
+
+/// スキーマバージョン2で `Increased` に `amount` を追加したAggregate
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub struct UpcastAggregate(pub u64);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum UpcastEvent {
+    Increased { amount: u64 },
+}
+
+impl Event<UpcastAggregate> for UpcastEvent {
+    fn apply_to(self, aggregate: &mut UpcastAggregate) {
+        match self {
+            UpcastEvent::Increased { amount } => aggregate.0 += amount,
+        }
+    }
+
+    fn schema_version() -> u32 {
+        2
+    }
+
+    fn upcasters() -> Upcasters {
+        Upcasters::new().register(1, |event| match event {
+            Value::String(ref s) if s == "Increased" => Ok(json!({ "Increased": { "amount": 1 } })),
+            e => Err(format!("unknown event: {}", e)),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpcastCommand;
+
+#[derive(Fail, Debug, Eq, PartialEq)]
+#[fail(display = "Invalid")]
+pub struct UpcastCommandError;
+
+impl CommandError for UpcastCommandError {}
+
+impl Command<UpcastAggregate> for UpcastCommand {
+    type Events = Option<UpcastEvent>;
+    type Error = UpcastCommandError;
+
+    fn execute_on(self, _aggregate: &UpcastAggregate) -> Result<Self::Events, Self::Error> {
+        Ok(Some(UpcastEvent::Increased { amount: 1 }))
+    }
+}
+
+impl Aggregate for UpcastAggregate {
+    type Event = UpcastEvent;
+    type Command = UpcastCommand;
+
+    fn type_name() -> &'static str {
+        "upcast"
+    }
+}
@@ -0,0 +1,119 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use cqrs_es::store::*;
+use cqrs_es::*;
+use futures::executor::ThreadPool;
+use futures::future::{BoxFuture, FutureExt};
+use futures::task::SpawnExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::codec::{Codec, JsonCodec};
+use crate::streams::FileStreams;
+use crate::FileEventStorageError;
+
+/// `FileEventStorage` の非同期版
+///
+/// ファイルの読み書きはスレッドプールで行うため、呼び出し元のランタイムを選ばない。
+/// ファイルの形式とロックは `FileEventStorage` と共通のため、同じディレクトリを同時に扱える
+/// ロックの取得を待つ時間は `DEFAULT_LOCK_TIMEOUT` とする。
+///
+/// `FileEventStorage` と異なり、スナップショットとProjectorは扱わない。
+/// これらが必要な場合は、 `FileEventStorage` を `AsyncAdapter` で包んで使う
+pub struct AsyncFileEventStorage<A, E, C = JsonCodec>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    streams: Arc<FileStreams<A, E, C>>,
+    pool: ThreadPool,
+}
+
+impl<A, E> AsyncFileEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned + Send + 'static,
+    E: Event<A> + Serialize + DeserializeOwned + Send + 'static,
+{
+    pub fn new<P>(root_path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        AsyncFileEventStorage::with_codec(root_path, JsonCodec)
+    }
+}
+
+impl<A, E, C> AsyncFileEventStorage<A, E, C>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned + Send + 'static,
+    E: Event<A> + Serialize + DeserializeOwned + Send + 'static,
+    C: Codec + Send + Sync + 'static,
+{
+    /// 新しく作るストリームのファイルを `codec` の形式で書き込む
+    ///
+    /// 使える形式と、形式を選べる範囲は `FileEventStorage::with_codec` と同じ
+    pub fn with_codec<P>(root_path: P, codec: C) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(AsyncFileEventStorage {
+            streams: Arc::new(FileStreams::new(root_path, codec)?),
+            pool: ThreadPool::new()?,
+        })
+    }
+
+    /// 他と共有するスレッドプールでファイルを読み書きする
+    pub fn with_thread_pool(mut self, pool: ThreadPool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    fn run<F, T>(&self, f: F) -> BoxFuture<'static, T>
+    where
+        F: FnOnce(&FileStreams<A, E, C>) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let streams = self.streams.clone();
+        self.pool
+            .spawn_with_handle(async move { f(&streams) })
+            .expect("ThreadPool does not shut down while it is referenced")
+            .boxed()
+    }
+}
+
+impl<A, E, C> AsyncEventStorage<A> for AsyncFileEventStorage<A, E, C>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned + Send + 'static,
+    E: Event<A> + Serialize + DeserializeOwned + Send + 'static,
+    C: Codec + Send + Sync + 'static,
+{
+    type Events = Vec<VersionedEvent<A>>;
+    type Error = FileEventStorageError;
+
+    fn insert(
+        &mut self,
+        id: Id<A>,
+        event: VersionedEvent<A>,
+    ) -> BoxFuture<'_, Result<(), Self::Error>> {
+        self.run(move |s| s.insert(id, event))
+    }
+
+    fn read(&self, id: Id<A>) -> BoxFuture<'_, Result<Self::Events, Self::Error>> {
+        self.run(move |s| s.read(id))
+    }
+
+    fn list_ids(&self, paging: Paging) -> BoxFuture<'_, Result<Vec<StreamInfo<A>>, Self::Error>> {
+        self.run(move |s| s.list_ids(paging))
+    }
+
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> BoxFuture<'_, Result<(), AppendError<Self::Error>>> {
+        self.run(move |s| s.append(id, expected_version, events))
+    }
+}
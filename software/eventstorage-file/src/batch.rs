@@ -0,0 +1,161 @@
+use std::fmt::{Error as FmtError, Formatter};
+use std::marker::PhantomData;
+
+use cqrs_es::store::{Metadata, Version, VersionedEvent};
+use cqrs_es::{Aggregate, Event};
+use serde::de::{DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::FileEventStorageError;
+
+/// ファイルの1レコード分のEvent
+///
+/// 1回の `append` で渡されたEventは1レコードに書き込まれる。
+/// JSONなど人が読む形式では、1件であればEventそのもの、複数件であればEventの配列として表現する。
+/// それ以外の形式では常に配列として表現し、自己記述的でない形式では各Eventを `Envelope` に包む
+pub struct Batch<A: Aggregate>(pub Vec<VersionedEvent<A>>);
+
+impl<A, E> Batch<A>
+where
+    A: Aggregate<Event = E>,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    pub fn wrap(events: &[VersionedEvent<A>]) -> Result<Vec<Envelope>, FileEventStorageError> {
+        events.iter().map(Envelope::wrap).collect()
+    }
+
+    pub fn unwrap(envelopes: Vec<Envelope>) -> Result<Batch<A>, FileEventStorageError> {
+        let events = envelopes
+            .into_iter()
+            .map(Envelope::unwrap)
+            .collect::<Result<_, _>>()?;
+        Ok(Batch(events))
+    }
+}
+
+/// 自己記述的でない形式で書き込む、Event1件分のレコード
+///
+/// 型を知らなければ値の構造を読み取れない形式でもUpcastできるよう、
+/// 保存時のスキーマバージョンをEventと分けて書き込み、Eventは自己記述的なJSONで保持する
+#[derive(Serialize, Deserialize)]
+pub struct Envelope {
+    version: Version,
+    schema_version: u32,
+    event: String,
+    metadata: Metadata,
+}
+
+impl Envelope {
+    fn wrap<A, E>(event: &VersionedEvent<A>) -> Result<Envelope, FileEventStorageError>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + Serialize,
+    {
+        Ok(Envelope {
+            version: event.version,
+            schema_version: E::schema_version(),
+            event: serde_json::to_string(&event.event)?,
+            metadata: event.metadata.clone(),
+        })
+    }
+
+    /// 保存時のスキーマバージョンが現在と異なれば、Upcastしてから読み込む
+    fn unwrap<A, E>(self) -> Result<VersionedEvent<A>, FileEventStorageError>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + DeserializeOwned,
+    {
+        let event = if self.schema_version == E::schema_version() {
+            serde_json::from_str(&self.event)?
+        } else {
+            let event = E::upcasters()
+                .upcast(
+                    serde_json::from_str(&self.event)?,
+                    self.schema_version,
+                    E::schema_version(),
+                )
+                .map_err(|e| FileEventStorageError::Codec(e.into()))?;
+            serde_json::from_value(event)?
+        };
+        Ok(VersionedEvent {
+            version: self.version,
+            event,
+            metadata: self.metadata,
+        })
+    }
+}
+
+/// 書き込み用の `Batch`
+pub struct BatchRef<'a, A: Aggregate>(pub &'a [VersionedEvent<A>]);
+
+impl<'a, A, E> Serialize for BatchRef<'a, A>
+where
+    A: Aggregate<Event = E>,
+    E: Event<A> + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            [event] if serializer.is_human_readable() => event.serialize(serializer),
+            events => events.serialize(serializer),
+        }
+    }
+}
+
+struct BatchVisitor<A, E> {
+    phantom: PhantomData<(A, E)>,
+}
+
+impl<'de, A, E> Visitor<'de> for BatchVisitor<A, E>
+where
+    A: Aggregate<Event = E>,
+    E: Event<A> + Deserialize<'de>,
+{
+    type Value = Batch<A>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> Result<(), FmtError> {
+        formatter.write_str("struct VersionedEvent or sequence of VersionedEvent")
+    }
+
+    fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let deserializer = serde::de::value::MapAccessDeserializer::new(map);
+        let event = VersionedEvent::deserialize(deserializer)?;
+        Ok(Batch(vec![event]))
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let mut events = Vec::new();
+        while let Some(event) = seq.next_element()? {
+            events.push(event);
+        }
+        Ok(Batch(events))
+    }
+}
+
+impl<'de, A, E> Deserialize<'de> for Batch<A>
+where
+    A: Aggregate<Event = E>,
+    E: Event<A> + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let visitor = BatchVisitor {
+            phantom: PhantomData,
+        };
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(visitor)
+        } else {
+            deserializer.deserialize_seq(visitor)
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::convert::TryFrom;
+
+use cqrs_es::store::VersionedEvent;
+use cqrs_es::{Aggregate, Event};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::batch::{Batch, BatchRef};
+use crate::FileEventStorageError;
+
+/// ファイルの先頭に置く、形式を表すヘッダーの接頭辞。改行までが形式名となる
+///
+/// ヘッダーのないファイルは、形式を選べるようになる前に書き込まれたJSONとみなす
+const HEADER_PREFIX: &[u8] = b"$format ";
+
+/// `Framing::LengthPrefixed` で、各レコードの前に置く長さ (ビッグエンディアンのu32) のバイト数
+const LENGTH_LEN: usize = 4;
+
+/// ファイル内でのレコードの区切り方
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Framing {
+    /// 各レコードを改行で終える。エンコード結果に改行を含まない形式に限る
+    Line,
+    /// 各レコードの前に長さを書き込む
+    LengthPrefixed,
+}
+
+/// Eventやスナップショットをファイルに書き込む形式
+///
+/// `name` はファイルのヘッダーに書き込まれ、読み込み時に形式を判別するために使う
+pub trait Codec {
+    fn name(&self) -> &'static str;
+
+    fn framing(&self) -> Framing {
+        Framing::LengthPrefixed
+    }
+
+    /// 型を知らなくても値の構造を読み取れる形式か
+    ///
+    /// Upcastは保存された値を一度 `serde_json::Value` として読み込むため、
+    /// 自己記述的でない形式では、スキーマバージョンとJSONにしたEventを包んだレコードとして書き込む
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FileEventStorageError>;
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FileEventStorageError>;
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn framing(&self) -> Framing {
+        Framing::Line
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FileEventStorageError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FileEventStorageError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CborCodec;
+
+impl Codec for CborCodec {
+    fn name(&self) -> &'static str {
+        "cbor"
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FileEventStorageError> {
+        serde_cbor::to_vec(value).map_err(|e| FileEventStorageError::Codec(e.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FileEventStorageError> {
+        serde_cbor::from_slice(bytes).map_err(|e| FileEventStorageError::Codec(e.into()))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MessagePackCodec;
+
+impl Codec for MessagePackCodec {
+    fn name(&self) -> &'static str {
+        "msgpack"
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FileEventStorageError> {
+        rmp_serde::to_vec(value).map_err(|e| FileEventStorageError::Codec(e.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FileEventStorageError> {
+        rmp_serde::from_slice(bytes).map_err(|e| FileEventStorageError::Codec(e.into()))
+    }
+}
+
+/// 自己記述的でない形式のため、ストリームのEventは、Upcastできるよう
+/// スキーマバージョンとJSONにしたEventを包んだレコードとして書き込む
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+
+    fn is_self_describing(&self) -> bool {
+        false
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FileEventStorageError> {
+        bincode::serialize(value).map_err(|e| FileEventStorageError::Codec(e.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FileEventStorageError> {
+        bincode::deserialize(bytes).map_err(|e| FileEventStorageError::Codec(e.into()))
+    }
+}
+
+/// ヘッダーから判別した、ファイルごとの形式
+///
+/// 新しく作るファイルには設定された形式を使い、既存のファイルはヘッダーの形式のまま読み書きする
+enum FileFormat<'a, C> {
+    Configured(&'a C),
+    Json,
+    Cbor,
+    MessagePack,
+    Bincode,
+}
+
+impl<'a, C: Codec> FileFormat<'a, C> {
+    /// ヘッダーを除いた内容と合わせて返す。ヘッダーが書き込み途中で終わっている場合は `None`
+    fn detect(codec: &'a C, content: &'a [u8]) -> Option<(Result<Self, String>, &'a [u8])> {
+        if !content.starts_with(HEADER_PREFIX) {
+            return Some((Ok(FileFormat::Json), content));
+        }
+        let end = content.iter().position(|b| *b == 0x0A)?;
+        let name = String::from_utf8_lossy(&content[HEADER_PREFIX.len()..end]);
+        let format = match name.as_ref() {
+            name if name == codec.name() => Ok(FileFormat::Configured(codec)),
+            "json" => Ok(FileFormat::Json),
+            "cbor" => Ok(FileFormat::Cbor),
+            "msgpack" => Ok(FileFormat::MessagePack),
+            "bincode" => Ok(FileFormat::Bincode),
+            name => Err(name.to_owned()),
+        };
+        Some((format, &content[end + 1..]))
+    }
+
+    fn is_self_describing(&self) -> bool {
+        match self {
+            FileFormat::Configured(codec) => codec.is_self_describing(),
+            FileFormat::Bincode => BincodeCodec.is_self_describing(),
+            _ => true,
+        }
+    }
+
+    fn framing(&self) -> Framing {
+        match self {
+            FileFormat::Configured(codec) => codec.framing(),
+            FileFormat::Json => JsonCodec.framing(),
+            FileFormat::Cbor => CborCodec.framing(),
+            FileFormat::MessagePack => MessagePackCodec.framing(),
+            FileFormat::Bincode => BincodeCodec.framing(),
+        }
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, FileEventStorageError> {
+        match self {
+            FileFormat::Configured(codec) => codec.encode(value),
+            FileFormat::Json => JsonCodec.encode(value),
+            FileFormat::Cbor => CborCodec.encode(value),
+            FileFormat::MessagePack => MessagePackCodec.encode(value),
+            FileFormat::Bincode => BincodeCodec.encode(value),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, FileEventStorageError> {
+        match self {
+            FileFormat::Configured(codec) => codec.decode(bytes),
+            FileFormat::Json => JsonCodec.decode(bytes),
+            FileFormat::Cbor => CborCodec.decode(bytes),
+            FileFormat::MessagePack => MessagePackCodec.decode(bytes),
+            FileFormat::Bincode => BincodeCodec.decode(bytes),
+        }
+    }
+
+    /// 自己記述的でない形式では、Eventを1件ずつ `Envelope` に包んで書き込む
+    fn encode_batch<A, E>(
+        &self,
+        events: &[VersionedEvent<A>],
+    ) -> Result<Vec<u8>, FileEventStorageError>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + Serialize + DeserializeOwned,
+    {
+        if self.is_self_describing() {
+            self.encode(&BatchRef(events))
+        } else {
+            self.encode(&Batch::wrap(events)?)
+        }
+    }
+
+    fn decode_batch<A, E>(&self, bytes: &[u8]) -> Result<Batch<A>, FileEventStorageError>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + Serialize + DeserializeOwned,
+    {
+        if self.is_self_describing() {
+            self.decode(bytes)
+        } else {
+            Batch::unwrap(self.decode(bytes)?)
+        }
+    }
+}
+
+fn detect<'a, C: Codec>(
+    codec: &'a C,
+    content: &'a [u8],
+) -> Result<(FileFormat<'a, C>, &'a [u8]), FileEventStorageError> {
+    match FileFormat::detect(codec, content) {
+        Some((Ok(format), body)) => Ok((format, body)),
+        Some((Err(name), _)) => Err(FileEventStorageError::UnknownFormat(name)),
+        // 書き込み途中のヘッダーしかないファイルは、レコードのないファイルとして扱う
+        None => Ok((FileFormat::Json, &[])),
+    }
+}
+
+/// `body` を先頭から区切ったレコードと、区切り終えた長さ
+///
+/// 末尾の書き込みが中断されたレコード (torn write) は含めない
+fn split_records(framing: Framing, body: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut records = Vec::new();
+    let mut rest = body;
+    loop {
+        let (record, len) = match framing {
+            Framing::Line => match rest.iter().position(|b| *b == 0x0A) {
+                Some(p) => (&rest[..p], p + 1),
+                None => break,
+            },
+            Framing::LengthPrefixed => {
+                let len = match rest.get(..LENGTH_LEN).map(<[u8; LENGTH_LEN]>::try_from) {
+                    Some(Ok(len)) => u32::from_be_bytes(len) as usize,
+                    _ => break,
+                };
+                match rest.get(LENGTH_LEN..LENGTH_LEN + len) {
+                    Some(record) => (record, LENGTH_LEN + len),
+                    None => break,
+                }
+            }
+        };
+        records.push(record);
+        rest = &rest[len..];
+    }
+    (records, body.len() - rest.len())
+}
+
+/// ファイルの内容のうち、レコードが完結している部分の長さ
+///
+/// ヘッダーが書き込み途中で終わっている場合は0、形式を判別できない場合は全体とする
+pub(crate) fn complete_len<C: Codec>(codec: &C, content: &[u8]) -> usize {
+    match FileFormat::detect(codec, content) {
+        Some((Ok(format), body)) => {
+            let (_, len) = split_records(format.framing(), body);
+            content.len() - body.len() + len
+        }
+        Some((Err(_), _)) => content.len(),
+        None => 0,
+    }
+}
+
+/// ファイルの内容に含まれるレコードを、ヘッダーの形式で読み込む
+pub(crate) fn decode_records<C, A, E>(
+    codec: &C,
+    content: &[u8],
+) -> Result<Vec<Batch<A>>, FileEventStorageError>
+where
+    C: Codec,
+    A: Aggregate<Event = E>,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    let (format, body) = detect(codec, content)?;
+    let (records, _) = split_records(format.framing(), body);
+    records
+        .into_iter()
+        .filter(|r| !r.is_empty())
+        .map(|r| format.decode_batch(r))
+        .collect()
+}
+
+/// ファイルの末尾のレコードから順に読み込み、 `done` が真を返したレコードまでで止める
+///
+/// 読み込んだレコードは、ファイル内と同じ順に並べて返す
+pub(crate) fn decode_records_rev<C, A, E, F>(
+    codec: &C,
+    content: &[u8],
+    mut done: F,
+) -> Result<Vec<Batch<A>>, FileEventStorageError>
+where
+    C: Codec,
+    A: Aggregate<Event = E>,
+    E: Event<A> + Serialize + DeserializeOwned,
+    F: FnMut(&Batch<A>) -> bool,
+{
+    let (format, body) = detect(codec, content)?;
+    let (records, _) = split_records(format.framing(), body);
+    let mut decoded = Vec::new();
+    for record in records.into_iter().rev().filter(|r| !r.is_empty()) {
+        let value = format.decode_batch(record)?;
+        let done = done(&value);
+        decoded.push(value);
+        if done {
+            break;
+        }
+    }
+    decoded.reverse();
+    Ok(decoded)
+}
+
+/// `content` の内容のファイルに追記する、 `events` をまとめたレコード
+///
+/// 空のファイルにはヘッダーを付け、 `codec` の形式で書き込む
+pub(crate) fn encode_record<C, A, E>(
+    codec: &C,
+    content: &[u8],
+    events: &[VersionedEvent<A>],
+) -> Result<Vec<u8>, FileEventStorageError>
+where
+    C: Codec,
+    A: Aggregate<Event = E>,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    let (format, mut bytes) = if content.is_empty() {
+        (FileFormat::Configured(codec), header(codec))
+    } else {
+        (detect(codec, content)?.0, Vec::new())
+    };
+    let record = format.encode_batch(events)?;
+    match format.framing() {
+        Framing::Line => {
+            bytes.extend(record);
+            bytes.push(0x0A);
+        }
+        Framing::LengthPrefixed => {
+            let len =
+                u32::try_from(record.len()).map_err(|e| FileEventStorageError::Codec(e.into()))?;
+            bytes.extend(&len.to_be_bytes());
+            bytes.extend(record);
+        }
+    }
+    Ok(bytes)
+}
+
+/// ヘッダーに続けて、 `value` のみを書き込んだファイルの内容
+pub(crate) fn encode_file<C, T>(codec: &C, value: &T) -> Result<Vec<u8>, FileEventStorageError>
+where
+    C: Codec,
+    T: Serialize,
+{
+    let mut bytes = header(codec);
+    bytes.extend(codec.encode(value)?);
+    Ok(bytes)
+}
+
+pub(crate) fn decode_file<C, T>(codec: &C, content: &[u8]) -> Result<T, FileEventStorageError>
+where
+    C: Codec,
+    T: DeserializeOwned,
+{
+    let (format, body) = detect(codec, content)?;
+    format.decode(body)
+}
+
+fn header<C: Codec>(codec: &C) -> Vec<u8> {
+    let mut header = HEADER_PREFIX.to_vec();
+    header.extend(codec.name().as_bytes());
+    header.push(0x0A);
+    header
+}
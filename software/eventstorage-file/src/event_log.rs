@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use cqrs_es::store::*;
+use cqrs_es::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    complete_len, lock_for_replace, open_locked, read_locked, sync_parent_dir,
+    FileEventStorageError, DEFAULT_LOCK_TIMEOUT,
+};
+
+/// `root_path` 直下に置く、全ストリームのログのファイル名
+const FILE_NAME: &str = "$all";
+
+/// 全Aggregateのストリームに追加されたEventを、1つのファイルに追記していくログ
+///
+/// ストリームへの書き込み後にログへ書き込む。その間で中断したり、ログへの書き込みに失敗したりして
+/// 欠落したEventは、次に同じAggregateの `FileEventStorage` を開いたときにストリームから追記する。
+/// そのため、欠落したEventのPositionは、他のストリームのその後のEventより後になることがある
+pub struct FileEventLog {
+    file_path: PathBuf,
+    lock_timeout: Duration,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PositionOnly {
+    position: Position,
+}
+
+/// ログの1行
+///
+/// `DeleteMode::Purge` で取り除いたEventのうち、Positionが再利用されないよう最後のものだけを
+/// Positionのみの行 ( `Purged` ) として残す
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Recorded(RecordedEvent),
+    Purged(PositionOnly),
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    version: Version,
+}
+
+impl FileEventLog {
+    pub fn new<P>(root_path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let root_path = root_path.as_ref();
+        fs::DirBuilder::new().recursive(true).create(root_path)?;
+        Ok(FileEventLog {
+            file_path: root_path.join(FILE_NAME),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        })
+    }
+
+    /// ファイルのロックを取得できるまで待つ時間を設定する。既定は `DEFAULT_LOCK_TIMEOUT`
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.set_lock_timeout(timeout);
+        self
+    }
+
+    pub(crate) fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
+
+    pub(crate) fn exists(&self) -> bool {
+        self.file_path.is_file()
+    }
+
+    /// 追記するためにログを開く
+    ///
+    /// `purge` がログを置き換えている間に、置き換えられる前のファイルに追記しないよう、
+    /// 置き換えと同じロックを先に取得する
+    fn open_for_append(&self) -> Result<(fs::File, fs::File, Vec<u8>), FileEventStorageError> {
+        let lock = lock_for_replace(&self.file_path, self.lock_timeout)?;
+        let (file, content) = open_locked(&self.file_path, self.lock_timeout, complete_len)?;
+        Ok((lock, file, content))
+    }
+
+    /// ストリームと同じ順序で記録されるよう、ストリームのロックを保持したまま呼び出す
+    pub(crate) fn append<A, E>(
+        &self,
+        id: Id<A>,
+        events: &[VersionedEvent<A>],
+    ) -> Result<(), FileEventStorageError>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + Serialize,
+    {
+        let (_lock, mut file, content) = self.open_for_append()?;
+        write(&mut file, &content, id, events)
+    }
+
+    /// `events` のうち、ログにまだ記録されていないバージョンのものを追記する
+    ///
+    /// `append` と同じく、ストリームのロックを保持したまま呼び出す
+    pub(crate) fn append_missing<A, E>(
+        &self,
+        id: Id<A>,
+        events: &[VersionedEvent<A>],
+    ) -> Result<(), FileEventStorageError>
+    where
+        A: Aggregate<Event = E>,
+        E: Event<A> + Serialize,
+    {
+        let (_lock, mut file, content) = self.open_for_append()?;
+        let mut logged = Version::default();
+        for (_, line) in lines(&content) {
+            match line? {
+                Line::Recorded(e) if e.is_of::<A>() && e.id == *id.as_uuid() => {
+                    logged = logged.max(VersionOnly::deserialize(&e.event)?.version);
+                }
+                _ => {}
+            }
+        }
+        let start = events
+            .iter()
+            .position(|e| e.version > logged)
+            .unwrap_or(events.len());
+        write(&mut file, &content, id, &events[start..])
+    }
+
+    /// `DeleteMode::Purge` で消去したストリームのEventを、ログからも取り除く
+    ///
+    /// 残す行を一時ファイルに書き込んでからrenameで置き換えるため、途中で中断してもログは失われず、
+    /// 記録済みのEventのPositionも変わらない
+    pub(crate) fn purge<A>(&self, id: Id<A>) -> Result<(), FileEventStorageError>
+    where
+        A: Aggregate,
+    {
+        let _lock = lock_for_replace(&self.file_path, self.lock_timeout)?;
+        // 置き換えが終わるまで、読み込みも待たせる
+        let (_file, content) = open_locked(&self.file_path, self.lock_timeout, complete_len)?;
+        let last = last_position(&content)?;
+        let mut kept = Vec::new();
+        let mut kept_last = Position::default();
+        let mut purged = false;
+        for (bytes, line) in lines(&content) {
+            match line? {
+                Line::Recorded(e) if e.is_of::<A>() && e.id == *id.as_uuid() => purged = true,
+                Line::Recorded(e) => {
+                    kept_last = e.position;
+                    kept.extend(bytes);
+                    kept.push(0x0A);
+                }
+                Line::Purged(_) => {}
+            }
+        }
+        if !purged {
+            return Ok(());
+        }
+        if kept_last < last {
+            serde_json::to_writer(&mut kept, &PositionOnly { position: last })?;
+            kept.push(0x0A);
+        }
+        let tmp_path = self.file_path.with_extension("tmp");
+        let mut tmp = fs::File::create(&tmp_path)?;
+        tmp.write_all(&kept)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.file_path)?;
+        sync_parent_dir(&self.file_path)?;
+        Ok(())
+    }
+
+    /// ログに記録された、 `A` の各ストリームの最新バージョン
+    pub(crate) fn last_versions<A>(&self) -> Result<HashMap<Id<A>, Version>, FileEventStorageError>
+    where
+        A: Aggregate,
+    {
+        let mut versions = HashMap::new();
+        for e in self.read_all(Position::default())? {
+            if !e.is_of::<A>() {
+                continue;
+            }
+            let version = VersionOnly::deserialize(&e.event)?.version;
+            let last = versions.entry(Id::from(e.id)).or_insert(version);
+            *last = (*last).max(version);
+        }
+        Ok(versions)
+    }
+}
+
+/// ロックを取得済みのログに、 `content` の最後のPositionに続けてEventを書き込む
+fn write<A, E>(
+    file: &mut fs::File,
+    content: &[u8],
+    id: Id<A>,
+    events: &[VersionedEvent<A>],
+) -> Result<(), FileEventStorageError>
+where
+    A: Aggregate<Event = E>,
+    E: Event<A> + Serialize,
+{
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut position = last_position(content)?;
+    let mut lines = Vec::new();
+    for event in events {
+        position = position.next();
+        serde_json::to_writer(&mut lines, &RecordedEvent::new(position, id, event)?)?;
+        lines.push(0x0A);
+    }
+    file.write_all(&lines)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// 各行の内容と、それを読み込んだもの
+fn lines(content: &[u8]) -> impl Iterator<Item = (&[u8], Result<Line, serde_json::Error>)> {
+    content
+        .split(|b| *b == 0x0A)
+        .filter(|bytes| !bytes.is_empty())
+        .map(|bytes| (bytes, serde_json::from_slice(bytes)))
+}
+
+/// 最後の行のPosition
+fn last_position(content: &[u8]) -> Result<Position, serde_json::Error> {
+    let content = match content.split_last() {
+        Some((_, content)) => content,
+        None => return Ok(Position::default()),
+    };
+    let start = content
+        .iter()
+        .rposition(|b| *b == 0x0A)
+        .map_or(0, |p| p + 1);
+    match serde_json::from_slice(&content[start..])? {
+        Line::Recorded(e) => Ok(e.position),
+        Line::Purged(p) => Ok(p.position),
+    }
+}
+
+impl EventLog for FileEventLog {
+    type Events = Vec<RecordedEvent>;
+    type Error = FileEventStorageError;
+
+    fn read_all(&self, from: Position) -> Result<Self::Events, Self::Error> {
+        let mut content = match read_locked(&self.file_path, self.lock_timeout)? {
+            Some(content) => content,
+            None => return Ok(Vec::new()),
+        };
+        content.truncate(complete_len(&content));
+        lines(&content)
+            .filter_map(|(_, line)| match line {
+                Ok(Line::Recorded(e)) if e.position >= from => Some(Ok(e)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            })
+            .collect()
+    }
+}
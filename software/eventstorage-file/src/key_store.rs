@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use cqrs_es::shredding::{KeyStore, SubjectId, SubjectKey};
+use sha2::{Digest, Sha256};
+
+use crate::{lock_for_replace, read_locked, FileEventStorageError, DEFAULT_LOCK_TIMEOUT};
+
+/// `root_path` 直下に置く、暗号鍵を保存するディレクトリ名
+const DIR_NAME: &str = "$keys";
+
+/// 主体ごとの暗号鍵を、主体ごとに1ファイルに保存する
+///
+/// 鍵のファイルを消せばその主体の個人データは読めなくなるため、
+/// Eventとは別の場所にバックアップを取らないようにする。
+/// 鍵のファイルは所有者のみ読み書きできるよう作成する (Unixのみ)
+pub struct FileKeyStore {
+    dir: PathBuf,
+    lock_timeout: Duration,
+}
+
+impl FileKeyStore {
+    pub fn new<P>(root_path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = root_path.as_ref().join(DIR_NAME);
+        fs::DirBuilder::new().recursive(true).create(&dir)?;
+        Ok(FileKeyStore {
+            dir,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        })
+    }
+
+    /// ファイルのロックを取得できるまで待つ時間を設定する。既定は `DEFAULT_LOCK_TIMEOUT`
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// 主体IDにはファイル名に使えない文字も含まれ、長さの上限もないため、
+    /// SHA-256のハッシュ値を16進数にしてファイル名とする
+    fn file_path(&self, subject: &SubjectId) -> PathBuf {
+        let hash = Sha256::digest(subject.0.as_bytes());
+        let file_name: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(file_name)
+    }
+
+    /// 書き込み途中のファイルを読まないよう、鍵ごとに別の一時ファイルに書き込んでfsyncする
+    fn write_tmp(
+        &self,
+        subject: &SubjectId,
+        key: &SubjectKey,
+    ) -> Result<PathBuf, FileEventStorageError> {
+        let tmp_path = self
+            .file_path(subject)
+            .with_extension(format!("{}.tmp", key.id));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(key)?)?;
+        file.sync_data()?;
+        Ok(tmp_path)
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    type Error = FileEventStorageError;
+
+    fn load(&self, subject: &SubjectId) -> Result<Option<SubjectKey>, Self::Error> {
+        match read_locked(&self.file_path(subject), self.lock_timeout)? {
+            Some(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, subject: &SubjectId, key: &SubjectKey) -> Result<(), Self::Error> {
+        let file_path = self.file_path(subject);
+        let _lock = lock_for_replace(&file_path, self.lock_timeout)?;
+        let tmp_path = self.write_tmp(subject, key)?;
+        fs::rename(tmp_path, file_path)?;
+        Ok(())
+    }
+
+    /// 一時ファイルをハードリンクすることで、既に鍵のファイルがあれば上書きせずに失敗させる
+    fn save_if_absent(
+        &mut self,
+        subject: &SubjectId,
+        key: &SubjectKey,
+    ) -> Result<bool, Self::Error> {
+        let file_path = self.file_path(subject);
+        let _lock = lock_for_replace(&file_path, self.lock_timeout)?;
+        let tmp_path = self.write_tmp(subject, key)?;
+        let linked = fs::hard_link(&tmp_path, file_path);
+        fs::remove_file(tmp_path)?;
+        match linked {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&mut self, subject: &SubjectId) -> Result<(), Self::Error> {
+        let file_path = self.file_path(subject);
+        let _lock = lock_for_replace(&file_path, self.lock_timeout)?;
+        match fs::remove_file(file_path) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
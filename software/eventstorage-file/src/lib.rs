@@ -1,29 +1,67 @@
+extern crate bincode;
+extern crate chrono;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+extern crate fs2;
+extern crate futures;
+extern crate rmp_serde;
 extern crate serde;
+extern crate serde_cbor;
 extern crate serde_json;
+#[cfg(feature = "shredding")]
+extern crate sha2;
 
 use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use cqrs_es::projector::Projector;
+use cqrs_es::store::snapshot::{SnapshotPolicy, SnapshotStore};
 use cqrs_es::store::*;
 use cqrs_es::*;
-use failure::_core::marker::PhantomData;
+use fs2::FileExt;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serde_json::Deserializer;
 
-pub struct FileEventStorage<A, E>
+mod batch;
+
+mod codec;
+pub use codec::{BincodeCodec, CborCodec, Codec, Framing, JsonCodec, MessagePackCodec};
+
+mod snapshot;
+pub use snapshot::FileSnapshotStore;
+
+mod event_log;
+pub use event_log::FileEventLog;
+
+mod saga;
+pub use saga::FileSagaStore;
+
+#[cfg(feature = "shredding")]
+mod key_store;
+#[cfg(feature = "shredding")]
+pub use key_store::FileKeyStore;
+
+mod streams;
+use streams::FileStreams;
+
+mod async_storage;
+pub use async_storage::AsyncFileEventStorage;
+
+pub struct FileEventStorage<A, E, C = JsonCodec>
 where
     A: Aggregate<Event = E> + Serialize + DeserializeOwned,
     E: Event<A> + Serialize + DeserializeOwned,
+    C: Codec,
 {
-    dir: PathBuf,
-    phantom: PhantomData<A>,
-    //    projectors: Vec<&'a mut dyn Projector<A>>,
+    streams: FileStreams<A, E, C>,
+    projectors: Vec<Box<dyn Projector<A>>>,
+    snapshot_store: Option<Box<dyn SnapshotStore<A, Error = FileEventStorageError>>>,
+    snapshot_policy: SnapshotPolicy,
 }
 
 impl<A, E> FileEventStorage<A, E>
@@ -35,34 +73,205 @@ where
     where
         P: AsRef<Path>,
     {
-        let mut aggregate_dir = root_path.as_ref().to_owned();
-        A::type_name()
-            .split('/')
-            .for_each(|e| aggregate_dir.push(e));
-
-        let mut dir_builder = fs::DirBuilder::new();
-        dir_builder.recursive(true);
-        dir_builder.create(aggregate_dir.as_path())?;
+        FileEventStorage::with_codec(root_path, JsonCodec)
+    }
+}
 
+impl<A, E, C> FileEventStorage<A, E, C>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// 新しく作るストリームのファイルを `codec` の形式で書き込む
+    ///
+    /// 既存のファイルはヘッダーに書かれた形式のまま読み書きするため、形式の異なるファイルが混在していてもよい。
+    /// `BincodeCodec` など自己記述的でない形式でも、保存時のスキーマバージョンからUpcastして読み込める。
+    /// 形式を選べるのはストリームのみで、全Aggregateを通したログ、Sagaの状態、暗号鍵は常にJSONで保存する。
+    /// スナップショットの形式は `FileSnapshotStore::with_codec` で指定する
+    pub fn with_codec<P>(root_path: P, codec: C) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
         Ok(FileEventStorage {
-            dir: aggregate_dir,
-            phantom: PhantomData,
-            //            projectors: Vec::new(),
+            streams: FileStreams::new(root_path, codec)?,
+            projectors: Vec::new(),
+            snapshot_store: None,
+            snapshot_policy: SnapshotPolicy::Never,
         })
     }
 
-    fn file_path(&self, id: Id<A>) -> PathBuf {
-        let mut file_path = self.dir.clone();
-        file_path.push(id.to_string());
-        file_path
+    pub fn with_snapshot_store<S>(mut self, snapshot_store: S, policy: SnapshotPolicy) -> Self
+    where
+        S: SnapshotStore<A, Error = FileEventStorageError> + 'static,
+    {
+        self.snapshot_store = Some(Box::new(snapshot_store));
+        self.snapshot_policy = policy;
+        self
+    }
+
+    /// ファイルのロックを取得できるまで待つ時間を設定する。既定は `DEFAULT_LOCK_TIMEOUT`
+    ///
+    /// 他のプロセスがロックを保持したまま `timeout` を過ぎた場合は `FileEventStorageError::Locked` となる
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.streams.set_lock_timeout(timeout);
+        self
+    }
+
+    pub fn add_projector<P>(&mut self, projector: P)
+    where
+        P: Projector<A> + 'static,
+    {
+        self.projectors.push(Box::new(projector))
+    }
+}
+
+/// ファイルのロックを取得できるまで待つ時間の既定値
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ロックを取得できなかった場合に、再び試みるまでの間隔
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum LockMode {
+    /// 読み込み用。他の読み込みとは同時に取得できる
+    Shared,
+    /// 書き込み用
+    Exclusive,
+}
+
+/// ファイルのロック (advisory lock) を取得する
+///
+/// `timeout` を過ぎても他のプロセスやEventStorageが解放しなければ `FileEventStorageError::Locked` とする。
+/// ロックはファイルを閉じると解放される
+fn lock(file: &fs::File, mode: LockMode, timeout: Duration) -> Result<(), FileEventStorageError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let result = match mode {
+            // 標準ライブラリの同名のメソッドではなく、fs2のものを使う
+            LockMode::Shared => FileExt::try_lock_shared(file),
+            LockMode::Exclusive => FileExt::try_lock_exclusive(file),
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(ref e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {}
+            Err(e) => return Err(e.into()),
+        }
+        if Instant::now() >= deadline {
+            return Err(FileEventStorageError::Locked);
+        }
+        thread::sleep(LOCK_RETRY_INTERVAL);
+    }
+}
+
+/// 他のプロセスと書き込みが混ざらないよう、排他ロックを取得した上でファイルを開き、
+/// `complete_len` で完結しているとされた部分の内容を返す
+///
+/// 書き込みが途中で中断された末尾のレコード (torn write) は、この時点で切り詰める
+fn open_locked<F>(
+    path: &Path,
+    timeout: Duration,
+    complete_len: F,
+) -> Result<(fs::File, Vec<u8>), FileEventStorageError>
+where
+    F: Fn(&[u8]) -> usize,
+{
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .create(true)
+        .append(true)
+        .open(path)?;
+    lock(&file, LockMode::Exclusive, timeout)?;
+
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    let len = complete_len(&content);
+    if len < content.len() {
+        file.set_len(len as u64)?;
+        content.truncate(len);
+    }
+    Ok((file, content))
+}
+
+/// 書き込み途中の内容を読まないよう、共有ロックを取得した上でファイルを読む
+///
+/// ファイルがなければ `None`
+fn read_locked(path: &Path, timeout: Duration) -> Result<Option<Vec<u8>>, FileEventStorageError> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    lock(&file, LockMode::Shared, timeout)?;
+
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    Ok(Some(content))
+}
+
+/// `path` のファイルを置き換える間、 `<path>.lock` の排他ロックを取得しておく
+///
+/// 置き換えは一時ファイルへの書き込みとrenameで行い、置き換えられる側のファイルは別のものになるため、
+/// 置き換える側同士は別のファイルのロックで排他する
+fn lock_for_replace(path: &Path, timeout: Duration) -> Result<fs::File, FileEventStorageError> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let (file, _) = open_locked(Path::new(&lock_path), timeout, |content| content.len())?;
+    Ok(file)
+}
+
+/// renameによる置き換えを永続化するため、 `path` のあるディレクトリをfsyncする
+///
+/// ディレクトリを開けないWindowsでは何もしない
+fn sync_parent_dir(path: &Path) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// 改行で終わっている部分の長さ
+///
+/// 各行は改行までを1回で書き込むため、改行のない末尾の行は書き込みが中断されたものとみなす
+fn complete_len(content: &[u8]) -> usize {
+    content
+        .iter()
+        .rposition(|b| *b == 0x0A)
+        .map_or(0, |p| p + 1)
+}
+
+/// `io::Error` を返すコンストラクタの中で起きたエラーを変換する
+fn into_io_error(e: FileEventStorageError) -> io::Error {
+    match e {
+        FileEventStorageError::Io(e) => e,
+        e => io::Error::other(failure::Fail::compat(e)),
     }
 }
 
-//impl<'a, A: Aggregate> FileEventStorage<'a, A> {
-//    pub fn add_projector<P: Projector<A>>(&mut self, projector: &'a mut P) {
-//        self.projectors.push(projector)
-//    }
-//}
+/// `root_path` 配下に、 `A::type_name()` の階層に沿ったディレクトリを作成する
+fn aggregate_dir<A, P>(root_path: P) -> Result<PathBuf, io::Error>
+where
+    A: Aggregate,
+    P: AsRef<Path>,
+{
+    let mut aggregate_dir = root_path.as_ref().to_owned();
+    A::type_name()
+        .split('/')
+        .for_each(|e| aggregate_dir.push(e));
+
+    let mut dir_builder = fs::DirBuilder::new();
+    dir_builder.recursive(true);
+    dir_builder.create(aggregate_dir.as_path())?;
+
+    Ok(aggregate_dir)
+}
 
 #[derive(Fail, Debug)]
 pub enum FileEventStorageError {
@@ -70,9 +279,23 @@ pub enum FileEventStorageError {
     Io(#[fail(cause)] io::Error),
     #[fail(display = "JSON error: {}", _0)]
     Json(#[fail(cause)] serde_json::Error),
+    #[fail(display = "Stream deleted")]
+    Deleted,
+    /// ロックを待つ時間 ( `FileEventStorage::with_lock_timeout` ) を過ぎても、他がファイルのロックを解放しなかった
+    #[fail(display = "File locked")]
+    Locked,
+    #[fail(display = "Codec error: {}", _0)]
+    Codec(failure::Error),
+    /// ファイルのヘッダーに書かれた形式が、組み込みの形式と設定された形式のいずれでもない
+    #[fail(display = "Unknown format: {}", _0)]
+    UnknownFormat(String),
 }
 
-impl EventStorageError for FileEventStorageError {}
+impl EventStorageError for FileEventStorageError {
+    fn is_deleted(&self) -> bool {
+        matches!(self, FileEventStorageError::Deleted)
+    }
+}
 
 impl From<io::Error> for FileEventStorageError {
     fn from(e: io::Error) -> Self {
@@ -90,53 +313,93 @@ impl From<serde_json::Error> for FileEventStorageError {
     }
 }
 
-impl<A, E> EventStorage<A> for FileEventStorage<A, E>
+impl<A, E, C> EventStorage<A> for FileEventStorage<A, E, C>
 where
     A: Aggregate<Event = E> + Serialize + DeserializeOwned,
     E: Event<A> + Serialize + DeserializeOwned,
+    C: Codec,
 {
     type Events = Vec<VersionedEvent<A>>;
     type Error = FileEventStorageError;
 
     fn insert(&mut self, id: Id<A>, event: VersionedEvent<A>) -> Result<(), Self::Error> {
-        let file_path = self.file_path(id);
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(file_path)?;
-        let json = serde_json::to_string(&event)?;
-        file.write_all(json.as_bytes())?;
-        file.write_all(&[0x0A])?;
+        self.streams.insert(id, event)
+    }
 
-        //        self.projectors
-        //            .iter_mut()
-        //            .for_each(|p| p.project(id, &event));
+    fn read(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        self.streams.read(id)
+    }
 
-        Ok(())
+    fn read_from(&self, id: Id<A>, from: Version) -> Result<Vec<VersionedEvent<A>>, Self::Error> {
+        self.streams.read_from(id, from)
     }
 
-    fn read(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
-        let file_path = self.file_path(id);
-
-        if let Ok(metadata) = fs::metadata(&file_path) {
-            if !metadata.is_file() {
-                return Ok(Vec::new());
-            }
-            if metadata.len() == 0 {
-                return Ok(Vec::new());
-            }
-        } else {
-            return Ok(Vec::new());
+    fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<A>>, Self::Error> {
+        self.streams.list_ids(paging)
+    }
+
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        self.streams.append(id, expected_version, events)
+    }
+
+    fn projectors(&mut self) -> &mut [Box<dyn Projector<A>>] {
+        &mut self.projectors
+    }
+
+    fn is_processed(&self, id: Id<A>, idempotency_key: &str) -> Result<bool, Self::Error> {
+        self.streams.is_processed(id, idempotency_key)
+    }
+
+    fn snapshot_policy(&self) -> SnapshotPolicy {
+        self.snapshot_policy
+    }
+
+    fn load_snapshot(&self, id: Id<A>) -> Result<Option<VersionedAggregate<A>>, Self::Error> {
+        match self.snapshot_store {
+            Some(ref store) => store.load(id),
+            None => Ok(None),
+        }
+    }
+
+    fn save_snapshot(
+        &mut self,
+        id: Id<A>,
+        aggregate: &VersionedAggregate<A>,
+    ) -> Result<(), Self::Error> {
+        match self.snapshot_store {
+            Some(ref mut store) => store.save(id, aggregate),
+            None => Ok(()),
         }
+    }
+}
+
+/// ストリームとスナップショットを削除する
+///
+/// 全Aggregateを通したログ ( `FileEventLog` ) のEventは、 `DeleteMode::Purge` で削除した場合のみ取り除く
+impl<A, E, C> DeletableEventStorage<A> for FileEventStorage<A, E, C>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    fn delete(&mut self, id: Id<A>, mode: DeleteMode) -> Result<(), Self::Error> {
+        self.streams.delete(id, mode)?;
+        match self.snapshot_store {
+            Some(ref mut store) => store.delete(id),
+            None => Ok(()),
+        }
+    }
+
+    fn is_deleted(&self, id: Id<A>) -> Result<bool, Self::Error> {
+        Ok(self.streams.is_deleted(id))
+    }
 
-        let file = fs::File::open(&file_path)?;
-        let file = io::BufReader::new(file);
-        Deserializer::from_reader(file)
-            .into_iter()
-            .try_fold(Vec::new(), |mut a, e| {
-                let event = e?;
-                a.push(event);
-                Ok(a)
-            })
+    fn read_archived(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        self.streams.read_archived(id)
     }
 }
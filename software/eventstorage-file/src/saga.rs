@@ -0,0 +1,70 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use cqrs_es::saga::{Saga, SagaState, SagaStore};
+
+use crate::{
+    lock_for_replace, read_locked, sync_parent_dir, FileEventStorageError, DEFAULT_LOCK_TIMEOUT,
+};
+
+/// `root_path` 直下に置く、Sagaの状態を保存するディレクトリ名
+const DIR_NAME: &str = "$sagas";
+
+/// Sagaの状態を、Sagaごとに1ファイルに保存する
+pub struct FileSagaStore<S: Saga> {
+    file_path: PathBuf,
+    lock_timeout: Duration,
+    phantom: PhantomData<S>,
+}
+
+impl<S: Saga> FileSagaStore<S> {
+    pub fn new<P>(root_path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file_path = root_path.as_ref().join(DIR_NAME);
+        S::type_name().split('/').for_each(|e| file_path.push(e));
+        if let Some(dir) = file_path.parent() {
+            fs::DirBuilder::new().recursive(true).create(dir)?;
+        }
+        Ok(FileSagaStore {
+            file_path,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            phantom: PhantomData,
+        })
+    }
+
+    /// ファイルのロックを取得できるまで待つ時間を設定する。既定は `DEFAULT_LOCK_TIMEOUT`
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+}
+
+impl<S: Saga> SagaStore<S> for FileSagaStore<S> {
+    type Error = FileEventStorageError;
+
+    fn load(&self) -> Result<Option<SagaState<S>>, Self::Error> {
+        match read_locked(&self.file_path, self.lock_timeout)? {
+            Some(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, state: &SagaState<S>) -> Result<(), Self::Error> {
+        // 書き込み途中のファイルを読まないよう、一時ファイルに書いてfsyncしてからrenameする
+        let _lock = lock_for_replace(&self.file_path, self.lock_timeout)?;
+        let tmp_path = self.file_path.with_extension("tmp");
+        let json = serde_json::to_vec(state)?;
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.file_path)?;
+        sync_parent_dir(&self.file_path)?;
+        Ok(())
+    }
+}
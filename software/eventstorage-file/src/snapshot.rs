@@ -0,0 +1,112 @@
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use cqrs_es::store::snapshot::SnapshotStore;
+use cqrs_es::store::VersionedAggregate;
+use cqrs_es::{Aggregate, Id};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::codec::{self, Codec, JsonCodec};
+use crate::{
+    aggregate_dir, lock_for_replace, read_locked, FileEventStorageError, DEFAULT_LOCK_TIMEOUT,
+};
+
+/// Aggregate IDごとに、最新のスナップショットを1ファイルに保存する
+///
+/// ファイル名は `<Aggregate ID>.snapshot` とし、 `FileEventStorage` と同じディレクトリを指定してもストリームと衝突しない
+pub struct FileSnapshotStore<A, C = JsonCodec>
+where
+    A: Aggregate + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    dir: PathBuf,
+    codec: C,
+    lock_timeout: Duration,
+    phantom: PhantomData<A>,
+}
+
+impl<A> FileSnapshotStore<A>
+where
+    A: Aggregate + Serialize + DeserializeOwned,
+{
+    pub fn new<P>(root_path: P) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        FileSnapshotStore::with_codec(root_path, JsonCodec)
+    }
+}
+
+impl<A, C> FileSnapshotStore<A, C>
+where
+    A: Aggregate + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// スナップショットを `codec` の形式で保存する
+    ///
+    /// 他の形式で保存されていたスナップショットも、ヘッダーに書かれた形式で読み込む
+    pub fn with_codec<P>(root_path: P, codec: C) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(FileSnapshotStore {
+            dir: aggregate_dir::<A, _>(root_path)?,
+            codec,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            phantom: PhantomData,
+        })
+    }
+
+    /// ファイルのロックを取得できるまで待つ時間を設定する。既定は `DEFAULT_LOCK_TIMEOUT`
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    fn file_path(&self, id: Id<A>) -> PathBuf {
+        let mut file_path = self.dir.clone();
+        file_path.push(id.to_string());
+        file_path.set_extension("snapshot");
+        file_path
+    }
+}
+
+impl<A, C> SnapshotStore<A> for FileSnapshotStore<A, C>
+where
+    A: Aggregate + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    type Error = FileEventStorageError;
+
+    fn load(&self, id: Id<A>) -> Result<Option<VersionedAggregate<A>>, Self::Error> {
+        match read_locked(&self.file_path(id), self.lock_timeout)? {
+            Some(content) => Ok(Some(codec::decode_file(&self.codec, &content)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, id: Id<A>, aggregate: &VersionedAggregate<A>) -> Result<(), Self::Error> {
+        // 書き込み途中のファイルを読まないよう、一時ファイルに書いてからrenameする
+        let file_path = self.file_path(id);
+        let _lock = lock_for_replace(&file_path, self.lock_timeout)?;
+        let tmp_path = file_path.with_extension("snapshot.tmp");
+        let content = codec::encode_file(&self.codec, aggregate)?;
+        fs::write(&tmp_path, content)?;
+        fs::rename(tmp_path, file_path)?;
+        Ok(())
+    }
+
+    fn delete(&mut self, id: Id<A>) -> Result<(), Self::Error> {
+        let file_path = self.file_path(id);
+        let _lock = lock_for_replace(&file_path, self.lock_timeout)?;
+        match fs::remove_file(file_path) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
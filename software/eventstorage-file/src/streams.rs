@@ -0,0 +1,491 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use cqrs_es::store::*;
+use cqrs_es::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::batch::Batch;
+use crate::codec::{self, Codec};
+use crate::{
+    aggregate_dir, into_io_error, open_locked, read_locked, sync_parent_dir, FileEventLog,
+    FileEventStorageError, DEFAULT_LOCK_TIMEOUT,
+};
+
+/// 既存の全てのストリームをログに記録済みであることを表す、Aggregateのディレクトリに置くファイル名
+const LOGGED_FILE_NAME: &str = "$logged";
+
+/// Aggregate IDごとに1ファイルを割り当て、Eventを追記していくストリーム群
+///
+/// 最新バージョンの確認から書き込みまではプロセス内でも排他し、さらにファイルロックで他のプロセスと排他するため、
+/// 同期・非同期どちらのEventStorageからも、他のプロセスからも共有できる。
+/// 読み込みには共有ロック、書き込みには排他ロックを取得する
+pub(crate) struct FileStreams<A, E, C>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    dir: PathBuf,
+    archive_dir: PathBuf,
+    log: FileEventLog,
+    /// ログへの書き込みに失敗し、ストリームから補う必要がある
+    log_pending: AtomicBool,
+    codec: C,
+    lock_timeout: Duration,
+    // Aggregateの値は保持しないため、 `A` によらずスレッド間で共有できるようにする
+    phantom: PhantomData<fn() -> A>,
+}
+
+impl<A, E, C> FileStreams<A, E, C>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// ログに欠落している可能性のあるストリームがあれば、ストリームから追記する
+    pub fn new<P>(root_path: P, codec: C) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut archive_dir = root_path.as_ref().to_owned();
+        archive_dir.push("$archive");
+        A::type_name().split('/').for_each(|e| archive_dir.push(e));
+
+        let streams = FileStreams {
+            dir: aggregate_dir::<A, _>(&root_path)?,
+            archive_dir,
+            log: FileEventLog::new(&root_path)?,
+            log_pending: AtomicBool::new(false),
+            codec,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            phantom: PhantomData,
+        };
+        streams.repair_log().map_err(into_io_error)?;
+        Ok(streams)
+    }
+
+    fn file_path(&self, id: Id<A>) -> PathBuf {
+        let mut file_path = self.dir.clone();
+        file_path.push(id.to_string());
+        file_path
+    }
+
+    /// ストリームに書き込んだEventを、まだログに記録し終えていない印
+    fn pending_path(&self, id: Id<A>) -> PathBuf {
+        self.file_path(id).with_extension("pending")
+    }
+
+    /// 既存の全てのストリームのEventをログに記録済みである印
+    fn logged_path(&self) -> PathBuf {
+        self.dir.join(LOGGED_FILE_NAME)
+    }
+
+    fn tombstone_path(&self, id: Id<A>) -> PathBuf {
+        self.file_path(id).with_extension("tombstone")
+    }
+
+    fn archive_path(&self, id: Id<A>) -> PathBuf {
+        let mut archive_path = self.archive_dir.clone();
+        archive_path.push(id.to_string());
+        archive_path
+    }
+
+    fn check_deleted(&self, id: Id<A>) -> Result<(), FileEventStorageError> {
+        if self.is_deleted(id) {
+            Err(FileEventStorageError::Deleted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// 全Aggregateを通したログのロックにも同じ時間を使う
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+        self.log.set_lock_timeout(timeout);
+    }
+
+    /// プロセス内で排他してから、ファイルロックを取得する
+    fn open_locked(
+        &self,
+        file_path: &Path,
+    ) -> Result<(StreamGuard, fs::File, Vec<u8>), FileEventStorageError> {
+        let guard = StreamGuard::acquire(file_path, self.lock_timeout)?;
+        let (file, content) = open_locked(file_path, self.lock_timeout, |content| {
+            codec::complete_len(&self.codec, content)
+        })?;
+        Ok((guard, file, content))
+    }
+
+    /// ファイルがなければ空とする
+    fn read_locked(&self, file_path: &Path) -> Result<Vec<u8>, FileEventStorageError> {
+        let mut content = read_locked(file_path, self.lock_timeout)?.unwrap_or_default();
+        content.truncate(codec::complete_len(&self.codec, &content));
+        Ok(content)
+    }
+
+    /// 書き込み用にロックを取得してストリームを開く
+    ///
+    /// ロックの取得を待っている間に削除された場合は、 `open_locked` が作った空のファイルを消してエラーとする
+    fn open_for_write(
+        &self,
+        id: Id<A>,
+    ) -> Result<(StreamGuard, fs::File, Vec<u8>), FileEventStorageError> {
+        self.check_deleted(id)?;
+        let file_path = self.file_path(id);
+        let (guard, file, content) = self.open_locked(&file_path)?;
+        if self.is_deleted(id) {
+            if content.is_empty() {
+                remove_if_exists(&file_path)?;
+            }
+            return Err(FileEventStorageError::Deleted);
+        }
+        Ok((guard, file, content))
+    }
+
+    /// Eventを1レコードにまとめ、1回の書き込みとfsyncで永続化する
+    ///
+    /// 既存のファイルには、そのファイルの形式で追記する
+    fn write_events(
+        &self,
+        file: &mut fs::File,
+        content: &[u8],
+        events: &[VersionedEvent<A>],
+    ) -> Result<(), FileEventStorageError> {
+        let record = codec::encode_record(&self.codec, content, events)?;
+        file.write_all(&record)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// ストリームに書き込む前に、ログに記録し終えていない印を付ける
+    ///
+    /// 書き込みの途中で中断した場合に、次に開いたときに補うストリームを見つけられるよう、
+    /// 印はストリームより先に永続化する
+    fn mark_pending(&self, id: Id<A>) -> Result<(), FileEventStorageError> {
+        let pending_path = self.pending_path(id);
+        fs::File::create(&pending_path)?;
+        sync_parent_dir(&pending_path)?;
+        Ok(())
+    }
+
+    /// ストリームに書き込んだEventをログに記録し、印を消す
+    ///
+    /// ストリームへの書き込みは済んでいるため、ログへの書き込みに失敗してもエラーとせず、次の書き込みの前に補う
+    fn append_to_log(&self, id: Id<A>, events: &[VersionedEvent<A>]) {
+        let logged = self
+            .log
+            .append(id, events)
+            .and_then(|()| Ok(fs::remove_file(self.pending_path(id))?));
+        if logged.is_err() {
+            self.log_pending.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// ストリームに書き込まれたものの、ログに記録されていないEventをログに追記する
+    ///
+    /// ログを導入する前から存在するストリームが残っている可能性がある場合に限って全てのストリームを照合し、
+    /// それ以外は書き込みの途中で中断した印のあるストリームのみ補う
+    fn repair_log(&self) -> Result<(), FileEventStorageError> {
+        if self.logged_path().is_file() && self.log.exists() {
+            return self.repair_pending();
+        }
+
+        let logged = self.log.last_versions::<A>()?;
+        for id in self.stream_ids()? {
+            if self.is_deleted(id) {
+                continue;
+            }
+            let last_version = self.read(id)?.last().map(|e| e.version);
+            if last_version > logged.get(&id).copied() || self.pending_path(id).is_file() {
+                self.repair_stream(id)?;
+            }
+        }
+        fs::File::create(self.logged_path())?;
+        sync_parent_dir(&self.logged_path())?;
+        Ok(())
+    }
+
+    fn repair_pending(&self) -> Result<(), FileEventStorageError> {
+        for entry in fs::read_dir(&self.dir)? {
+            let file_name = entry?.file_name();
+            let id = match file_name.to_str().and_then(|n| n.strip_suffix(".pending")) {
+                Some(id) => id.parse::<Id<A>>(),
+                None => continue,
+            };
+            if let Ok(id) = id {
+                self.repair_stream(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 他の書き込みとログの順序が入れ替わらないよう、ストリームのロックを取得してから追記し、印を消す
+    fn repair_stream(&self, id: Id<A>) -> Result<(), FileEventStorageError> {
+        let (_guard, _file, content) = match self.open_for_write(id) {
+            Ok(opened) => opened,
+            Err(FileEventStorageError::Deleted) => {
+                return Ok(remove_if_exists(&self.pending_path(id))?)
+            }
+            Err(e) => return Err(e),
+        };
+        self.log.append_missing(id, &self.parse_events(&content)?)?;
+        remove_if_exists(&self.pending_path(id))?;
+        Ok(())
+    }
+
+    /// ストリームのロックを取得する前に呼び出す
+    fn repair_log_if_pending(&self) -> Result<(), FileEventStorageError> {
+        if self.log_pending.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.repair_pending() {
+                self.log_pending.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_events(
+        &self,
+        content: &[u8],
+    ) -> Result<Vec<VersionedEvent<A>>, FileEventStorageError> {
+        let batches = codec::decode_records(&self.codec, content)?;
+        Ok(batches.into_iter().flat_map(|b| b.0).collect())
+    }
+
+    pub fn insert(&self, id: Id<A>, event: VersionedEvent<A>) -> Result<(), FileEventStorageError> {
+        self.repair_log_if_pending()?;
+        let (_guard, mut file, content) = self.open_for_write(id)?;
+        let events = [event];
+        self.mark_pending(id)?;
+        self.write_events(&mut file, &content, &events)?;
+        self.append_to_log(id, &events);
+        Ok(())
+    }
+
+    /// ストリームのファイルの内容。ファイルがなければ空とする
+    fn read_stream(&self, id: Id<A>) -> Result<Vec<u8>, FileEventStorageError> {
+        self.check_deleted(id)?;
+        let file_path = self.file_path(id);
+
+        if let Ok(metadata) = fs::metadata(&file_path) {
+            if !metadata.is_file() {
+                return Ok(Vec::new());
+            }
+            if metadata.len() == 0 {
+                return Ok(Vec::new());
+            }
+        } else {
+            return Ok(Vec::new());
+        }
+
+        self.read_locked(&file_path)
+    }
+
+    pub fn read(&self, id: Id<A>) -> Result<Vec<VersionedEvent<A>>, FileEventStorageError> {
+        let content = self.read_stream(id)?;
+        self.parse_events(&content)
+    }
+
+    /// 末尾のレコードから読み込み、 `from` 以前のEventを含むレコードまでで止める
+    pub fn read_from(
+        &self,
+        id: Id<A>,
+        from: Version,
+    ) -> Result<Vec<VersionedEvent<A>>, FileEventStorageError> {
+        let content = self.read_stream(id)?;
+        let batches = codec::decode_records_rev(&self.codec, &content, |b: &Batch<A>| {
+            b.0.first().is_some_and(|e| e.version <= from)
+        })?;
+        Ok(batches
+            .into_iter()
+            .flat_map(|b| b.0)
+            .skip_while(|e| e.version < from)
+            .collect())
+    }
+
+    /// 空でないストリームのID
+    fn stream_ids(&self) -> Result<Vec<Id<A>>, FileEventStorageError> {
+        // Aggregate ID以外のファイルや、他のAggregateの階層のディレクトリは除く
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let id = match entry.file_name().to_str().map(str::parse::<Id<A>>) {
+                Some(Ok(id)) => id,
+                _ => continue,
+            };
+            let metadata = entry.metadata()?;
+            if metadata.is_file() && metadata.len() > 0 {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// ファイルにキーのバイト列が含まれない場合は、デコードせずに未処理と判定する
+    ///
+    /// JSONではエスケープされた形で、その他の形式ではそのままのバイト列で書き込まれるため、両方を探す
+    pub fn is_processed(
+        &self,
+        id: Id<A>,
+        idempotency_key: &str,
+    ) -> Result<bool, FileEventStorageError> {
+        let content = self.read_stream(id)?;
+        let escaped = serde_json::to_string(idempotency_key)?;
+        let escaped = &escaped[1..escaped.len() - 1];
+        if !idempotency_key.is_empty()
+            && !contains(&content, idempotency_key.as_bytes())
+            && !contains(&content, escaped.as_bytes())
+        {
+            return Ok(false);
+        }
+        Ok(self
+            .parse_events(&content)?
+            .iter()
+            .any(|e| e.metadata.idempotency_key.as_deref() == Some(idempotency_key)))
+    }
+
+    pub fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<A>>, FileEventStorageError> {
+        paging
+            .apply(self.stream_ids()?)
+            .map(|id| {
+                let last_version = self.read(id)?.last().map(|e| e.version).unwrap_or_default();
+                let last_modified = fs::metadata(self.file_path(id))?
+                    .modified()
+                    .ok()
+                    .map(DateTime::<Utc>::from);
+                Ok(StreamInfo {
+                    id,
+                    last_version,
+                    last_modified,
+                })
+            })
+            .collect()
+    }
+
+    pub fn append(
+        &self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> Result<(), AppendError<FileEventStorageError>> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        self.repair_log_if_pending()?;
+        // ロックを保持したまま最新バージョンの確認と書き込みを行う
+        let (_guard, mut file, content) = self.open_for_write(id)?;
+        let actual_version = self
+            .parse_events(&content)?
+            .last()
+            .map(|e| e.version)
+            .unwrap_or_default();
+        if actual_version != expected_version {
+            return Err(AppendError::ConcurrencyConflict {
+                expected: expected_version,
+                actual: actual_version,
+            });
+        }
+        self.mark_pending(id)?;
+        self.write_events(&mut file, &content, &events)?;
+        self.append_to_log(id, &events);
+        Ok(())
+    }
+
+    /// ストリームのロックを保持したまま削除済みの印 (tombstone) を作り、Eventを移動または消去する
+    ///
+    /// 全Aggregateを通したログのEventは、 `DeleteMode::Purge` の場合のみ取り除く
+    pub fn delete(&self, id: Id<A>, mode: DeleteMode) -> Result<(), FileEventStorageError> {
+        let file_path = self.file_path(id);
+        let (_guard, _file, content) = self.open_locked(&file_path)?;
+        fs::File::create(self.tombstone_path(id))?.sync_all()?;
+        remove_if_exists(&self.pending_path(id))?;
+
+        match mode {
+            DeleteMode::Archive if !content.is_empty() => {
+                fs::DirBuilder::new()
+                    .recursive(true)
+                    .create(&self.archive_dir)?;
+                fs::rename(&file_path, self.archive_path(id))?;
+            }
+            DeleteMode::Archive => remove_if_exists(&file_path)?,
+            DeleteMode::Purge => {
+                remove_if_exists(&file_path)?;
+                remove_if_exists(&self.archive_path(id))?;
+                self.log.purge(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_deleted(&self, id: Id<A>) -> bool {
+        self.tombstone_path(id).is_file()
+    }
+
+    pub fn read_archived(
+        &self,
+        id: Id<A>,
+    ) -> Result<Vec<VersionedEvent<A>>, FileEventStorageError> {
+        let content = self.read_locked(&self.archive_path(id))?;
+        self.parse_events(&content)
+    }
+}
+
+/// プロセス内で書き込み中のストリームのファイル
+static WRITING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+static RELEASED: Condvar = Condvar::new();
+
+/// ストリームへの書き込みを、プロセス内で排他する
+///
+/// ファイルロックの挙動はプラットフォームによって異なり、同じプロセス内で開いたファイル同士では
+/// 排他されないこともあるため、最新バージョンの確認から書き込みまではファイルロックによらずに排他する
+struct StreamGuard(PathBuf);
+
+impl StreamGuard {
+    /// `timeout` を過ぎても解放されなければ `FileEventStorageError::Locked` とする
+    fn acquire(file_path: &Path, timeout: Duration) -> Result<Self, FileEventStorageError> {
+        let deadline = Instant::now() + timeout;
+        let mut writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+        while writing.iter().any(|p| p == file_path) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(FileEventStorageError::Locked);
+            }
+            writing = RELEASED
+                .wait_timeout(writing, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        writing.push(file_path.to_owned());
+        Ok(StreamGuard(file_path.to_owned()))
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+        writing.retain(|p| *p != self.0);
+        RELEASED.notify_all();
+    }
+}
+
+fn contains(content: &[u8], needle: &[u8]) -> bool {
+    content.windows(needle.len()).any(|w| w == needle)
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), io::Error> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
@@ -1,80 +1,80 @@
-use std::fs;
-use std::path::PathBuf;
-
-use failure::Fail;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use serde_json::json;
 
+use cqrs_es::store::upcaster::Upcasters;
 use cqrs_es::*;
+use cqrs_es_testkit::{TestCommand, TestCommandError};
 
-pub struct TestContext {
-    dir: PathBuf,
-}
+/// スキーマバージョンを上げる前の `UpcastedAggregate`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LegacyAggregate;
 
-impl TestContext {
-    pub fn new() -> TestContext {
-        let mut dir = PathBuf::new();
-        dir.push("target");
-        dir.push("tests");
-        dir.push(Uuid::new_v4().to_string());
-        TestContext { dir }
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LegacyEvent {
+    Increased,
+}
 
-    pub fn dir(&self) -> PathBuf {
-        self.dir.clone()
-    }
+impl Event<LegacyAggregate> for LegacyEvent {
+    fn apply_to(self, _aggregate: &mut LegacyAggregate) {}
 }
 
-impl Drop for TestContext {
-    fn drop(&mut self) {
-        fs::remove_dir_all(self.dir.as_path()).unwrap();
+impl Command<LegacyAggregate> for TestCommand {
+    type Events = Option<LegacyEvent>;
+    type Error = TestCommandError;
+
+    fn execute_on(self, _aggregate: &LegacyAggregate) -> Result<Self::Events, Self::Error> {
+        Ok(Some(LegacyEvent::Increased))
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub struct TestAggregate(pub u64);
+impl Aggregate for LegacyAggregate {
+    type Event = LegacyEvent;
+    type Command = TestCommand;
 
-impl Default for TestAggregate {
-    fn default() -> Self {
-        TestAggregate(0)
+    fn type_name() -> &'static str {
+        "test/upcasted"
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub enum TestEvent {
-    Increased,
+/// スキーマバージョンを上げ、Eventに増やす量を持たせたAggregate
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub struct UpcastedAggregate(pub u64);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UpcastedEvent {
+    Increased { amount: u64 },
 }
 
-impl Event<TestAggregate> for TestEvent {
-    fn apply_to(self, aggregate: &mut TestAggregate) {
-        aggregate.0 = aggregate.0 + 1
+impl Event<UpcastedAggregate> for UpcastedEvent {
+    fn apply_to(self, aggregate: &mut UpcastedAggregate) {
+        match self {
+            UpcastedEvent::Increased { amount } => aggregate.0 += amount,
+        }
     }
-}
 
-pub struct TestCommand {}
+    fn schema_version() -> u32 {
+        2
+    }
 
-#[derive(Fail, Debug, Eq, PartialEq)]
-#[fail(display = "Invalid")]
-pub struct TestCommandError {
-    // Invalid,
+    fn upcasters() -> Upcasters {
+        Upcasters::new().register(1, |_| Ok(json!({ "Increased": { "amount": 1 } })))
+    }
 }
 
-impl CommandError for TestCommandError {}
-
-impl Command<TestAggregate> for TestCommand {
-    type Events = Option<TestEvent>;
+impl Command<UpcastedAggregate> for TestCommand {
+    type Events = Option<UpcastedEvent>;
     type Error = TestCommandError;
 
-    fn execute_on(self, _aggregate: &TestAggregate) -> Result<Self::Events, Self::Error> {
-        Ok(Some(TestEvent::Increased))
+    fn execute_on(self, _aggregate: &UpcastedAggregate) -> Result<Self::Events, Self::Error> {
+        Ok(Some(UpcastedEvent::Increased { amount: 2 }))
     }
 }
 
-impl Aggregate for TestAggregate {
-    type Event = TestEvent;
+impl Aggregate for UpcastedAggregate {
+    type Event = UpcastedEvent;
     type Command = TestCommand;
 
     fn type_name() -> &'static str {
-        "test"
+        "test/upcasted"
     }
 }
@@ -0,0 +1,22 @@
+extern crate cqrs_es_testkit;
+extern crate eventstorage_file;
+
+use cqrs_es_testkit::{StorageFactory, TestAggregate, TestDir, TestEvent};
+use eventstorage_file::{FileEventLog, FileEventStorage};
+
+struct Factory(TestDir);
+
+impl StorageFactory for Factory {
+    type Storage = FileEventStorage<TestAggregate, TestEvent>;
+    type Log = FileEventLog;
+
+    fn open(&self) -> Self::Storage {
+        FileEventStorage::new(self.0.path()).unwrap()
+    }
+
+    fn open_log(&self) -> Self::Log {
+        FileEventLog::new(self.0.path()).unwrap()
+    }
+}
+
+cqrs_es_testkit::event_storage_conformance!(Factory(TestDir::new()));
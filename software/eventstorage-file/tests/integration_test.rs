@@ -1,49 +1,664 @@
-extern crate failure;
-extern crate uuid;
-
+//! ファイル固有の振る舞いの検証
+//!
+//! バックエンドによらない振る舞いは `conformance.rs` で `cqrs_es_testkit` のスイートを使って検証する
 extern crate cqrs_es;
+extern crate cqrs_es_testkit;
 extern crate eventstorage_file;
+extern crate fs2;
+extern crate futures;
+extern crate serde_json;
+
+use std::fs;
+use std::time::Duration;
 
+use cqrs_es::bus::CommandBus;
+use cqrs_es::saga::{SagaRunner, SagaStore};
+use cqrs_es::store::snapshot::{SnapshotPolicy, SnapshotStore};
 use cqrs_es::store::*;
 use cqrs_es::*;
 
 mod common;
 use common::*;
-use eventstorage_file::FileEventStorage;
+use cqrs_es_testkit::{CountingSaga, TestAggregate, TestCommand, TestDir, TestEvent};
+use eventstorage_file::{
+    AsyncFileEventStorage, BincodeCodec, CborCodec, Codec, FileEventLog, FileEventStorage,
+    FileEventStorageError, FileSagaStore, FileSnapshotStore, MessagePackCodec,
+};
+use fs2::FileExt;
+use futures::executor::block_on;
+use futures::future;
 
 #[test]
-fn it_works() {
-    let ctx = TestContext::new();
-    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.dir()).unwrap();
+fn snapshot() {
+    let ctx = TestDir::new();
+    let mut events_dir = ctx.path().to_path_buf();
+    events_dir.push("events");
+    let mut snapshots_dir = ctx.path().to_path_buf();
+    snapshots_dir.push("snapshots");
+
+    let snapshot_store = FileSnapshotStore::<TestAggregate>::new(&snapshots_dir).unwrap();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(&events_dir)
+        .unwrap()
+        .with_snapshot_store(snapshot_store, SnapshotPolicy::Every(2));
 
     let id = Id::<TestAggregate>::new();
-    storage
-        .insert(
+    (0..3).for_each(|_| storage.execute_command(id, TestCommand::Increase).unwrap());
+
+    let mut snapshot_store = FileSnapshotStore::<TestAggregate>::new(&snapshots_dir).unwrap();
+    let snapshot = snapshot_store.load(id).unwrap().unwrap();
+    assert_eq!(snapshot.version, Version(2));
+    assert_eq!(snapshot.aggregate, TestAggregate(2));
+
+    // スナップショット以前のEventが再適用されないことを確認する
+    snapshot_store
+        .save(
             id,
-            VersionedEvent {
-                version: Version(1),
-                event: TestEvent::Increased,
+            &VersionedAggregate {
+                version: Version(2),
+                aggregate: TestAggregate(100),
             },
         )
         .unwrap();
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(3));
+    assert_eq!(aggregate.aggregate, TestAggregate(101));
+}
+
+#[test]
+fn snapshot_in_same_directory() {
+    let ctx = TestDir::new();
+    let snapshot_store = FileSnapshotStore::<TestAggregate>::new(ctx.path()).unwrap();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path())
+        .unwrap()
+        .with_snapshot_store(snapshot_store, SnapshotPolicy::Every(2));
+
+    let id = Id::<TestAggregate>::new();
+    (0..3).for_each(|_| storage.execute_command(id, TestCommand::Increase).unwrap());
+
+    assert_eq!(storage.read(id).unwrap().len(), 3);
+    let ids: Vec<_> = storage
+        .list_ids(Paging::all())
+        .unwrap()
+        .into_iter()
+        .map(|s| s.id)
+        .collect();
+    assert_eq!(ids, vec![id]);
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(3));
+    assert_eq!(aggregate.aggregate, TestAggregate(3));
+}
+
+#[test]
+fn delete_archive_removes_snapshot() {
+    let ctx = TestDir::new();
+    let snapshots_dir = ctx.path().join("snapshots");
+    let snapshot_store = FileSnapshotStore::<TestAggregate>::new(&snapshots_dir).unwrap();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path())
+        .unwrap()
+        .with_snapshot_store(snapshot_store, SnapshotPolicy::Every(1));
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    storage.delete(id, DeleteMode::Archive).unwrap();
+
+    let snapshot_store = FileSnapshotStore::<TestAggregate>::new(&snapshots_dir).unwrap();
+    assert!(snapshot_store.load(id).unwrap().is_none());
+}
+
+#[test]
+fn delete_purge_replaces_log() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    storage.delete(id, DeleteMode::Purge).unwrap();
+
+    // ログは一時ファイルに書き込んでから置き換える
+    assert!(ctx.path().join("$all").exists());
+    assert!(!ctx.path().join("$all.tmp").exists());
+}
+
+#[test]
+fn append_batch() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+
+    let id = Id::<TestAggregate>::new();
+    let events: Vec<_> = (1..=3)
+        .map(|v| VersionedEvent {
+            version: Version(v),
+            event: TestEvent::Increased,
+            metadata: Metadata::new(),
+        })
+        .collect();
+    storage.append(id, Version(0), events).unwrap();
+
+    let mut file_path = ctx.path().to_path_buf();
+    file_path.push(TestAggregate::type_name());
+    file_path.push(id.to_string());
+    let content = fs::read_to_string(file_path).unwrap();
+    let mut lines = content.lines();
+    assert_eq!(lines.next(), Some("$format json"));
+    assert_eq!(lines.count(), 1);
+
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(3));
+    assert_eq!(aggregate.aggregate, TestAggregate(3));
+}
+
+#[test]
+fn torn_write() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+
+    let id = Id::<TestAggregate>::new();
+    let event = |v| VersionedEvent {
+        version: Version(v),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    };
+    storage.append(id, Version(0), vec![event(1)]).unwrap();
+
+    // 2件目以降のバッチの書き込みが途中で中断された状態を再現する
+    let mut file_path = ctx.path().to_path_buf();
+    file_path.push(TestAggregate::type_name());
+    file_path.push(id.to_string());
+    let mut content = fs::read(&file_path).unwrap();
+    content.extend_from_slice(br#"[{"version":2,"event":"Increased"},{"vers"#);
+    fs::write(&file_path, content).unwrap();
+
+    let events = storage.read(id).unwrap();
+    assert_eq!(events.len(), 1);
+
+    storage
+        .append(id, Version(1), vec![event(2), event(3)])
+        .unwrap();
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(3));
+    assert_eq!(aggregate.aggregate, TestAggregate(3));
+}
+
+fn codec_round_trip<C: Codec>(codec: C) {
+    let ctx = TestDir::new();
+    let mut storage =
+        FileEventStorage::<TestAggregate, TestEvent, _>::with_codec(ctx.path(), codec).unwrap();
+
+    let id = Id::<TestAggregate>::new();
+    let metadata = Metadata::new()
+        .with_recorded_at("2020-01-02T03:04:05Z".parse().unwrap())
+        .with_actor("nisshiee")
+        .with_correlation_id(uuid::Uuid::new_v4())
+        .with_header("source", "test");
+    let event = |v| VersionedEvent {
+        version: Version(v),
+        event: TestEvent::Increased,
+        metadata: metadata.clone(),
+    };
+    storage.insert(id, event(1)).unwrap();
+    storage
+        .append(id, Version(1), vec![event(2), event(3)])
+        .unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    let events = storage.read(id).unwrap();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[2].metadata, metadata);
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(4));
+    assert_eq!(aggregate.aggregate, TestAggregate(4));
+    let got = storage.list_ids(Paging::all()).unwrap();
+    assert_eq!(got[0].last_version, Version(4));
+}
+
+#[test]
+fn cbor_codec() {
+    codec_round_trip(CborCodec);
+}
+
+#[test]
+fn message_pack_codec() {
+    codec_round_trip(MessagePackCodec);
+}
+
+#[test]
+fn bincode_codec() {
+    codec_round_trip(BincodeCodec);
+}
+
+/// 古いスキーマバージョンで書き込んだストリームに追記し、Upcastして読み込む
+fn codec_upcast<C: Codec + Copy>(codec: C) {
+    let ctx = TestDir::new();
+    let id = Id::<UpcastedAggregate>::new();
+    let mut storage =
+        FileEventStorage::<LegacyAggregate, LegacyEvent, _>::with_codec(ctx.path(), codec).unwrap();
+    storage
+        .execute_command(Id::from(*id.as_uuid()), TestCommand::Increase)
+        .unwrap();
+    drop(storage);
+
+    let mut storage =
+        FileEventStorage::<UpcastedAggregate, UpcastedEvent, _>::with_codec(ctx.path(), codec)
+            .unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(2));
+    assert_eq!(aggregate.aggregate, UpcastedAggregate(3));
+    let got = storage.read_from(id, Version(2)).unwrap();
+    assert_eq!(got.len(), 1);
+}
+
+#[test]
+fn cbor_codec_upcast() {
+    codec_upcast(CborCodec);
+}
+
+#[test]
+fn bincode_codec_upcast() {
+    codec_upcast(BincodeCodec);
+}
+
+#[test]
+fn snapshot_with_codec() {
+    let ctx = TestDir::new();
+    let mut cbor_store =
+        FileSnapshotStore::<TestAggregate, _>::with_codec(ctx.path(), CborCodec).unwrap();
+    let mut bincode_store =
+        FileSnapshotStore::<TestAggregate, _>::with_codec(ctx.path(), BincodeCodec).unwrap();
+
+    let id1 = Id::<TestAggregate>::new();
+    let id2 = Id::<TestAggregate>::new();
+    let aggregate = |v| VersionedAggregate {
+        version: Version(v),
+        aggregate: TestAggregate(v),
+    };
+    cbor_store.save(id1, &aggregate(1)).unwrap();
+    bincode_store.save(id2, &aggregate(2)).unwrap();
+
+    // ヘッダーの形式で読むため、他の形式で保存されたスナップショットも読める
+    let got = bincode_store.load(id1).unwrap().unwrap();
+    assert_eq!(got.aggregate, TestAggregate(1));
+    let got = FileSnapshotStore::<TestAggregate>::new(ctx.path())
+        .unwrap()
+        .load(id2)
+        .unwrap()
+        .unwrap();
+    assert_eq!(got.aggregate, TestAggregate(2));
+}
+
+#[test]
+fn mixed_formats() {
+    let ctx = TestDir::new();
+    let mut dir = ctx.path().to_path_buf();
+    dir.push(TestAggregate::type_name());
+    fs::create_dir_all(&dir).unwrap();
+
+    // 形式を選べるようになる前に書き込まれた、ヘッダーのないファイル
+    let legacy = Id::<TestAggregate>::new();
+    fs::write(
+        dir.join(legacy.to_string()),
+        "{\"version\":1,\"event\":\"Increased\"}\n",
+    )
+    .unwrap();
+
+    let mut storage =
+        FileEventStorage::<TestAggregate, TestEvent, _>::with_codec(ctx.path(), CborCodec).unwrap();
+    let cbor = Id::<TestAggregate>::new();
+    storage
+        .execute_command(cbor, TestCommand::Increase)
+        .unwrap();
+    // 既存のファイルには、そのファイルの形式で追記する
     storage
-        .insert(
+        .execute_command(legacy, TestCommand::Increase)
+        .unwrap();
+    assert!(fs::read(dir.join(legacy.to_string()))
+        .unwrap()
+        .starts_with(b"{"));
+    assert!(fs::read(dir.join(cbor.to_string()))
+        .unwrap()
+        .starts_with(b"$format cbor\n"));
+
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    storage
+        .execute_command(cbor, TestCommand::Increase)
+        .unwrap();
+    assert_eq!(
+        storage.replay_aggregate(legacy).unwrap().version,
+        Version(2)
+    );
+    assert_eq!(storage.replay_aggregate(cbor).unwrap().version, Version(2));
+    assert_eq!(storage.list_ids(Paging::all()).unwrap().len(), 2);
+}
+
+#[test]
+fn unknown_format() {
+    let ctx = TestDir::new();
+    let storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+
+    let id = Id::<TestAggregate>::new();
+    let mut file_path = ctx.path().to_path_buf();
+    file_path.push(TestAggregate::type_name());
+    file_path.push(id.to_string());
+    fs::write(&file_path, "$format unknown\n").unwrap();
+
+    match storage.read(id) {
+        Err(FileEventStorageError::UnknownFormat(name)) => assert_eq!(name, "unknown"),
+        r => panic!("expected UnknownFormat, but got {:?}", r.map(|e| e.len())),
+    }
+}
+
+#[test]
+fn torn_write_length_prefixed() {
+    let ctx = TestDir::new();
+    let mut storage =
+        FileEventStorage::<TestAggregate, TestEvent, _>::with_codec(ctx.path(), BincodeCodec)
+            .unwrap();
+
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    // 長さまで書き込んだところで中断された状態を再現する
+    let mut file_path = ctx.path().to_path_buf();
+    file_path.push(TestAggregate::type_name());
+    file_path.push(id.to_string());
+    let mut content = fs::read(&file_path).unwrap();
+    content.extend_from_slice(&[0, 0, 0, 100, 1, 2, 3]);
+    fs::write(&file_path, content).unwrap();
+
+    assert_eq!(storage.read(id).unwrap().len(), 1);
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(2));
+    assert_eq!(aggregate.aggregate, TestAggregate(2));
+}
+
+fn lock_stream(ctx: &TestDir, id: Id<TestAggregate>) -> fs::File {
+    let mut file_path = ctx.path().to_path_buf();
+    file_path.push(TestAggregate::type_name());
+    file_path.push(id.to_string());
+    fs::File::open(file_path).unwrap()
+}
+
+#[test]
+fn locked_by_other_process() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path())
+        .unwrap()
+        .with_lock_timeout(Duration::from_millis(50));
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    // 他のプロセスが書き込み中の状態を再現する
+    let file = lock_stream(&ctx, id);
+    FileExt::lock_exclusive(&file).unwrap();
+    match storage.read(id) {
+        Err(FileEventStorageError::Locked) => {}
+        r => panic!("expected Locked, but got {:?}", r.map(|e| e.len())),
+    }
+    match storage.execute_command(id, TestCommand::Increase) {
+        Err(ExecuteCommandError::ReplayAggregate(ReplayAggregateError::Read(
+            FileEventStorageError::Locked,
+        ))) => {}
+        r => panic!("expected Locked, but got {:?}", r),
+    }
+
+    drop(file);
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    assert_eq!(storage.replay_aggregate(id).unwrap().version, Version(2));
+}
+
+#[test]
+fn snapshot_store_locked_by_other_process() {
+    let ctx = TestDir::new();
+    let mut snapshot_store = FileSnapshotStore::<TestAggregate>::new(ctx.path())
+        .unwrap()
+        .with_lock_timeout(Duration::from_millis(50));
+    let id = Id::<TestAggregate>::new();
+    let snapshot = VersionedAggregate {
+        version: Version(1),
+        aggregate: TestAggregate(1),
+    };
+    snapshot_store.save(id, &snapshot).unwrap();
+
+    // 他のプロセスがスナップショットを置き換え中の状態を再現する
+    let mut file_path = ctx.path().to_path_buf();
+    file_path.push(TestAggregate::type_name());
+    file_path.push(format!("{}.snapshot.lock", id));
+    let file = fs::File::open(&file_path).unwrap();
+    FileExt::lock_exclusive(&file).unwrap();
+    match snapshot_store.save(id, &snapshot) {
+        Err(FileEventStorageError::Locked) => {}
+        r => panic!("expected Locked, but got {:?}", r),
+    }
+    assert_eq!(
+        snapshot_store.load(id).unwrap().unwrap().version,
+        Version(1)
+    );
+    drop(file);
+
+    // 他のプロセスがスナップショットを読み込み中でも、置き換えられる
+    let file = fs::File::open(file_path.with_extension("")).unwrap();
+    FileExt::lock_shared(&file).unwrap();
+    snapshot_store.save(id, &snapshot).unwrap();
+    drop(file);
+    snapshot_store.delete(id).unwrap();
+    assert!(snapshot_store.load(id).unwrap().is_none());
+}
+
+#[test]
+fn shared_lock_blocks_only_writes() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path())
+        .unwrap()
+        .with_lock_timeout(Duration::from_millis(50));
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    // 他のプロセスが読み込み中の状態を再現する
+    let file = lock_stream(&ctx, id);
+    FileExt::lock_shared(&file).unwrap();
+    assert_eq!(storage.read(id).unwrap().len(), 1);
+    let event = VersionedEvent {
+        version: Version(2),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    };
+    match storage.append(id, Version(1), vec![event.clone()]) {
+        Err(AppendError::Storage(FileEventStorageError::Locked)) => {}
+        r => panic!("expected Locked, but got {:?}", r),
+    }
+
+    drop(file);
+    storage.append(id, Version(1), vec![event]).unwrap();
+    assert_eq!(storage.read(id).unwrap().len(), 2);
+}
+
+#[test]
+fn read_all_backfills_existing_streams() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    // ログを導入する前に作られたストリーム
+    fs::remove_file(ctx.path().join("$all")).unwrap();
+
+    FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+
+    let log = FileEventLog::new(ctx.path()).unwrap();
+    let got: Vec<_> = log
+        .read_all(Position::default())
+        .unwrap()
+        .into_iter()
+        .map(|e| {
+            let (id, event) = e.decode::<TestAggregate, _>().unwrap().unwrap();
+            (e.position, id, event.version)
+        })
+        .collect();
+    assert_eq!(
+        got,
+        vec![(Position(1), id, Version(1)), (Position(2), id, Version(2))]
+    );
+}
+
+#[test]
+fn read_all_repairs_missing_events() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    let id1 = Id::<TestAggregate>::new();
+    let id2 = Id::<TestAggregate>::new();
+    storage.execute_command(id1, TestCommand::Increase).unwrap();
+    storage.execute_command(id2, TestCommand::Increase).unwrap();
+    storage.execute_command(id1, TestCommand::Increase).unwrap();
+    // ストリームへの書き込み後、ログへの書き込み前に中断された
+    let log_path = ctx.path().join("$all");
+    let content = fs::read(&log_path).unwrap();
+    let first_line = content.iter().position(|b| *b == b'\n').unwrap() + 1;
+    fs::write(&log_path, &content[..first_line]).unwrap();
+    let log = FileEventLog::new(ctx.path()).unwrap();
+
+    // 書き込み途中の印がなければ、ストリームを読まない
+    FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    assert_eq!(log.read_all(Position::default()).unwrap().len(), 1);
+
+    let dir = ctx.path().join(TestAggregate::type_name());
+    for id in &[id1, id2] {
+        fs::write(dir.join(format!("{}.pending", id)), b"").unwrap();
+    }
+    // 何度開いても重複して追記しない
+    FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+
+    assert!(!dir.join(format!("{}.pending", id1)).exists());
+    let mut got: Vec<_> = log
+        .read_all(Position::default())
+        .unwrap()
+        .into_iter()
+        .map(|e| {
+            let (id, event) = e.decode::<TestAggregate, _>().unwrap().unwrap();
+            (id, event.version)
+        })
+        .collect();
+    got.sort();
+    let mut expected = vec![(id1, Version(1)), (id2, Version(1)), (id1, Version(2))];
+    expected.sort();
+    assert_eq!(got, expected);
+}
+
+#[test]
+fn async_file_event_storage() {
+    let ctx = TestDir::new();
+    let mut storage = AsyncFileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+
+    let id = Id::<TestAggregate>::new();
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+    let got = block_on(storage.append(id, Version(1), vec![]));
+    assert!(got.is_ok());
+    let got = block_on(storage.append(
+        id,
+        Version(1),
+        vec![VersionedEvent {
+            version: Version(2),
+            event: TestEvent::Increased,
+            metadata: Metadata::new(),
+        }],
+    ));
+    if let Err(AppendError::ConcurrencyConflict { expected, actual }) = got {
+        assert_eq!(expected, Version(1));
+        assert_eq!(actual, Version(2));
+    } else {
+        panic!();
+    }
+
+    let aggregate = block_on(storage.replay_aggregate(id)).unwrap();
+    assert_eq!(aggregate.version, Version(2));
+    assert_eq!(aggregate.aggregate, TestAggregate(2));
+
+    // 同期版と同じファイルを読み書きする
+    let storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(2));
+}
+
+#[test]
+fn async_file_event_storage_ignores_snapshots() {
+    let ctx = TestDir::new();
+    let mut storage = AsyncFileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    let id = Id::<TestAggregate>::new();
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+
+    // 同じディレクトリのスナップショットを使わず、常に全てのEventから復元する
+    let mut snapshot_store = FileSnapshotStore::<TestAggregate>::new(ctx.path()).unwrap();
+    snapshot_store
+        .save(
             id,
-            VersionedEvent {
+            &VersionedAggregate {
                 version: Version(2),
-                event: TestEvent::Increased,
+                aggregate: TestAggregate(100),
             },
         )
         .unwrap();
+    let aggregate = block_on(storage.replay_aggregate(id)).unwrap();
+    assert_eq!(aggregate.version, Version(2));
+    assert_eq!(aggregate.aggregate, TestAggregate(2));
+}
 
-    let mut events = storage.read(id).unwrap();
-    assert_eq!(events.len(), 2);
+#[test]
+fn async_file_event_storage_concurrently() {
+    let ctx = TestDir::new();
+    let storage = AsyncFileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    let ids: Vec<_> = (0..4).map(|_| Id::<TestAggregate>::new()).collect();
+
+    let appends = ids.iter().map(|id| {
+        let mut storage =
+            AsyncFileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+        let id = *id;
+        async move {
+            for _ in 0..10 {
+                storage
+                    .execute_command(id, TestCommand::Increase)
+                    .await
+                    .unwrap();
+            }
+        }
+    });
+    block_on(future::join_all(appends));
 
-    let VersionedEvent { version, event } = events.remove(0);
-    assert_eq!(version, Version(1));
-    assert_eq!(event, TestEvent::Increased);
+    let got = block_on(storage.list_ids(Paging::all())).unwrap();
+    assert_eq!(got.len(), 4);
+    assert!(got.iter().all(|s| s.last_version == Version(10)));
+}
 
-    let VersionedEvent { version, event } = events.remove(0);
-    assert_eq!(version, Version(2));
-    assert_eq!(event, TestEvent::Increased);
+#[test]
+fn saga_state_survives_restart() {
+    let ctx = TestDir::new();
+    let mut storage = FileEventStorage::<TestAggregate, TestEvent>::new(ctx.path()).unwrap();
+    let id = Id::<TestAggregate>::new();
+    (0..3).for_each(|_| storage.execute_command(id, TestCommand::Increase).unwrap());
+    let mut bus = CommandBus::new();
+
+    let mut runner = SagaRunner::new(
+        FileEventLog::new(ctx.path()).unwrap(),
+        FileSagaStore::<CountingSaga>::new(ctx.path()).unwrap(),
+    );
+    assert_eq!(runner.run(&mut bus).unwrap(), 3);
+
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    let mut runner = SagaRunner::new(
+        FileEventLog::new(ctx.path()).unwrap(),
+        FileSagaStore::<CountingSaga>::new(ctx.path()).unwrap(),
+    );
+    assert_eq!(runner.run(&mut bus).unwrap(), 1);
+
+    let state = FileSagaStore::<CountingSaga>::new(ctx.path())
+        .unwrap()
+        .load()
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.position, Position(4));
+    assert_eq!(state.saga.count, 4);
 }
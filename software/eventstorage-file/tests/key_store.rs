@@ -0,0 +1,65 @@
+//! `shredding` フィーチャーを有効にした場合のみ使える `FileKeyStore` の検証
+extern crate cqrs_es;
+extern crate cqrs_es_testkit;
+extern crate eventstorage_file;
+
+use std::fs;
+
+use cqrs_es::shredding::{KeyStore, PersonalData, Revealed, SubjectId};
+use cqrs_es_testkit::TestDir;
+use eventstorage_file::FileKeyStore;
+
+#[test]
+fn key_store_survives_restart() {
+    let ctx = TestDir::new();
+    let subject = SubjectId("nisshiee/1".to_owned());
+
+    let mut key_store = FileKeyStore::new(ctx.path()).unwrap();
+    let data = PersonalData::encrypt(subject.clone(), &"Hiro".to_owned(), &mut key_store).unwrap();
+    drop(key_store);
+
+    let mut key_store = FileKeyStore::new(ctx.path()).unwrap();
+    assert_eq!(
+        data.reveal(&key_store).unwrap(),
+        Revealed::Value("Hiro".to_owned())
+    );
+    key_store.delete(&subject).unwrap();
+    drop(key_store);
+
+    let key_store = FileKeyStore::new(ctx.path()).unwrap();
+    assert_eq!(data.reveal(&key_store).unwrap(), Revealed::Redacted);
+}
+
+#[test]
+fn key_store_generates_one_key_concurrently() {
+    let ctx = TestDir::new();
+    // ファイル名の長さの上限を超える主体ID
+    let subject = SubjectId("x".repeat(300));
+
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let dir = ctx.path().to_path_buf();
+            let subject = subject.clone();
+            std::thread::spawn(move || {
+                let mut key_store = FileKeyStore::new(dir).unwrap();
+                key_store.load_or_generate(&subject).unwrap().id
+            })
+        })
+        .collect();
+    let ids: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    assert!(ids.iter().all(|id| *id == ids[0]));
+    // 置き換えの排他に使うロックファイルは除く
+    let files: Vec<_> = fs::read_dir(ctx.path().join("$keys"))
+        .unwrap()
+        .map(|e| e.unwrap())
+        .filter(|e| e.path().extension().is_none())
+        .collect();
+    assert_eq!(files.len(), 1);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = files[0].metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
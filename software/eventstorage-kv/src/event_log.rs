@@ -0,0 +1,61 @@
+use cqrs_es::store::event_log::{EventLog, Position, RecordedEvent};
+use redb::TableError;
+use uuid::Uuid;
+
+use crate::{KvDatabase, KvEventStorageError, ARCHIVE, EVENTS, LOG};
+
+/// `KvEventStorage` と同じデータベースから、全Aggregateのストリームに追加されたEventを読み出す
+///
+/// ストリームへの追加と同じトランザクションで記録されるため、ログから欠落することはない。
+/// `DeleteMode::Archive` で削除したストリームのEventは読めるが、 `DeleteMode::Purge` で削除したものは読み飛ばす
+pub struct KvEventLog {
+    db: KvDatabase,
+}
+
+impl KvEventLog {
+    pub fn new(db: &KvDatabase) -> Self {
+        KvEventLog { db: db.clone() }
+    }
+}
+
+impl EventLog for KvEventLog {
+    type Events = Vec<RecordedEvent>;
+    type Error = KvEventStorageError;
+
+    fn read_all(&self, from: Position) -> Result<Self::Events, Self::Error> {
+        let txn = self.db.begin_read()?;
+        let (log, events) = match (txn.open_table(LOG), txn.open_table(EVENTS)) {
+            (Ok(log), Ok(events)) => (log, events),
+            (Err(TableError::TableDoesNotExist(_)), _)
+            | (_, Err(TableError::TableDoesNotExist(_))) => return Ok(Vec::new()),
+            (Err(e), _) | (_, Err(e)) => return Err(e.into()),
+        };
+        let archive = match txn.open_table(ARCHIVE) {
+            Ok(archive) => Some(archive),
+            Err(TableError::TableDoesNotExist(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut recorded = Vec::new();
+        for entry in log.range(from.0..)? {
+            let (position, key) = entry?;
+            let key = key.value();
+            let event = match events.get(key)? {
+                Some(event) => Some(event),
+                None => match archive {
+                    Some(ref archive) => archive.get(key)?,
+                    None => None,
+                },
+            };
+            if let Some(event) = event {
+                recorded.push(RecordedEvent {
+                    position: Position(position.value()),
+                    type_name: key.0.to_owned(),
+                    id: Uuid::from_u128(key.1),
+                    event: serde_json::from_slice(event.value())?,
+                });
+            }
+        }
+        Ok(recorded)
+    }
+}
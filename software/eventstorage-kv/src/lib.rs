@@ -0,0 +1,423 @@
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+extern crate redb;
+extern crate serde;
+extern crate serde_json;
+extern crate uuid;
+
+use std::fs;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+
+use cqrs_es::projector::Projector;
+use cqrs_es::store::snapshot::SnapshotPolicy;
+use cqrs_es::store::*;
+use cqrs_es::*;
+use redb::{
+    CommitError, Database, DatabaseError, ReadTransaction, ReadableTable, StorageError,
+    TableDefinition, TableError, TransactionError, WriteTransaction,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+mod event_log;
+pub use event_log::KvEventLog;
+
+/// `(type_name, id, version)` をキーとしたEvent
+const EVENTS: TableDefinition<(&str, u128, u64), &[u8]> = TableDefinition::new("events");
+/// ストリームごとの最新バージョン
+const STREAMS: TableDefinition<(&str, u128), u64> = TableDefinition::new("streams");
+/// 全Aggregateを通した記録順 ( `Position` ) から、 `EVENTS` のキーを引く
+const LOG: TableDefinition<u64, (&str, u128, u64)> = TableDefinition::new("log");
+const SNAPSHOTS: TableDefinition<(&str, u128), &[u8]> = TableDefinition::new("snapshots");
+const TOMBSTONES: TableDefinition<(&str, u128), ()> = TableDefinition::new("tombstones");
+/// `DeleteMode::Archive` で削除したストリームのEvent
+const ARCHIVE: TableDefinition<(&str, u128, u64), &[u8]> = TableDefinition::new("archive");
+
+/// 1つのファイルに全Aggregateのストリームを保存する、組み込みのキーバリューストア
+///
+/// 同じファイルは1つのプロセスから1度しか開けないため、
+/// 複数のEventStorageで共有する場合はこの値を `clone` して渡す
+#[derive(Clone)]
+pub struct KvDatabase {
+    db: Arc<Database>,
+}
+
+impl KvDatabase {
+    pub fn open<P>(db_path: P) -> Result<Self, KvEventStorageError>
+    where
+        P: AsRef<Path>,
+    {
+        let db_path = db_path.as_ref();
+        if let Some(dir) = db_path.parent() {
+            fs::DirBuilder::new().recursive(true).create(dir)?;
+        }
+        Ok(KvDatabase {
+            db: Arc::new(Database::create(db_path)?),
+        })
+    }
+
+    fn begin_read(&self) -> Result<ReadTransaction, KvEventStorageError> {
+        Ok(self.db.begin_read()?)
+    }
+
+    /// 書き込みトランザクションは同時に1つしか開始できないため、
+    /// 最新バージョンの確認と書き込みを同じトランザクションで行えば競合を検出できる
+    fn begin_write(&self) -> Result<WriteTransaction, KvEventStorageError> {
+        Ok(self.db.begin_write()?)
+    }
+}
+
+pub struct KvEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    db: KvDatabase,
+    projectors: Vec<Box<dyn Projector<A>>>,
+    snapshot_policy: SnapshotPolicy,
+    phantom: PhantomData<fn() -> A>,
+}
+
+impl<A, E> KvEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    pub fn new(db: &KvDatabase) -> Self {
+        KvEventStorage {
+            db: db.clone(),
+            projectors: Vec::new(),
+            snapshot_policy: SnapshotPolicy::Never,
+            phantom: PhantomData,
+        }
+    }
+
+    /// スナップショットは同じデータベースの別のテーブルに保存する
+    pub fn with_snapshot_policy(mut self, policy: SnapshotPolicy) -> Self {
+        self.snapshot_policy = policy;
+        self
+    }
+
+    pub fn add_projector<P>(&mut self, projector: P)
+    where
+        P: Projector<A> + 'static,
+    {
+        self.projectors.push(Box::new(projector))
+    }
+
+    fn stream_key(id: Id<A>) -> (&'static str, u128) {
+        (A::type_name(), id.as_uuid().as_u128())
+    }
+
+    /// 削除済みでなければ、ストリームの最新バージョンを返す
+    fn current_version(txn: &WriteTransaction, id: Id<A>) -> Result<Version, KvEventStorageError> {
+        let stream_key = Self::stream_key(id);
+        if txn.open_table(TOMBSTONES)?.get(stream_key)?.is_some() {
+            return Err(KvEventStorageError::Deleted);
+        }
+        Ok(txn
+            .open_table(STREAMS)?
+            .get(stream_key)?
+            .map(|v| Version(v.value()))
+            .unwrap_or_default())
+    }
+
+    /// Eventを書き込み、全体のログにも記録する
+    fn write_events(
+        txn: &WriteTransaction,
+        id: Id<A>,
+        current_version: Version,
+        events: &[VersionedEvent<A>],
+    ) -> Result<(), KvEventStorageError> {
+        let stream_key = Self::stream_key(id);
+        let mut table = txn.open_table(EVENTS)?;
+        let mut log = txn.open_table(LOG)?;
+        let mut position = log.last()?.map_or(0, |(k, _)| k.value());
+        let mut last_version = current_version;
+        for event in events {
+            let key = (stream_key.0, stream_key.1, event.version.0);
+            let value = serde_json::to_vec(event)?;
+            if table.insert(key, value.as_slice())?.is_some() {
+                return Err(KvEventStorageError::VersionDuplicated(event.version));
+            }
+            position += 1;
+            log.insert(position, key)?;
+            last_version = last_version.max(event.version);
+        }
+        txn.open_table(STREAMS)?
+            .insert(stream_key, last_version.0)?;
+        Ok(())
+    }
+
+    fn is_deleted_in(txn: &ReadTransaction, id: Id<A>) -> Result<bool, KvEventStorageError> {
+        match txn.open_table(TOMBSTONES) {
+            Ok(table) => Ok(table.get(Self::stream_key(id))?.is_some()),
+            Err(TableError::TableDoesNotExist(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// `EVENTS` または `ARCHIVE` から、 `id` のストリームの `from` 以降のEventを読む
+    fn read_table(
+        &self,
+        definition: TableDefinition<(&str, u128, u64), &[u8]>,
+        id: Id<A>,
+        from: Version,
+    ) -> Result<Vec<VersionedEvent<A>>, KvEventStorageError> {
+        let txn = self.db.begin_read()?;
+        let table = match txn.open_table(definition) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let (type_name, id) = Self::stream_key(id);
+        table
+            .range((type_name, id, from.0)..=(type_name, id, u64::MAX))?
+            .map(|entry| {
+                let (_, value) = entry?;
+                Ok(serde_json::from_slice(value.value())?)
+            })
+            .collect()
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum KvEventStorageError {
+    #[fail(display = "redb error: {}", _0)]
+    /// 他のエラーに比べて大きいため、ボックス化して保持する
+    Redb(#[fail(cause)] Box<redb::Error>),
+    #[fail(display = "IO error: {}", _0)]
+    Io(#[fail(cause)] std::io::Error),
+    #[fail(display = "JSON error: {}", _0)]
+    Json(#[fail(cause)] serde_json::Error),
+    #[fail(display = "Version {:?} already exists", _0)]
+    VersionDuplicated(Version),
+    #[fail(display = "Stream deleted")]
+    Deleted,
+}
+
+impl EventStorageError for KvEventStorageError {
+    fn is_deleted(&self) -> bool {
+        matches!(self, KvEventStorageError::Deleted)
+    }
+}
+
+impl From<redb::Error> for KvEventStorageError {
+    fn from(e: redb::Error) -> Self {
+        KvEventStorageError::Redb(Box::new(e))
+    }
+}
+
+impl From<DatabaseError> for KvEventStorageError {
+    fn from(e: DatabaseError) -> Self {
+        KvEventStorageError::Redb(Box::new(e.into()))
+    }
+}
+
+impl From<TransactionError> for KvEventStorageError {
+    fn from(e: TransactionError) -> Self {
+        KvEventStorageError::Redb(Box::new(e.into()))
+    }
+}
+
+impl From<TableError> for KvEventStorageError {
+    fn from(e: TableError) -> Self {
+        KvEventStorageError::Redb(Box::new(e.into()))
+    }
+}
+
+impl From<StorageError> for KvEventStorageError {
+    fn from(e: StorageError) -> Self {
+        KvEventStorageError::Redb(Box::new(e.into()))
+    }
+}
+
+impl From<CommitError> for KvEventStorageError {
+    fn from(e: CommitError) -> Self {
+        KvEventStorageError::Redb(Box::new(e.into()))
+    }
+}
+
+impl From<std::io::Error> for KvEventStorageError {
+    fn from(e: std::io::Error) -> Self {
+        KvEventStorageError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for KvEventStorageError {
+    fn from(e: serde_json::Error) -> Self {
+        KvEventStorageError::Json(e)
+    }
+}
+
+impl<A, E> EventStorage<A> for KvEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    type Events = Vec<VersionedEvent<A>>;
+    type Error = KvEventStorageError;
+
+    fn insert(&mut self, id: Id<A>, event: VersionedEvent<A>) -> Result<(), Self::Error> {
+        let txn = self.db.begin_write()?;
+        let current_version = Self::current_version(&txn, id)?;
+        Self::write_events(&txn, id, current_version, &[event])?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn read(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        if Self::is_deleted_in(&self.db.begin_read()?, id)? {
+            return Err(KvEventStorageError::Deleted);
+        }
+        self.read_table(EVENTS, id, Version::default())
+    }
+
+    /// キーの範囲を絞って、 `from` 以降のEventのみを読む
+    fn read_from(&self, id: Id<A>, from: Version) -> Result<Vec<VersionedEvent<A>>, Self::Error> {
+        if Self::is_deleted_in(&self.db.begin_read()?, id)? {
+            return Err(KvEventStorageError::Deleted);
+        }
+        self.read_table(EVENTS, id, from)
+    }
+
+    fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<A>>, Self::Error> {
+        let txn = self.db.begin_read()?;
+        let (streams, events) = match (txn.open_table(STREAMS), txn.open_table(EVENTS)) {
+            (Ok(streams), Ok(events)) => (streams, events),
+            (Err(TableError::TableDoesNotExist(_)), _)
+            | (_, Err(TableError::TableDoesNotExist(_))) => return Ok(Vec::new()),
+            (Err(e), _) | (_, Err(e)) => return Err(e.into()),
+        };
+        let type_name = A::type_name();
+        // UUIDを数値とみなした順序は、IDの順序と一致する
+        let entries = streams.range((type_name, 0)..=(type_name, u128::MAX))?;
+        paging
+            .apply(entries)
+            .map(|entry| {
+                let (key, last_version) = entry?;
+                let (_, id) = key.value();
+                let last_version = last_version.value();
+                let last = events
+                    .get((type_name, id, last_version))?
+                    .map(|v| serde_json::from_slice::<VersionedEvent<A>>(v.value()))
+                    .transpose()?;
+                Ok(StreamInfo {
+                    id: Id::from(Uuid::from_u128(id)),
+                    last_version: Version(last_version),
+                    last_modified: last.and_then(|e| e.metadata.recorded_at),
+                })
+            })
+            .collect()
+    }
+
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let txn = self.db.begin_write()?;
+        let actual_version = Self::current_version(&txn, id)?;
+        if actual_version != expected_version {
+            return Err(AppendError::ConcurrencyConflict {
+                expected: expected_version,
+                actual: actual_version,
+            });
+        }
+        // 途中で失敗した場合は、コミットせずに破棄されるためひとつも保存されない
+        Self::write_events(&txn, id, actual_version, &events)?;
+        txn.commit().map_err(KvEventStorageError::from)?;
+        Ok(())
+    }
+
+    fn projectors(&mut self) -> &mut [Box<dyn Projector<A>>] {
+        &mut self.projectors
+    }
+
+    fn snapshot_policy(&self) -> SnapshotPolicy {
+        self.snapshot_policy
+    }
+
+    fn load_snapshot(&self, id: Id<A>) -> Result<Option<VersionedAggregate<A>>, Self::Error> {
+        let txn = self.db.begin_read()?;
+        let table = match txn.open_table(SNAPSHOTS) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot = table.get(Self::stream_key(id))?;
+        match snapshot {
+            Some(snapshot) => Ok(Some(serde_json::from_slice(snapshot.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_snapshot(
+        &mut self,
+        id: Id<A>,
+        aggregate: &VersionedAggregate<A>,
+    ) -> Result<(), Self::Error> {
+        let value = serde_json::to_vec(aggregate)?;
+        let txn = self.db.begin_write()?;
+        txn.open_table(SNAPSHOTS)?
+            .insert(Self::stream_key(id), value.as_slice())?;
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+/// ストリームとスナップショットを削除する
+///
+/// `DeleteMode::Purge` で削除したEventは、 `KvEventLog` からも読めなくなる
+impl<A, E> DeletableEventStorage<A> for KvEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    fn delete(&mut self, id: Id<A>, mode: DeleteMode) -> Result<(), Self::Error> {
+        let stream_key = Self::stream_key(id);
+        let (type_name, id) = stream_key;
+        let range = (type_name, id, 0)..=(type_name, id, u64::MAX);
+
+        let txn = self.db.begin_write()?;
+        {
+            txn.open_table(TOMBSTONES)?.insert(stream_key, ())?;
+            txn.open_table(STREAMS)?.remove(stream_key)?;
+            txn.open_table(SNAPSHOTS)?.remove(stream_key)?;
+
+            let mut events = txn.open_table(EVENTS)?;
+            let mut archive = txn.open_table(ARCHIVE)?;
+            match mode {
+                DeleteMode::Archive => {
+                    for entry in events.extract_from_if(range, |_, _| true)? {
+                        let (key, value) = entry?;
+                        archive.insert(key.value(), value.value())?;
+                    }
+                }
+                DeleteMode::Purge => {
+                    events.retain_in(range.clone(), |_, _| false)?;
+                    archive.retain_in(range, |_, _| false)?;
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn is_deleted(&self, id: Id<A>) -> Result<bool, Self::Error> {
+        Self::is_deleted_in(&self.db.begin_read()?, id)
+    }
+
+    fn read_archived(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        self.read_table(ARCHIVE, id, Version::default())
+    }
+}
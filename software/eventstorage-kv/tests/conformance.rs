@@ -0,0 +1,36 @@
+extern crate cqrs_es_testkit;
+extern crate eventstorage_kv;
+
+use cqrs_es_testkit::{StorageFactory, TestAggregate, TestDir, TestEvent};
+use eventstorage_kv::{KvDatabase, KvEventLog, KvEventStorage};
+
+/// データベースは1つのプロセスから1度しか開けないため、開いたものを使い回す
+struct Factory {
+    db: KvDatabase,
+    _dir: TestDir,
+}
+
+impl Factory {
+    fn new() -> Factory {
+        let dir = TestDir::new();
+        Factory {
+            db: KvDatabase::open(dir.path().join("events.db")).unwrap(),
+            _dir: dir,
+        }
+    }
+}
+
+impl StorageFactory for Factory {
+    type Storage = KvEventStorage<TestAggregate, TestEvent>;
+    type Log = KvEventLog;
+
+    fn open(&self) -> Self::Storage {
+        KvEventStorage::new(&self.db)
+    }
+
+    fn open_log(&self) -> Self::Log {
+        KvEventLog::new(&self.db)
+    }
+}
+
+cqrs_es_testkit::event_storage_conformance!(Factory::new());
@@ -0,0 +1,154 @@
+//! KVストア固有の振る舞いの検証
+//!
+//! バックエンドによらない振る舞いは `conformance.rs` で `cqrs_es_testkit` のスイートを使って検証する
+extern crate cqrs_es;
+extern crate cqrs_es_testkit;
+extern crate eventstorage_kv;
+extern crate futures;
+
+use cqrs_es::store::snapshot::SnapshotPolicy;
+use cqrs_es::store::*;
+use cqrs_es::*;
+use cqrs_es_testkit::{TestAggregate, TestCommand, TestDir, TestEvent};
+use eventstorage_kv::{KvDatabase, KvEventLog, KvEventStorage};
+use futures::executor::block_on;
+
+fn event(version: u64) -> VersionedEvent<TestAggregate> {
+    VersionedEvent {
+        version: Version(version),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    }
+}
+
+fn open(dir: &TestDir) -> KvDatabase {
+    KvDatabase::open(dir.path().join("events.db")).unwrap()
+}
+
+#[test]
+fn insert_duplicated_version() {
+    let dir = TestDir::new();
+    let db = open(&dir);
+    let mut storage = KvEventStorage::<TestAggregate, TestEvent>::new(&db);
+
+    let id = Id::<TestAggregate>::new();
+    storage.insert(id, event(1)).unwrap();
+
+    // キーがバージョンを含むため、 `insert` でも同じバージョンは保存されない
+    storage.insert(id, event(1)).unwrap_err();
+    assert_eq!(storage.read(id).unwrap().len(), 1);
+}
+
+#[test]
+fn snapshot() {
+    let dir = TestDir::new();
+    let db = open(&dir);
+    let mut storage = KvEventStorage::<TestAggregate, TestEvent>::new(&db)
+        .with_snapshot_policy(SnapshotPolicy::Every(2));
+
+    let id = Id::<TestAggregate>::new();
+    (0..3).for_each(|_| storage.execute_command(id, TestCommand::Increase).unwrap());
+
+    let snapshot = storage.load_snapshot(id).unwrap().unwrap();
+    assert_eq!(snapshot.version, Version(2));
+    assert_eq!(snapshot.aggregate, TestAggregate(2));
+
+    // スナップショット以前のEventが再適用されないことを確認する
+    storage
+        .save_snapshot(
+            id,
+            &VersionedAggregate {
+                version: Version(2),
+                aggregate: TestAggregate(100),
+            },
+        )
+        .unwrap();
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(3));
+    assert_eq!(aggregate.aggregate, TestAggregate(101));
+}
+
+#[test]
+fn append_batch_is_atomic() {
+    let dir = TestDir::new();
+    let db = open(&dir);
+    let mut storage = KvEventStorage::<TestAggregate, TestEvent>::new(&db);
+
+    let id = Id::<TestAggregate>::new();
+    storage
+        .append(id, Version(0), (1..=3).map(event).collect())
+        .unwrap();
+    // 途中で失敗した場合は、ストリームにもログにもひとつも保存されない
+    storage
+        .append(id, Version(3), vec![event(4), event(4)])
+        .unwrap_err();
+
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(3));
+    let log = KvEventLog::new(&db);
+    assert_eq!(log.read_all(Position::default()).unwrap().len(), 3);
+}
+
+#[test]
+fn reopen_database() {
+    let dir = TestDir::new();
+    let id = Id::<TestAggregate>::new();
+    let deleted = Id::<TestAggregate>::new();
+    let metadata = Metadata::new().with_idempotency_key("increase-1");
+
+    let db = open(&dir);
+    let mut storage = KvEventStorage::<TestAggregate, TestEvent>::new(&db);
+    storage
+        .execute_command_with_metadata(id, TestCommand::Increase, metadata.clone())
+        .unwrap();
+    storage
+        .execute_command(deleted, TestCommand::Increase)
+        .unwrap();
+    storage.delete(deleted, DeleteMode::Archive).unwrap();
+    drop(storage);
+    drop(db);
+
+    // データベースを開き直しても、処理済みのキーと削除済みの記録が残っている
+    let db = open(&dir);
+    let mut storage = KvEventStorage::<TestAggregate, TestEvent>::new(&db);
+    storage
+        .execute_command_with_metadata(id, TestCommand::Increase, metadata)
+        .unwrap();
+    assert_eq!(storage.read(id).unwrap().len(), 1);
+    assert!(storage.is_deleted(deleted).unwrap());
+    assert_eq!(storage.read_archived(deleted).unwrap().len(), 1);
+}
+
+#[test]
+fn delete_archive_removes_snapshot() {
+    let dir = TestDir::new();
+    let db = open(&dir);
+    let mut storage = KvEventStorage::<TestAggregate, TestEvent>::new(&db)
+        .with_snapshot_policy(SnapshotPolicy::Every(1));
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    storage.delete(id, DeleteMode::Archive).unwrap();
+
+    assert!(storage.load_snapshot(id).unwrap().is_none());
+}
+
+#[test]
+fn async_adapter() {
+    let dir = TestDir::new();
+    let db = open(&dir);
+    let mut storage = {
+        let db = db.clone();
+        AsyncAdapter::spawn(move || KvEventStorage::<TestAggregate, TestEvent>::new(&db))
+    };
+
+    let id = Id::<TestAggregate>::new();
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+    block_on(storage.execute_command(id, TestCommand::Increase)).unwrap();
+
+    // 同期版と同じデータベースを読み書きする
+    let storage = KvEventStorage::<TestAggregate, TestEvent>::new(&db);
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(2));
+}
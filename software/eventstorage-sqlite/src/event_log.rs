@@ -0,0 +1,54 @@
+use std::path::Path;
+
+use cqrs_es::store::event_log::{EventLog, Position, RecordedEvent};
+use rusqlite::{params, Connection};
+
+use crate::{open_connection, SqliteEventStorageError};
+
+/// `SqliteEventStorage` と同じデータベースから、全Aggregateのストリームに追加されたEventを読み出す
+///
+/// ストリームへの追加と同じトランザクションで記録されるため、ファイル版と異なりログから欠落することはない
+pub struct SqliteEventLog {
+    conn: Connection,
+}
+
+impl SqliteEventLog {
+    pub fn new<P>(db_path: P) -> Result<Self, SqliteEventStorageError>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(SqliteEventLog {
+            conn: open_connection(db_path.as_ref())?,
+        })
+    }
+}
+
+impl EventLog for SqliteEventLog {
+    type Events = Vec<RecordedEvent>;
+    type Error = SqliteEventStorageError;
+
+    fn read_all(&self, from: Position) -> Result<Self::Events, Self::Error> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT position, type_name, id, event FROM events
+             WHERE position >= ?1 ORDER BY position",
+        )?;
+        let rows = stmt.query_map(params![from.0 as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (position, type_name, id, event) = row?;
+            Ok(RecordedEvent {
+                position: Position(position as u64),
+                type_name,
+                id: id.parse()?,
+                event: serde_json::from_str(&event)?,
+            })
+        })
+        .collect()
+    }
+}
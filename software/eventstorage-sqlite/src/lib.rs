@@ -0,0 +1,423 @@
+extern crate chrono;
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+extern crate rusqlite;
+extern crate serde;
+extern crate serde_json;
+extern crate uuid;
+
+use std::fs;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::time::Duration;
+
+use cqrs_es::projector::Projector;
+use cqrs_es::store::snapshot::SnapshotPolicy;
+use cqrs_es::store::*;
+use cqrs_es::*;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+mod event_log;
+pub use event_log::SqliteEventLog;
+
+/// 他の接続が書き込み中の場合に、ロックの解放を待つ時間
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `events` の `position` は全Aggregateを通した記録順で、 `EventLog` の `Position` にそのまま対応する。
+/// `INTEGER PRIMARY KEY` のため、Positionによる読み出しは主キーで引ける
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    position INTEGER PRIMARY KEY AUTOINCREMENT,
+    type_name TEXT NOT NULL,
+    id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    idempotency_key TEXT,
+    event TEXT NOT NULL,
+    UNIQUE (type_name, id, version)
+);
+CREATE INDEX IF NOT EXISTS events_idempotency_key
+    ON events (type_name, id, idempotency_key) WHERE idempotency_key IS NOT NULL;
+CREATE TABLE IF NOT EXISTS snapshots (
+    type_name TEXT NOT NULL,
+    id TEXT NOT NULL,
+    aggregate TEXT NOT NULL,
+    PRIMARY KEY (type_name, id)
+);
+CREATE TABLE IF NOT EXISTS tombstones (
+    type_name TEXT NOT NULL,
+    id TEXT NOT NULL,
+    PRIMARY KEY (type_name, id)
+);
+";
+
+/// 1つのSQLiteデータベースに、全Aggregateのストリームを保存する
+///
+/// `(type_name, id, version)` の一意制約により、同じデータベースを開いた他の接続とも競合を検出する
+pub struct SqliteEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    conn: Connection,
+    projectors: Vec<Box<dyn Projector<A>>>,
+    snapshot_policy: SnapshotPolicy,
+    phantom: PhantomData<fn() -> A>,
+}
+
+impl<A, E> SqliteEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    pub fn new<P>(db_path: P) -> Result<Self, SqliteEventStorageError>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(SqliteEventStorage {
+            conn: open_connection(db_path.as_ref())?,
+            projectors: Vec::new(),
+            snapshot_policy: SnapshotPolicy::Never,
+            phantom: PhantomData,
+        })
+    }
+
+    /// スナップショットは同じデータベースの `snapshots` テーブルに保存する
+    pub fn with_snapshot_policy(mut self, policy: SnapshotPolicy) -> Self {
+        self.snapshot_policy = policy;
+        self
+    }
+
+    pub fn add_projector<P>(&mut self, projector: P)
+    where
+        P: Projector<A> + 'static,
+    {
+        self.projectors.push(Box::new(projector))
+    }
+}
+
+/// データベースを開き、テーブルがなければ作成する
+fn open_connection(db_path: &Path) -> Result<Connection, SqliteEventStorageError> {
+    if let Some(dir) = db_path.parent() {
+        fs::DirBuilder::new().recursive(true).create(dir)?;
+    }
+    let conn = Connection::open(db_path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    // 書き込み中も他の接続から読めるようにする
+    conn.query_row("PRAGMA journal_mode = WAL", params![], |_| Ok(()))?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+fn last_version<A: Aggregate>(
+    conn: &Connection,
+    id: Id<A>,
+) -> Result<Version, SqliteEventStorageError> {
+    let version: Option<i64> = conn.query_row(
+        "SELECT MAX(version) FROM events WHERE type_name = ?1 AND id = ?2",
+        params![A::type_name(), id.to_string()],
+        |row| row.get(0),
+    )?;
+    Ok(Version(version.unwrap_or_default() as u64))
+}
+
+fn is_deleted<A: Aggregate>(conn: &Connection, id: Id<A>) -> Result<bool, SqliteEventStorageError> {
+    let found: Option<i64> = conn
+        .query_row(
+            "SELECT 1 FROM tombstones WHERE type_name = ?1 AND id = ?2",
+            params![A::type_name(), id.to_string()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(found.is_some())
+}
+
+fn check_deleted<A: Aggregate>(
+    conn: &Connection,
+    id: Id<A>,
+) -> Result<(), SqliteEventStorageError> {
+    if is_deleted(conn, id)? {
+        Err(SqliteEventStorageError::Deleted)
+    } else {
+        Ok(())
+    }
+}
+
+/// `events` テーブルから、ストリームの `from` 以降のEventを読み込む
+fn read_events<A>(
+    conn: &Connection,
+    id: Id<A>,
+    from: Version,
+) -> Result<Vec<VersionedEvent<A>>, SqliteEventStorageError>
+where
+    A: Aggregate + DeserializeOwned,
+    A::Event: DeserializeOwned,
+{
+    let mut stmt = conn.prepare_cached(
+        "SELECT event FROM events WHERE type_name = ?1 AND id = ?2 AND version >= ?3
+         ORDER BY version",
+    )?;
+    let rows = stmt.query_map(
+        params![A::type_name(), id.to_string(), from.0 as i64],
+        |row| row.get::<_, String>(0),
+    )?;
+    rows.map(|event| Ok(serde_json::from_str(&event?)?))
+        .collect()
+}
+
+fn insert_events<A>(
+    conn: &Connection,
+    id: Id<A>,
+    events: &[VersionedEvent<A>],
+) -> Result<(), SqliteEventStorageError>
+where
+    A: Aggregate + Serialize,
+    A::Event: Serialize,
+{
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO events (type_name, id, version, idempotency_key, event)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for event in events {
+        stmt.execute(params![
+            A::type_name(),
+            id.to_string(),
+            event.version.0 as i64,
+            event.metadata.idempotency_key,
+            serde_json::to_string(event)?,
+        ])?;
+    }
+    Ok(())
+}
+
+#[derive(Fail, Debug)]
+pub enum SqliteEventStorageError {
+    #[fail(display = "SQLite error: {}", _0)]
+    Sqlite(#[fail(cause)] rusqlite::Error),
+    #[fail(display = "IO error: {}", _0)]
+    Io(#[fail(cause)] std::io::Error),
+    #[fail(display = "JSON error: {}", _0)]
+    Json(#[fail(cause)] serde_json::Error),
+    #[fail(display = "Invalid ID: {}", _0)]
+    InvalidId(#[fail(cause)] uuid::Error),
+    #[fail(display = "Stream deleted")]
+    Deleted,
+}
+
+impl EventStorageError for SqliteEventStorageError {
+    fn is_deleted(&self) -> bool {
+        matches!(self, SqliteEventStorageError::Deleted)
+    }
+}
+
+impl From<rusqlite::Error> for SqliteEventStorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        SqliteEventStorageError::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for SqliteEventStorageError {
+    fn from(e: std::io::Error) -> Self {
+        SqliteEventStorageError::Io(e)
+    }
+}
+
+impl From<uuid::Error> for SqliteEventStorageError {
+    fn from(e: uuid::Error) -> Self {
+        SqliteEventStorageError::InvalidId(e)
+    }
+}
+
+impl From<serde_json::Error> for SqliteEventStorageError {
+    fn from(e: serde_json::Error) -> Self {
+        SqliteEventStorageError::Json(e)
+    }
+}
+
+impl<A, E> EventStorage<A> for SqliteEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    type Events = Vec<VersionedEvent<A>>;
+    type Error = SqliteEventStorageError;
+
+    fn insert(&mut self, id: Id<A>, event: VersionedEvent<A>) -> Result<(), Self::Error> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        check_deleted(&tx, id)?;
+        insert_events(&tx, id, &[event])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn read(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        check_deleted(&self.conn, id)?;
+        read_events(&self.conn, id, Version::default())
+    }
+
+    /// `(type_name, id, version)` の一意制約のインデックスで、 `from` 以降のEventのみを読む
+    fn read_from(&self, id: Id<A>, from: Version) -> Result<Vec<VersionedEvent<A>>, Self::Error> {
+        check_deleted(&self.conn, id)?;
+        read_events(&self.conn, id, from)
+    }
+
+    fn list_ids(&self, paging: Paging) -> Result<Vec<StreamInfo<A>>, Self::Error> {
+        // Idの文字列表現はUUIDのバイト列と同じ順に並ぶ
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, event FROM events AS e
+             WHERE type_name = ?1
+               AND NOT EXISTS (
+                 SELECT 1 FROM tombstones WHERE type_name = e.type_name AND id = e.id
+               )
+               AND version = (
+                 SELECT MAX(version) FROM events WHERE type_name = e.type_name AND id = e.id
+               )
+             ORDER BY id LIMIT ?2 OFFSET ?3",
+        )?;
+        let limit = paging.limit.map_or(-1, |limit| limit as i64);
+        let rows = stmt.query_map(
+            params![A::type_name(), limit, paging.offset as i64],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        )?;
+        rows.map(|row| {
+            let (id, event) = row?;
+            let id = id.parse()?;
+            let last: VersionedEvent<A> = serde_json::from_str(&event)?;
+            Ok(StreamInfo {
+                id,
+                last_version: last.version,
+                last_modified: last.metadata.recorded_at,
+            })
+        })
+        .collect()
+    }
+
+    /// 書き込みロックを取得したトランザクションの中で、最新バージョンの確認と書き込みを行う
+    fn append(
+        &mut self,
+        id: Id<A>,
+        expected_version: Version,
+        events: Vec<VersionedEvent<A>>,
+    ) -> Result<(), AppendError<Self::Error>> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(SqliteEventStorageError::from)?;
+        check_deleted(&tx, id)?;
+        let actual_version = last_version(&tx, id)?;
+        if actual_version != expected_version {
+            return Err(AppendError::ConcurrencyConflict {
+                expected: expected_version,
+                actual: actual_version,
+            });
+        }
+        // 途中で失敗した場合は、コミットせずに破棄されるためひとつも保存されない
+        insert_events(&tx, id, &events)?;
+        tx.commit().map_err(SqliteEventStorageError::from)?;
+        Ok(())
+    }
+
+    fn projectors(&mut self) -> &mut [Box<dyn Projector<A>>] {
+        &mut self.projectors
+    }
+
+    fn snapshot_policy(&self) -> SnapshotPolicy {
+        self.snapshot_policy
+    }
+
+    fn load_snapshot(&self, id: Id<A>) -> Result<Option<VersionedAggregate<A>>, Self::Error> {
+        let aggregate: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT aggregate FROM snapshots WHERE type_name = ?1 AND id = ?2",
+                params![A::type_name(), id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match aggregate {
+            Some(aggregate) => Ok(Some(serde_json::from_str(&aggregate)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_snapshot(
+        &mut self,
+        id: Id<A>,
+        aggregate: &VersionedAggregate<A>,
+    ) -> Result<(), Self::Error> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO snapshots (type_name, id, aggregate) VALUES (?1, ?2, ?3)",
+            params![
+                A::type_name(),
+                id.to_string(),
+                serde_json::to_string(aggregate)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn is_processed(&self, id: Id<A>, idempotency_key: &str) -> Result<bool, Self::Error> {
+        let found: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM events WHERE type_name = ?1 AND id = ?2 AND idempotency_key = ?3",
+                params![A::type_name(), id.to_string(), idempotency_key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+}
+
+/// 削除済みの印 (tombstone) は `tombstones` テーブルに記録する
+///
+/// `DeleteMode::Archive` ではEventを `events` テーブルに残したまま読めなくし、 `read_archived` で読めるようにする。
+/// そのため `SqliteEventLog` からは、 `DeleteMode::Purge` で削除した場合のみEventが取り除かれる
+impl<A, E> DeletableEventStorage<A> for SqliteEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    fn delete(&mut self, id: Id<A>, mode: DeleteMode) -> Result<(), Self::Error> {
+        let params = params![A::type_name(), id.to_string()];
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "INSERT OR IGNORE INTO tombstones (type_name, id) VALUES (?1, ?2)",
+            params,
+        )?;
+        tx.execute(
+            "DELETE FROM snapshots WHERE type_name = ?1 AND id = ?2",
+            params,
+        )?;
+        if mode == DeleteMode::Purge {
+            tx.execute(
+                "DELETE FROM events WHERE type_name = ?1 AND id = ?2",
+                params,
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn is_deleted(&self, id: Id<A>) -> Result<bool, Self::Error> {
+        is_deleted(&self.conn, id)
+    }
+
+    fn read_archived(&self, id: Id<A>) -> Result<Self::Events, Self::Error> {
+        if is_deleted(&self.conn, id)? {
+            read_events(&self.conn, id, Version::default())
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
@@ -0,0 +1,22 @@
+extern crate cqrs_es_testkit;
+extern crate eventstorage_sqlite;
+
+use cqrs_es_testkit::{StorageFactory, TestAggregate, TestDir, TestEvent};
+use eventstorage_sqlite::{SqliteEventLog, SqliteEventStorage};
+
+struct Factory(TestDir);
+
+impl StorageFactory for Factory {
+    type Storage = SqliteEventStorage<TestAggregate, TestEvent>;
+    type Log = SqliteEventLog;
+
+    fn open(&self) -> Self::Storage {
+        SqliteEventStorage::new(self.0.path().join("events.db")).unwrap()
+    }
+
+    fn open_log(&self) -> Self::Log {
+        SqliteEventLog::new(self.0.path().join("events.db")).unwrap()
+    }
+}
+
+cqrs_es_testkit::event_storage_conformance!(Factory(TestDir::new()));
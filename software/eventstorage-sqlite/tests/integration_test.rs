@@ -0,0 +1,85 @@
+//! SQLite固有の振る舞いの検証
+//!
+//! バックエンドによらない振る舞いは `conformance.rs` で `cqrs_es_testkit` のスイートを使って検証する
+extern crate cqrs_es;
+extern crate cqrs_es_testkit;
+extern crate eventstorage_sqlite;
+
+use cqrs_es::store::snapshot::SnapshotPolicy;
+use cqrs_es::store::*;
+use cqrs_es::*;
+use cqrs_es_testkit::{TestAggregate, TestCommand, TestDir, TestEvent};
+use eventstorage_sqlite::SqliteEventStorage;
+
+fn event(version: u64) -> VersionedEvent<TestAggregate> {
+    VersionedEvent {
+        version: Version(version),
+        event: TestEvent::Increased,
+        metadata: Metadata::new(),
+    }
+}
+
+fn open(dir: &TestDir) -> SqliteEventStorage<TestAggregate, TestEvent> {
+    SqliteEventStorage::new(dir.path().join("events.db")).unwrap()
+}
+
+#[test]
+fn insert_duplicated_version() {
+    let dir = TestDir::new();
+    let mut storage = open(&dir);
+
+    let id = Id::<TestAggregate>::new();
+    storage.insert(id, event(1)).unwrap();
+
+    // 一意制約により、 `insert` でも同じバージョンは保存されない
+    storage.insert(id, event(1)).unwrap_err();
+    assert_eq!(storage.read(id).unwrap().len(), 1);
+}
+
+#[test]
+fn execute_command_with_snapshot() {
+    let dir = TestDir::new();
+    let mut storage = open(&dir).with_snapshot_policy(SnapshotPolicy::Every(2));
+
+    let id = Id::<TestAggregate>::new();
+    (0..3).for_each(|_| storage.execute_command(id, TestCommand::Increase).unwrap());
+
+    let snapshot = storage.load_snapshot(id).unwrap().unwrap();
+    assert_eq!(snapshot.version, Version(2));
+    assert_eq!(snapshot.aggregate, TestAggregate(2));
+    let aggregate = storage.replay_aggregate(id).unwrap();
+    assert_eq!(aggregate.version, Version(3));
+    assert_eq!(aggregate.aggregate, TestAggregate(3));
+}
+
+#[test]
+fn append_batch_is_atomic() {
+    let dir = TestDir::new();
+    let mut storage = open(&dir);
+
+    let id = Id::<TestAggregate>::new();
+    storage
+        .append(id, Version(0), vec![event(1), event(2)])
+        .unwrap();
+    // 途中で一意制約に違反した場合は、ひとつも保存されない
+    storage
+        .append(id, Version(2), vec![event(3), event(3)])
+        .unwrap_err();
+
+    assert_eq!(storage.read(id).unwrap().len(), 2);
+}
+
+#[test]
+fn delete_archive_removes_snapshot() {
+    let dir = TestDir::new();
+    let mut storage = open(&dir).with_snapshot_policy(SnapshotPolicy::Every(1));
+    let id = Id::<TestAggregate>::new();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+    storage.execute_command(id, TestCommand::Increase).unwrap();
+
+    storage.delete(id, DeleteMode::Archive).unwrap();
+    drop(storage);
+
+    let storage = open(&dir);
+    assert!(storage.load_snapshot(id).unwrap().is_none());
+}
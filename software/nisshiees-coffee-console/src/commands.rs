@@ -1,4 +1,3 @@
-use cqrs_es::store::EventStorage;
 use nisshiees_coffee_core::canister_list;
 use nisshiees_coffee_core::seller::stock;
 use structopt::StructOpt;
@@ -7,6 +6,7 @@ use crate::commands::canister::CanisterCommands;
 use crate::commands::seller::SellerCommands;
 use crate::Context;
 
+mod as_of;
 mod canister;
 mod seller;
 
@@ -25,14 +25,10 @@ impl Commands {
         match self {
             Commands::Init => {
                 let cmd = canister_list::CanisterListCommand::Create;
-                ctx.canister_list_storage
-                    .execute_command(ctx.default_canister_list_id, cmd)
-                    .unwrap();
+                ctx.dispatch(cmd).unwrap();
 
                 let cmd = stock::StockCommand::Create;
-                ctx.seller_stock_storage
-                    .execute_command(ctx.default_seller_stock_id, cmd)
-                    .unwrap();
+                ctx.dispatch(cmd).unwrap();
             }
             Commands::Canister(c) => c.exec(ctx),
             Commands::Seller(c) => c.exec(ctx),
@@ -0,0 +1,51 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use cqrs_es::store::{EventStorage, ReplayAggregateError, Version, VersionedAggregate};
+use cqrs_es::{Aggregate, Id};
+
+/// 過去の時点の状態を表示するための、 `--as-of` で指定する時点
+#[derive(Debug)]
+pub enum AsOf {
+    Version(Version),
+    Time(DateTime<Utc>),
+}
+
+impl FromStr for AsOf {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(version) = s.parse() {
+            return Ok(AsOf::Version(Version(version)));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(AsOf::Time(time.with_timezone(&Utc)));
+        }
+        // 日付のみの場合は、その日の終わりの時点とする
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(23, 59, 59))
+            .and_then(|time| Local.from_local_datetime(&time).earliest())
+            .map(|time| AsOf::Time(time.with_timezone(&Utc)))
+            .ok_or(
+                "バージョン、RFC3339形式の日時、YYYY-MM-DD形式の日付のいずれかを指定してください",
+            )
+    }
+}
+
+/// `as_of` が指定されていればその時点の、指定されていなければ最新のAggregateを返す
+pub fn replay<A, S>(
+    storage: &S,
+    id: Id<A>,
+    as_of: Option<AsOf>,
+) -> Result<VersionedAggregate<A>, ReplayAggregateError<S::Error>>
+where
+    A: Aggregate,
+    S: EventStorage<A>,
+{
+    match as_of {
+        None => storage.replay_aggregate(id),
+        Some(AsOf::Version(version)) => storage.replay_aggregate_until(id, version),
+        Some(AsOf::Time(time)) => storage.replay_aggregate_as_of(id, time),
+    }
+}
@@ -1,17 +1,25 @@
-use cqrs_es::store::EventStorage;
 use nisshiees_coffee_core::canister_list;
 use std::str::FromStr;
 use structopt::StructOpt;
-use uuid::Uuid;
 
+use crate::commands::as_of;
+use crate::commands::as_of::AsOf;
 use crate::context::Context;
 
 #[derive(Debug, StructOpt)]
 pub enum CanisterCommands {
     #[structopt(about = "新しいキャニスターを登録します")]
     Add { color: Color, name: Name },
+    #[structopt(about = "キャニスターを空にします")]
+    Empty { name: Name },
     #[structopt(about = "キャニスターの一覧を表示します")]
-    List,
+    List {
+        #[structopt(
+            long = "--as-of",
+            help = "指定したバージョンまたは日時の時点の一覧を表示します"
+        )]
+        as_of: Option<AsOf>,
+    },
 }
 
 #[derive(Debug)]
@@ -86,19 +94,24 @@ impl CanisterCommands {
             CanisterCommands::Add { color, name } => {
                 let cmd =
                     canister_list::CanisterListCommand::AddCanister(canister_list::Canister {
-                        id: canister_list::CanisterId(Uuid::new_v4()),
+                        id: canister_list::CanisterId::new(),
                         color: color.into(),
                         name: name.into(),
+                        content: None,
                     });
-                ctx.canister_list_storage
-                    .execute_command(ctx.default_canister_list_id, cmd)
-                    .unwrap();
+                ctx.dispatch(cmd).unwrap();
             }
-            CanisterCommands::List => {
-                let agg = ctx
-                    .canister_list_storage
-                    .replay_aggregate(ctx.default_canister_list_id)
-                    .unwrap();
+            CanisterCommands::Empty { name } => {
+                let cmd = canister_list::CanisterListCommand::Empty(name.into());
+                ctx.dispatch(cmd).unwrap();
+            }
+            CanisterCommands::List { as_of } => {
+                let agg = as_of::replay(
+                    &ctx.canister_list_storage,
+                    ctx.default_canister_list_id,
+                    as_of,
+                )
+                .unwrap();
                 let agg = agg.aggregate;
                 if let canister_list::CanisterListAggregate::Created { canisters } = agg {
                     canisters.into_iter().for_each(|c| println!("{:?}", c))
@@ -1,8 +1,9 @@
-use cqrs_es::store::EventStorage;
 use nisshiees_coffee_core::seller::stock;
 use nisshiees_coffee_core::{Brand, Roast};
 use structopt::StructOpt;
 
+use crate::commands::as_of;
+use crate::commands::as_of::AsOf;
 use crate::context::Context;
 
 #[derive(Debug, StructOpt)]
@@ -24,7 +25,13 @@ pub enum StockCommands {
         all: bool,
     },
     #[structopt(about = "在庫状況を表示します")]
-    Show,
+    Show {
+        #[structopt(
+            long = "--as-of",
+            help = "指定したバージョンまたは日時の時点の在庫状況を表示します"
+        )]
+        as_of: Option<AsOf>,
+    },
 }
 
 impl StockCommands {
@@ -35,9 +42,7 @@ impl StockCommands {
                     brand: Brand(brand),
                     roast: Roast(roast),
                 };
-                ctx.seller_stock_storage
-                    .execute_command(ctx.default_seller_stock_id, cmd)
-                    .unwrap();
+                ctx.dispatch(cmd).unwrap();
             }
             StockCommands::Use { brand, roast, all } => {
                 let cmd = stock::StockCommand::Use {
@@ -45,15 +50,16 @@ impl StockCommands {
                     roast: Roast(roast),
                     all,
                 };
-                ctx.seller_stock_storage
-                    .execute_command(ctx.default_seller_stock_id, cmd)
-                    .unwrap();
+                ctx.dispatch(cmd).unwrap();
+                ctx.run_sagas().unwrap();
             }
-            StockCommands::Show => {
-                let agg = ctx
-                    .seller_stock_storage
-                    .replay_aggregate(ctx.default_seller_stock_id)
-                    .unwrap();
+            StockCommands::Show { as_of } => {
+                let agg = as_of::replay(
+                    &ctx.seller_stock_storage,
+                    ctx.default_seller_stock_id,
+                    as_of,
+                )
+                .unwrap();
                 let agg = agg.aggregate;
                 if let stock::StockAggregate::Created { packs } = agg {
                     packs.iter().for_each(|p| println!("{:?}", p));
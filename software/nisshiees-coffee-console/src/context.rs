@@ -1,7 +1,19 @@
-use cqrs_es::Id;
-use eventstorage_file::FileEventStorage;
+use std::env;
+use std::time::Duration;
+
+use cqrs_es::bus::middleware::Retry;
+use cqrs_es::bus::{CommandBus, DispatchError};
+use cqrs_es::saga::{SagaError, SagaRunner};
+use cqrs_es::store::retry::Backoff;
+use cqrs_es::store::snapshot::SnapshotPolicy;
+use cqrs_es::store::{Metadata, RetryPolicy};
+use cqrs_es::{Aggregate, Event, Id};
+use eventstorage_file::{FileEventLog, FileEventStorage, FileSagaStore, FileSnapshotStore};
+use nisshiees_coffee_core::canister_filling::CanisterFillingSaga;
 use nisshiees_coffee_core::canister_list::{CanisterListAggregate, CanisterListEvent};
 use nisshiees_coffee_core::seller::stock::{StockAggregate, StockEvent};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use uuid::Uuid;
 
 pub struct Context {
@@ -9,25 +21,97 @@ pub struct Context {
     pub default_canister_list_id: Id<CanisterListAggregate>,
     pub seller_stock_storage: FileEventStorage<StockAggregate, StockEvent>,
     pub default_seller_stock_id: Id<StockAggregate>,
+    bus: CommandBus,
+    canister_filling:
+        SagaRunner<CanisterFillingSaga, FileEventLog, FileSagaStore<CanisterFillingSaga>>,
+    correlation_id: Uuid,
+    idempotency_key: Option<String>,
 }
 
 const EVENT_STORAGE_ROOT_PATH: &str = "target/storage/events";
+const SNAPSHOT_STORAGE_ROOT_PATH: &str = "target/storage/snapshots";
+const SNAPSHOT_POLICY: SnapshotPolicy = SnapshotPolicy::Every(100);
+const RETRY: Retry = Retry::new(RetryPolicy::new(
+    3,
+    Backoff::Fixed(Duration::from_millis(100)),
+));
 
 impl Context {
     pub fn new() -> Context {
-        let canister_list_storage = FileEventStorage::new(EVENT_STORAGE_ROOT_PATH).unwrap();
-        let seller_stock_storage = FileEventStorage::new(EVENT_STORAGE_ROOT_PATH).unwrap();
-        let default_canister_list_id =
-            Uuid::parse_str("008044ba-7674-4ff3-a0ae-ef724ddd66a6").unwrap();
-        let default_canister_list_id = From::from(default_canister_list_id);
-        let default_seller_stock_id =
-            Uuid::parse_str("7b068432-c5a8-4e7e-ba79-758b902a07ba").unwrap();
-        let default_seller_stock_id = From::from(default_seller_stock_id);
+        let canister_list_storage = storage();
+        let seller_stock_storage = storage();
+        let default_canister_list_id = "008044ba-7674-4ff3-a0ae-ef724ddd66a6"
+            .parse::<Id<_>>()
+            .unwrap();
+        let default_seller_stock_id = "7b068432-c5a8-4e7e-ba79-758b902a07ba"
+            .parse::<Id<_>>()
+            .unwrap();
+
+        // 参照用とは別のインスタンスをCommandBusに渡し、コマンドの実行に使う
+        let mut bus = CommandBus::new();
+        bus.register::<CanisterListAggregate, _, _>(storage(), move |_| default_canister_list_id);
+        bus.register::<StockAggregate, _, _>(storage(), move |_| default_seller_stock_id);
+        bus.add_middleware(RETRY);
+
+        // 導入前に使った豆は、キャニスターに入れずにおく
+        let mut canister_filling = SagaRunner::new(
+            FileEventLog::new(EVENT_STORAGE_ROOT_PATH).unwrap(),
+            FileSagaStore::new(EVENT_STORAGE_ROOT_PATH).unwrap(),
+        );
+        canister_filling.start_from_latest().unwrap();
+
         Context {
             canister_list_storage,
             default_canister_list_id,
             seller_stock_storage,
             default_seller_stock_id,
+            bus,
+            canister_filling,
+            correlation_id: Uuid::new_v4(),
+            idempotency_key: None,
         }
     }
+
+    pub fn with_idempotency_key(mut self, idempotency_key: Option<String>) -> Context {
+        self.idempotency_key = idempotency_key;
+        self
+    }
+
+    /// 共通のMetadataを付与して、コマンドを対応するAggregateで実行する
+    pub fn dispatch<C>(&mut self, command: C) -> Result<(), DispatchError>
+    where
+        C: Clone + 'static,
+    {
+        let metadata = self.metadata();
+        self.bus.dispatch_with_metadata(command, metadata)
+    }
+
+    /// 記録されたEventに反応して、他のAggregateにコマンドを発行する
+    pub fn run_sagas(&mut self) -> Result<(), SagaError> {
+        self.canister_filling.run(&mut self.bus)?;
+        Ok(())
+    }
+
+    /// 1回のコマンド実行で記録されるEventに共通で付与する情報
+    pub fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new().with_correlation_id(self.correlation_id);
+        metadata.idempotency_key = self.idempotency_key.clone();
+        match env::var("USER").or_else(|_| env::var("USERNAME")) {
+            Ok(actor) => metadata.with_actor(actor),
+            Err(_) => metadata,
+        }
+    }
+}
+
+fn storage<A, E>() -> FileEventStorage<A, E>
+where
+    A: Aggregate<Event = E> + Serialize + DeserializeOwned + 'static,
+    E: Event<A> + Serialize + DeserializeOwned,
+{
+    FileEventStorage::new(EVENT_STORAGE_ROOT_PATH)
+        .unwrap()
+        .with_snapshot_store(
+            FileSnapshotStore::new(SNAPSHOT_STORAGE_ROOT_PATH).unwrap(),
+            SNAPSHOT_POLICY,
+        )
 }
@@ -11,12 +11,17 @@ mod context;
 #[derive(Debug, StructOpt)]
 #[structopt(name = "nisshiees-coffee", about = "nisshiee's coffee運用ツールです")]
 struct Opt {
+    #[structopt(
+        long = "--idempotency-key",
+        help = "同じキーで実行済みのコマンドは、再度実行しても記録しません"
+    )]
+    idempotency_key: Option<String>,
     #[structopt(subcommand)]
     sub: Commands,
 }
 
 fn main() {
     let opt = Opt::from_args();
-    let mut ctx = Context::new();
+    let mut ctx = Context::new().with_idempotency_key(opt.idempotency_key);
     opt.sub.exec(&mut ctx);
 }
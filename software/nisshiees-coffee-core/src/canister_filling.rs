@@ -0,0 +1,67 @@
+use cqrs_es::bus::DispatchError;
+use cqrs_es::saga::{Saga, Step};
+use cqrs_es::store::VersionedEvent;
+use cqrs_es::Id;
+use serde::{Deserialize, Serialize};
+
+use crate::canister_list::{CanisterListAggregate, CanisterListCommand, Content};
+use crate::seller::stock::{StockAggregate, StockCommand, StockEvent};
+
+#[cfg(test)]
+mod tests;
+
+/// 在庫から使った豆をキャニスターに入れる
+///
+/// 空いているキャニスターがない場合は、豆を在庫に戻し、使う前の残量に戻す
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CanisterFillingSaga {
+    /// キャニスターに入れられず、在庫に戻した豆
+    pub returned: Vec<Content>,
+}
+
+impl Saga for CanisterFillingSaga {
+    type Source = StockAggregate;
+    type Target = CanisterListAggregate;
+
+    fn type_name() -> &'static str {
+        "canister_filling"
+    }
+
+    fn handle(
+        &mut self,
+        _id: Id<StockAggregate>,
+        event: &VersionedEvent<StockAggregate>,
+    ) -> Vec<Step<Self>> {
+        match &event.event {
+            StockEvent::Decreased {
+                brand,
+                roast,
+                before,
+            }
+            | StockEvent::Removed {
+                brand,
+                roast,
+                before,
+            } => {
+                vec![Step {
+                    command: CanisterListCommand::Fill(Content {
+                        brand: brand.clone(),
+                        roast: *roast,
+                    }),
+                    compensation: Some(StockCommand::Return {
+                        brand: brand.clone(),
+                        roast: *roast,
+                        remaining_amount: *before,
+                    }),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn failed(&mut self, step: &Step<Self>, _error: &DispatchError) {
+        if let CanisterListCommand::Fill(content) = &step.command {
+            self.returned.push(content.clone());
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use cqrs_es::store::{Metadata, Version};
+
+use super::*;
+use crate::seller::stock::RemainingAmount;
+use crate::{Brand, Roast};
+
+fn event(event: StockEvent) -> VersionedEvent<StockAggregate> {
+    VersionedEvent {
+        version: Version(1),
+        event,
+        metadata: Metadata::new(),
+    }
+}
+
+fn mandheling() -> Content {
+    Content {
+        brand: Brand("Mandheling".to_owned()),
+        roast: Roast(8),
+    }
+}
+
+#[test]
+fn fill_used_beans() {
+    let Content { brand, roast } = mandheling();
+    let mut saga = CanisterFillingSaga::default();
+
+    let got = saga.handle(
+        Id::new(),
+        &event(StockEvent::Removed {
+            brand,
+            roast,
+            before: RemainingAmount::LtFillingCanister,
+        }),
+    );
+
+    assert_eq!(got.len(), 1);
+    if let CanisterListCommand::Fill(content) = &got[0].command {
+        assert_eq!(*content, mandheling());
+    } else {
+        panic!();
+    }
+    if let Some(StockCommand::Return {
+        brand,
+        roast,
+        remaining_amount,
+    }) = &got[0].compensation
+    {
+        assert_eq!(*brand, mandheling().brand);
+        assert_eq!(*roast, mandheling().roast);
+        assert_eq!(*remaining_amount, RemainingAmount::LtFillingCanister);
+    } else {
+        panic!();
+    }
+}
+
+#[test]
+fn ignore_other_events() {
+    let Content { brand, roast } = mandheling();
+    let mut saga = CanisterFillingSaga::default();
+
+    let got = saga.handle(Id::new(), &event(StockEvent::Purchased { brand, roast }));
+
+    assert!(got.is_empty());
+}
+
+#[test]
+fn record_returned_beans() {
+    let Content { brand, roast } = mandheling();
+    let mut saga = CanisterFillingSaga::default();
+    let steps = saga.handle(
+        Id::new(),
+        &event(StockEvent::Decreased {
+            brand,
+            roast,
+            before: RemainingAmount::GteFillingCanister,
+        }),
+    );
+
+    saga.failed(
+        &steps[0],
+        &DispatchError::Rejected {
+            reason: "test".to_owned(),
+        },
+    );
+
+    assert_eq!(saga.returned, vec![mandheling()]);
+}
@@ -1,22 +1,42 @@
 use cqrs_es::*;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::{Brand, Roast};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Aggregate)]
+#[aggregate(
+    type_name = "canister_list",
+    event = "CanisterListEvent",
+    command = "CanisterListCommand"
+)]
 pub enum CanisterListAggregate {
+    #[aggregate(default)]
     Uninitialized,
-    Created { canisters: Vec<Canister> },
+    Created {
+        canisters: Vec<Canister>,
+    },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Canister {
     pub id: CanisterId,
     pub color: Color,
     pub name: Name,
+    /// 入っている豆。空の場合は `None`
+    #[serde(default)]
+    pub content: Option<Content>,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
-pub struct CanisterId(pub Uuid);
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Content {
+    pub brand: Brand,
+    pub roast: Roast,
+}
+
+pub type CanisterId = Id<Canister>;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Color {
@@ -34,51 +54,30 @@ pub enum Name {
     Makabe,
 }
 
-impl Aggregate for CanisterListAggregate {
-    type Event = CanisterListEvent;
-    type Command = CanisterListCommand;
-
-    fn type_name() -> &'static str {
-        "canister_list"
-    }
-}
-
-impl Default for CanisterListAggregate {
-    fn default() -> Self {
-        CanisterListAggregate::Uninitialized
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Event)]
+#[event(aggregate = "CanisterListAggregate")]
 pub enum CanisterListEvent {
     Created,
     CanisterAdded(Canister),
+    Filled { id: CanisterId, content: Content },
+    Emptied(CanisterId),
 }
 
-impl Event<CanisterListAggregate> for CanisterListEvent {
-    fn apply_to(self, aggregate: &mut CanisterListAggregate) {
-        match self {
-            CanisterListEvent::Created => {
-                *aggregate = CanisterListAggregate::Created {
-                    canisters: Vec::new(),
-                }
-            }
-            CanisterListEvent::CanisterAdded(canister) => {
-                if let CanisterListAggregate::Created { canisters } = aggregate {
-                    canisters.push(canister)
-                }
-            }
-        }
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Command)]
+#[command(
+    aggregate = "CanisterListAggregate",
+    events = "Option<CanisterListEvent>",
+    error = "CanisterListCommandError"
+)]
 pub enum CanisterListCommand {
     Create,
     AddCanister(Canister),
+    /// 空いているキャニスターのうち、最初に登録されたものに豆を入れる
+    Fill(Content),
+    Empty(Name),
 }
 
-#[derive(Fail, Debug)]
+#[derive(Fail, Debug, Eq, PartialEq)]
 pub enum CanisterListCommandError {
     #[fail(display = "ID duplicated")]
     IdDuplicated,
@@ -90,36 +89,106 @@ pub enum CanisterListCommandError {
     AlreadyCreated,
     #[fail(display = "CanisterList uninitialized")]
     Uninitialized,
+    #[fail(display = "No empty canister")]
+    NoEmptyCanister,
+    #[fail(display = "Canister {:?} not found", _0)]
+    NotFound(Name),
 }
 
 impl CommandError for CanisterListCommandError {}
 
-impl Command<CanisterListAggregate> for CanisterListCommand {
-    type Events = Option<CanisterListEvent>;
-    type Error = CanisterListCommandError;
+impl CanisterListAggregate {
+    fn canister_mut(&mut self, id: CanisterId) -> Option<&mut Canister> {
+        match self {
+            CanisterListAggregate::Created { canisters } => {
+                canisters.iter_mut().find(|c| c.id == id)
+            }
+            CanisterListAggregate::Uninitialized => None,
+        }
+    }
+
+    fn apply_created(&mut self) {
+        *self = CanisterListAggregate::Created {
+            canisters: Vec::new(),
+        }
+    }
+
+    fn apply_canister_added(&mut self, canister: Canister) {
+        if let CanisterListAggregate::Created { canisters } = self {
+            canisters.push(canister)
+        }
+    }
+
+    fn apply_filled(&mut self, id: CanisterId, content: Content) {
+        if let Some(canister) = self.canister_mut(id) {
+            canister.content = Some(content)
+        }
+    }
+
+    fn apply_emptied(&mut self, id: CanisterId) {
+        if let Some(canister) = self.canister_mut(id) {
+            canister.content = None
+        }
+    }
+
+    fn handle_create(&self) -> Result<Option<CanisterListEvent>, CanisterListCommandError> {
+        match self {
+            CanisterListAggregate::Uninitialized => Ok(Some(CanisterListEvent::Created)),
+            _ => Err(CanisterListCommandError::AlreadyCreated),
+        }
+    }
+
+    fn handle_add_canister(
+        &self,
+        adding: Canister,
+    ) -> Result<Option<CanisterListEvent>, CanisterListCommandError> {
+        match self {
+            CanisterListAggregate::Created { canisters } => {
+                if canisters.iter().any(|c| c.id == adding.id) {
+                    return Err(CanisterListCommandError::IdDuplicated);
+                }
+                if canisters.iter().any(|c| c.color == adding.color) {
+                    return Err(CanisterListCommandError::ColorDuplicated);
+                }
+                if canisters.iter().any(|c| c.name == adding.name) {
+                    return Err(CanisterListCommandError::NameDuplicated);
+                }
+                Ok(Some(CanisterListEvent::CanisterAdded(adding)))
+            }
+            _ => Err(CanisterListCommandError::Uninitialized),
+        }
+    }
 
-    fn execute_on(self, aggregate: &CanisterListAggregate) -> Result<Self::Events, Self::Error> {
+    fn handle_fill(
+        &self,
+        content: Content,
+    ) -> Result<Option<CanisterListEvent>, CanisterListCommandError> {
         match self {
-            CanisterListCommand::Create => match aggregate {
-                CanisterListAggregate::Uninitialized => Ok(Some(CanisterListEvent::Created)),
-                _ => Err(CanisterListCommandError::AlreadyCreated),
-            },
-
-            CanisterListCommand::AddCanister(adding) => match aggregate {
-                CanisterListAggregate::Created { canisters } => {
-                    if canisters.into_iter().any(|c| c.id == adding.id) {
-                        return Err(CanisterListCommandError::IdDuplicated);
-                    }
-                    if canisters.into_iter().any(|c| c.color == adding.color) {
-                        return Err(CanisterListCommandError::ColorDuplicated);
-                    }
-                    if canisters.into_iter().any(|c| c.name == adding.name) {
-                        return Err(CanisterListCommandError::NameDuplicated);
-                    }
-                    Ok(Some(CanisterListEvent::CanisterAdded(adding)))
+            CanisterListAggregate::Created { canisters } => {
+                match canisters.iter().find(|c| c.content.is_none()) {
+                    Some(canister) => Ok(Some(CanisterListEvent::Filled {
+                        id: canister.id,
+                        content,
+                    })),
+                    None => Err(CanisterListCommandError::NoEmptyCanister),
                 }
-                _ => Err(CanisterListCommandError::Uninitialized),
-            },
+            }
+            _ => Err(CanisterListCommandError::Uninitialized),
+        }
+    }
+
+    fn handle_empty(
+        &self,
+        name: Name,
+    ) -> Result<Option<CanisterListEvent>, CanisterListCommandError> {
+        match self {
+            CanisterListAggregate::Created { canisters } => {
+                match canisters.iter().find(|c| c.name == name) {
+                    Some(canister) => Ok(Some(CanisterListEvent::Emptied(canister.id))),
+                    None => Err(CanisterListCommandError::NotFound(name)),
+                }
+            }
+            _ => Err(CanisterListCommandError::Uninitialized),
         }
     }
 }
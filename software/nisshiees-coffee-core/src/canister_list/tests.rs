@@ -0,0 +1,161 @@
+use cqrs_es::testing::*;
+
+use super::*;
+
+fn canister(color: Color, name: Name) -> Canister {
+    Canister {
+        id: CanisterId::new(),
+        color,
+        name,
+        content: None,
+    }
+}
+
+#[test]
+fn create() {
+    given_no_previous_events::<CanisterListAggregate>()
+        .when(CanisterListCommand::Create)
+        .then_expect_events(vec![CanisterListEvent::Created]);
+}
+
+#[test]
+fn create_already_created() {
+    given::<CanisterListAggregate>(vec![CanisterListEvent::Created])
+        .when(CanisterListCommand::Create)
+        .then_expect_error(CanisterListCommandError::AlreadyCreated);
+}
+
+#[test]
+fn add_canister() {
+    let adding = canister(Color::Blue, Name::Matsubara);
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(canister(Color::Red, Name::Makabe)),
+    ])
+    .when(CanisterListCommand::AddCanister(adding.clone()))
+    .then_expect_events(vec![CanisterListEvent::CanisterAdded(adding)]);
+}
+
+#[test]
+fn add_canister_uninitialized() {
+    given_no_previous_events::<CanisterListAggregate>()
+        .when(CanisterListCommand::AddCanister(canister(
+            Color::Blue,
+            Name::Matsubara,
+        )))
+        .then_expect_error(CanisterListCommandError::Uninitialized);
+}
+
+#[test]
+fn add_canister_id_duplicated() {
+    let added = canister(Color::Red, Name::Makabe);
+    let adding = Canister {
+        color: Color::Blue,
+        name: Name::Matsubara,
+        ..added.clone()
+    };
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(added),
+    ])
+    .when(CanisterListCommand::AddCanister(adding))
+    .then_expect_error(CanisterListCommandError::IdDuplicated);
+}
+
+#[test]
+fn add_canister_color_duplicated() {
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(canister(Color::Red, Name::Makabe)),
+    ])
+    .when(CanisterListCommand::AddCanister(canister(
+        Color::Red,
+        Name::Matsubara,
+    )))
+    .then_expect_error(CanisterListCommandError::ColorDuplicated);
+}
+
+#[test]
+fn add_canister_name_duplicated() {
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(canister(Color::Red, Name::Makabe)),
+    ])
+    .when(CanisterListCommand::AddCanister(canister(
+        Color::Blue,
+        Name::Makabe,
+    )))
+    .then_expect_error(CanisterListCommandError::NameDuplicated);
+}
+
+fn filled(canister: &Canister, content: Content) -> CanisterListEvent {
+    CanisterListEvent::Filled {
+        id: canister.id,
+        content,
+    }
+}
+
+fn mandheling() -> Content {
+    Content {
+        brand: Brand("Mandheling".to_owned()),
+        roast: Roast(8),
+    }
+}
+
+#[test]
+fn fill() {
+    let first = canister(Color::Red, Name::Makabe);
+    let second = canister(Color::Blue, Name::Matsubara);
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(first.clone()),
+        CanisterListEvent::CanisterAdded(second.clone()),
+        filled(&first, mandheling()),
+    ])
+    .when(CanisterListCommand::Fill(mandheling()))
+    .then_expect_events(vec![filled(&second, mandheling())]);
+}
+
+#[test]
+fn fill_no_empty_canister() {
+    let added = canister(Color::Red, Name::Makabe);
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(added.clone()),
+        filled(&added, mandheling()),
+    ])
+    .when(CanisterListCommand::Fill(mandheling()))
+    .then_expect_error(CanisterListCommandError::NoEmptyCanister);
+}
+
+#[test]
+fn empty() {
+    let added = canister(Color::Red, Name::Makabe);
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(added.clone()),
+        filled(&added, mandheling()),
+    ])
+    .when(CanisterListCommand::Empty(Name::Makabe))
+    .then_expect_events(vec![CanisterListEvent::Emptied(added.id)]);
+}
+
+#[test]
+fn empty_not_found() {
+    given::<CanisterListAggregate>(vec![
+        CanisterListEvent::Created,
+        CanisterListEvent::CanisterAdded(canister(Color::Red, Name::Makabe)),
+    ])
+    .when(CanisterListCommand::Empty(Name::Matsubara))
+    .then_expect_error(CanisterListCommandError::NotFound(Name::Matsubara));
+}
+
+#[test]
+fn deserialize_canister_without_content() {
+    let json = r#"{"id":"5d4ffb41-86f6-4a07-8e51-28b1e5b0e7ad","color":"Red","name":"Makabe"}"#;
+
+    let got: Canister = serde_json::from_str(json).unwrap();
+
+    assert_eq!(got.id.to_string(), "5d4ffb41-86f6-4a07-8e51-28b1e5b0e7ad");
+    assert_eq!(got.content, None);
+}
@@ -1,10 +1,10 @@
 extern crate chrono;
 extern crate failure;
 extern crate serde;
-extern crate uuid;
 #[macro_use]
 extern crate failure_derive;
 
+pub mod canister_filling;
 pub mod canister_list;
 pub mod seller;
 
@@ -3,10 +3,21 @@ use serde::{Deserialize, Serialize};
 
 use crate::{Brand, Roast};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Aggregate)]
+#[aggregate(
+    type_name = "seller/stock",
+    event = "StockEvent",
+    command = "StockCommand"
+)]
 pub enum StockAggregate {
+    #[aggregate(default)]
     Uninitialized,
-    Created { packs: Vec<Pack> },
+    Created {
+        packs: Vec<Pack>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,72 +33,52 @@ impl Pack {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+/// 残量を記録する前のEventを読み込む場合は、 `GteFillingCanister` とする
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub enum RemainingAmount {
+    #[default]
     GteFillingCanister,
     LtFillingCanister,
 }
 
-impl Default for StockAggregate {
-    fn default() -> Self {
-        StockAggregate::Uninitialized
-    }
-}
-
-impl Aggregate for StockAggregate {
-    type Event = StockEvent;
-    type Command = StockCommand;
-
-    fn type_name() -> &'static str {
-        "seller/stock"
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Event)]
+#[event(aggregate = "StockAggregate")]
 pub enum StockEvent {
     Created,
-    Purchased { brand: Brand, roast: Roast },
-    Decreased { brand: Brand, roast: Roast },
-    Removed { brand: Brand, roast: Roast },
-}
-
-impl Event<StockAggregate> for StockEvent {
-    fn apply_to(self, aggregate: &mut StockAggregate) {
-        match self {
-            StockEvent::Created => *aggregate = StockAggregate::Created { packs: Vec::new() },
-            StockEvent::Purchased { brand, roast } => {
-                if let StockAggregate::Created { packs } = aggregate {
-                    let same_bean_pack = packs.iter_mut().find(|p| p.is_same_bean(&brand, &roast));
-                    match same_bean_pack {
-                        None => packs.push(Pack {
-                            brand,
-                            roast,
-                            remaining_amount: RemainingAmount::GteFillingCanister,
-                        }),
-                        Some(same_bean_pack) => {
-                            same_bean_pack.remaining_amount = RemainingAmount::GteFillingCanister
-                        }
-                    }
-                }
-            }
-            StockEvent::Decreased { brand, roast } => {
-                if let StockAggregate::Created { packs } = aggregate {
-                    let same_bean_pack = packs.iter_mut().find(|p| p.is_same_bean(&brand, &roast));
-                    if let Some(same_bean_pack) = same_bean_pack {
-                        same_bean_pack.remaining_amount = RemainingAmount::LtFillingCanister;
-                    }
-                }
-            }
-            StockEvent::Removed { brand, roast } => {
-                if let StockAggregate::Created { packs } = aggregate {
-                    packs.retain(|p| !p.is_same_bean(&brand, &roast))
-                }
-            }
-        }
-    }
+    Purchased {
+        brand: Brand,
+        roast: Roast,
+    },
+    Decreased {
+        brand: Brand,
+        roast: Roast,
+        /// 使う前の残量
+        #[serde(default)]
+        before: RemainingAmount,
+    },
+    Removed {
+        brand: Brand,
+        roast: Roast,
+        /// 使う前の残量
+        #[serde(default)]
+        before: RemainingAmount,
+    },
+    /// 使おうとした豆を、使わずに在庫に戻した
+    Returned {
+        brand: Brand,
+        roast: Roast,
+        /// 使う前の残量。戻した後の残量はこれ以上となる
+        #[serde(default)]
+        remaining_amount: RemainingAmount,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Command)]
+#[command(
+    aggregate = "StockAggregate",
+    events = "Option<StockEvent>",
+    error = "StockCommandError"
+)]
 pub enum StockCommand {
     Create,
     Purchase {
@@ -99,9 +90,15 @@ pub enum StockCommand {
         roast: Roast,
         all: bool,
     },
+    /// 使った豆を戻し、使う前の残量 `remaining_amount` に戻す
+    Return {
+        brand: Brand,
+        roast: Roast,
+        remaining_amount: RemainingAmount,
+    },
 }
 
-#[derive(Fail, Debug)]
+#[derive(Fail, Debug, Eq, PartialEq)]
 pub enum StockCommandError {
     #[fail(
         display = "brand: {:?}, roast: {:?} not contained in Stock",
@@ -116,34 +113,129 @@ pub enum StockCommandError {
 
 impl CommandError for StockCommandError {}
 
-impl Command<StockAggregate> for StockCommand {
-    type Events = Option<StockEvent>;
-    type Error = StockCommandError;
-
-    fn execute_on(self, aggregate: &StockAggregate) -> Result<Self::Events, Self::Error> {
+impl StockAggregate {
+    fn packs_mut(&mut self) -> Option<&mut Vec<Pack>> {
         match self {
-            StockCommand::Create => match aggregate {
-                StockAggregate::Uninitialized => Ok(Some(StockEvent::Created)),
-                _ => Err(StockCommandError::AlreadyCreated),
-            },
-            StockCommand::Purchase { brand, roast } => match aggregate {
-                StockAggregate::Created { .. } => Ok(Some(StockEvent::Purchased { brand, roast })),
-                StockAggregate::Uninitialized => Err(StockCommandError::Uninitialized),
-            },
-            StockCommand::Use { brand, roast, all } => match aggregate {
-                StockAggregate::Created { packs } => {
-                    if packs.iter().any(|p| p.is_same_bean(&brand, &roast)) {
-                        if all {
-                            Ok(Some(StockEvent::Removed { brand, roast }))
-                        } else {
-                            Ok(Some(StockEvent::Decreased { brand, roast }))
-                        }
-                    } else {
-                        Err(StockCommandError::NotInStock { brand, roast })
+            StockAggregate::Created { packs } => Some(packs),
+            StockAggregate::Uninitialized => None,
+        }
+    }
+
+    fn apply_created(&mut self) {
+        *self = StockAggregate::Created { packs: Vec::new() }
+    }
+
+    fn apply_purchased(&mut self, brand: Brand, roast: Roast) {
+        if let Some(packs) = self.packs_mut() {
+            let same_bean_pack = packs.iter_mut().find(|p| p.is_same_bean(&brand, &roast));
+            match same_bean_pack {
+                None => packs.push(Pack {
+                    brand,
+                    roast,
+                    remaining_amount: RemainingAmount::GteFillingCanister,
+                }),
+                Some(same_bean_pack) => {
+                    same_bean_pack.remaining_amount = RemainingAmount::GteFillingCanister
+                }
+            }
+        }
+    }
+
+    fn apply_decreased(&mut self, brand: Brand, roast: Roast, _before: RemainingAmount) {
+        if let Some(packs) = self.packs_mut() {
+            let same_bean_pack = packs.iter_mut().find(|p| p.is_same_bean(&brand, &roast));
+            if let Some(same_bean_pack) = same_bean_pack {
+                same_bean_pack.remaining_amount = RemainingAmount::LtFillingCanister;
+            }
+        }
+    }
+
+    fn apply_removed(&mut self, brand: Brand, roast: Roast, _before: RemainingAmount) {
+        if let Some(packs) = self.packs_mut() {
+            packs.retain(|p| !p.is_same_bean(&brand, &roast))
+        }
+    }
+
+    /// 戻すまでの間に買い足した場合は、買い足した後の残量を優先する
+    fn apply_returned(&mut self, brand: Brand, roast: Roast, remaining_amount: RemainingAmount) {
+        if let Some(packs) = self.packs_mut() {
+            let same_bean_pack = packs.iter_mut().find(|p| p.is_same_bean(&brand, &roast));
+            match same_bean_pack {
+                None => packs.push(Pack {
+                    brand,
+                    roast,
+                    remaining_amount,
+                }),
+                Some(same_bean_pack) => {
+                    if remaining_amount == RemainingAmount::GteFillingCanister {
+                        same_bean_pack.remaining_amount = remaining_amount
                     }
                 }
-                StockAggregate::Uninitialized => Err(StockCommandError::Uninitialized),
-            },
+            }
+        }
+    }
+
+    fn handle_create(&self) -> Result<Option<StockEvent>, StockCommandError> {
+        match self {
+            StockAggregate::Uninitialized => Ok(Some(StockEvent::Created)),
+            _ => Err(StockCommandError::AlreadyCreated),
+        }
+    }
+
+    fn handle_purchase(
+        &self,
+        brand: Brand,
+        roast: Roast,
+    ) -> Result<Option<StockEvent>, StockCommandError> {
+        match self {
+            StockAggregate::Created { .. } => Ok(Some(StockEvent::Purchased { brand, roast })),
+            StockAggregate::Uninitialized => Err(StockCommandError::Uninitialized),
+        }
+    }
+
+    fn handle_use(
+        &self,
+        brand: Brand,
+        roast: Roast,
+        all: bool,
+    ) -> Result<Option<StockEvent>, StockCommandError> {
+        match self {
+            StockAggregate::Created { packs } => {
+                let before = match packs.iter().find(|p| p.is_same_bean(&brand, &roast)) {
+                    Some(pack) => pack.remaining_amount,
+                    None => return Err(StockCommandError::NotInStock { brand, roast }),
+                };
+                if all {
+                    Ok(Some(StockEvent::Removed {
+                        brand,
+                        roast,
+                        before,
+                    }))
+                } else {
+                    Ok(Some(StockEvent::Decreased {
+                        brand,
+                        roast,
+                        before,
+                    }))
+                }
+            }
+            StockAggregate::Uninitialized => Err(StockCommandError::Uninitialized),
+        }
+    }
+
+    fn handle_return(
+        &self,
+        brand: Brand,
+        roast: Roast,
+        remaining_amount: RemainingAmount,
+    ) -> Result<Option<StockEvent>, StockCommandError> {
+        match self {
+            StockAggregate::Created { .. } => Ok(Some(StockEvent::Returned {
+                brand,
+                roast,
+                remaining_amount,
+            })),
+            StockAggregate::Uninitialized => Err(StockCommandError::Uninitialized),
         }
     }
 }
@@ -0,0 +1,282 @@
+use cqrs_es::store::{EventStorage, ExecuteCommandError, InMemoryEventStorage, Version};
+use cqrs_es::testing::*;
+use cqrs_es::Id;
+
+use super::*;
+
+fn mandheling() -> (Brand, Roast) {
+    (Brand("Mandheling".to_owned()), Roast(8))
+}
+
+#[test]
+fn purchase_and_use() {
+    let mut storage = InMemoryEventStorage::<StockAggregate>::new();
+    let id = Id::new();
+    let (brand, roast) = mandheling();
+
+    storage.execute_command(id, StockCommand::Create).unwrap();
+    storage
+        .execute_command(
+            id,
+            StockCommand::Purchase {
+                brand: brand.clone(),
+                roast,
+            },
+        )
+        .unwrap();
+    storage
+        .execute_command(
+            id,
+            StockCommand::Use {
+                brand: brand.clone(),
+                roast,
+                all: false,
+            },
+        )
+        .unwrap();
+
+    let got = storage.replay_aggregate(id).unwrap();
+    assert_eq!(got.version, Version(3));
+    if let StockAggregate::Created { packs } = got.aggregate {
+        assert_eq!(packs.len(), 1);
+        assert!(packs[0].is_same_bean(&brand, &roast));
+        assert_eq!(
+            packs[0].remaining_amount,
+            RemainingAmount::LtFillingCanister
+        );
+    } else {
+        panic!();
+    }
+
+    storage
+        .execute_command(
+            id,
+            StockCommand::Use {
+                brand,
+                roast,
+                all: true,
+            },
+        )
+        .unwrap();
+
+    if let StockAggregate::Created { packs } = storage.replay_aggregate(id).unwrap().aggregate {
+        assert!(packs.is_empty());
+    } else {
+        panic!();
+    }
+}
+
+#[test]
+fn use_not_in_stock() {
+    let mut storage = InMemoryEventStorage::<StockAggregate>::new();
+    let id = Id::new();
+    let (brand, roast) = mandheling();
+    storage.execute_command(id, StockCommand::Create).unwrap();
+
+    let got = storage.execute_command(
+        id,
+        StockCommand::Use {
+            brand,
+            roast,
+            all: false,
+        },
+    );
+
+    if let Err(ExecuteCommandError::Command(StockCommandError::NotInStock { .. })) = got {
+    } else {
+        panic!();
+    }
+    assert_eq!(storage.read(id).unwrap().len(), 1);
+}
+
+#[test]
+fn create_already_created() {
+    given::<StockAggregate>(vec![StockEvent::Created])
+        .when(StockCommand::Create)
+        .then_expect_error(StockCommandError::AlreadyCreated);
+}
+
+#[test]
+fn purchase_uninitialized() {
+    let (brand, roast) = mandheling();
+    given_no_previous_events::<StockAggregate>()
+        .when(StockCommand::Purchase { brand, roast })
+        .then_expect_error(StockCommandError::Uninitialized);
+}
+
+#[test]
+fn use_partially() {
+    let (brand, roast) = mandheling();
+    given::<StockAggregate>(vec![
+        StockEvent::Created,
+        StockEvent::Purchased {
+            brand: brand.clone(),
+            roast,
+        },
+    ])
+    .when(StockCommand::Use {
+        brand: brand.clone(),
+        roast,
+        all: false,
+    })
+    .then_expect_events(vec![StockEvent::Decreased {
+        brand,
+        roast,
+        before: RemainingAmount::GteFillingCanister,
+    }]);
+}
+
+#[test]
+fn use_all() {
+    let (brand, roast) = mandheling();
+    given::<StockAggregate>(vec![
+        StockEvent::Created,
+        StockEvent::Purchased {
+            brand: brand.clone(),
+            roast,
+        },
+    ])
+    .when(StockCommand::Use {
+        brand: brand.clone(),
+        roast,
+        all: true,
+    })
+    .then_expect_events(vec![StockEvent::Removed {
+        brand,
+        roast,
+        before: RemainingAmount::GteFillingCanister,
+    }]);
+}
+
+#[test]
+fn use_removed() {
+    let (brand, roast) = mandheling();
+    given::<StockAggregate>(vec![
+        StockEvent::Created,
+        StockEvent::Purchased {
+            brand: brand.clone(),
+            roast,
+        },
+        StockEvent::Removed {
+            brand: brand.clone(),
+            roast,
+            before: RemainingAmount::GteFillingCanister,
+        },
+    ])
+    .when(StockCommand::Use {
+        brand: brand.clone(),
+        roast,
+        all: false,
+    })
+    .then_expect_error(StockCommandError::NotInStock { brand, roast });
+}
+
+#[test]
+fn return_removed() {
+    let (brand, roast) = mandheling();
+    let mut aggregate = StockAggregate::Created { packs: Vec::new() };
+
+    StockEvent::Returned {
+        brand: brand.clone(),
+        roast,
+        remaining_amount: RemainingAmount::GteFillingCanister,
+    }
+    .apply_to(&mut aggregate);
+
+    if let StockAggregate::Created { packs } = aggregate {
+        assert_eq!(packs.len(), 1);
+        assert!(packs[0].is_same_bean(&brand, &roast));
+        assert_eq!(
+            packs[0].remaining_amount,
+            RemainingAmount::GteFillingCanister
+        );
+    } else {
+        panic!();
+    }
+}
+
+#[test]
+fn return_uninitialized() {
+    let (brand, roast) = mandheling();
+    given_no_previous_events::<StockAggregate>()
+        .when(StockCommand::Return {
+            brand,
+            roast,
+            remaining_amount: RemainingAmount::GteFillingCanister,
+        })
+        .then_expect_error(StockCommandError::Uninitialized);
+}
+
+/// `uses` 回使い、最後に使った分を在庫に戻した後の残量
+fn use_and_return(uses: usize) -> RemainingAmount {
+    let mut storage = InMemoryEventStorage::<StockAggregate>::new();
+    let id = Id::new();
+    let (brand, roast) = mandheling();
+    storage.execute_command(id, StockCommand::Create).unwrap();
+    storage
+        .execute_command(
+            id,
+            StockCommand::Purchase {
+                brand: brand.clone(),
+                roast,
+            },
+        )
+        .unwrap();
+    for _ in 0..uses {
+        storage
+            .execute_command(
+                id,
+                StockCommand::Use {
+                    brand: brand.clone(),
+                    roast,
+                    all: false,
+                },
+            )
+            .unwrap();
+    }
+    let remaining_amount = match storage.read(id).unwrap().pop().unwrap().event {
+        StockEvent::Decreased { before, .. } => before,
+        _ => panic!(),
+    };
+
+    // キャニスターに入れられず、在庫に戻した
+    storage
+        .execute_command(
+            id,
+            StockCommand::Return {
+                brand,
+                roast,
+                remaining_amount,
+            },
+        )
+        .unwrap();
+
+    match storage.replay_aggregate(id).unwrap().aggregate {
+        StockAggregate::Created { packs } => packs[0].remaining_amount,
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn return_restores_remaining_amount() {
+    assert_eq!(use_and_return(1), RemainingAmount::GteFillingCanister);
+    // 既に一部を使っていた場合は、使う前の残り少ない状態に戻す
+    assert_eq!(use_and_return(2), RemainingAmount::LtFillingCanister);
+}
+
+#[test]
+fn deserialize_events_without_remaining_amount() {
+    let json = r#"{"Decreased":{"brand":"Mandheling","roast":8}}"#;
+
+    let got: StockEvent = serde_json::from_str(json).unwrap();
+
+    let (brand, roast) = mandheling();
+    assert_eq!(
+        got,
+        StockEvent::Decreased {
+            brand,
+            roast,
+            before: RemainingAmount::GteFillingCanister,
+        }
+    );
+}